@@ -1,6 +1,8 @@
 use crate::ray::Ray;
 use crate::vec3::Vector3;
 
+// Named before clippy's acronym lint, and used by every shape
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone)]
 pub struct AABB {
     min: Vector3,
@@ -84,6 +86,44 @@ impl AABB {
         true
     }
 
+    // The part of [t_min, t_max] where the ray is inside the box
+    pub fn intersect(&self, r: &Ray, mut t_min: f32, mut t_max: f32) -> Option<(f32, f32)> {
+        for axis in 0..3 {
+            let inv_d = 1.0 / r.direction().component(axis);
+            let mut t0 = (self.min.component(axis) - r.origin().component(axis)) * inv_d;
+            let mut t1 = (self.max.component(axis) - r.origin().component(axis)) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_max < t_min {
+                return None;
+            }
+        }
+        Some((t_min, t_max))
+    }
+
+    // The part of the box inside the other one, if any
+    pub fn overlap(&self, other: &AABB) -> Option<AABB> {
+        let min = Vector3::new(
+            self.min.x().max(other.min.x()),
+            self.min.y().max(other.min.y()),
+            self.min.z().max(other.min.z()),
+        );
+        let max = Vector3::new(
+            self.max.x().min(other.max.x()),
+            self.max.y().min(other.max.y()),
+            self.max.z().min(other.max.z()),
+        );
+        if min.x() <= max.x() && min.y() <= max.y() && min.z() <= max.z() {
+            Some(AABB::new(min, max))
+        } else {
+            None
+        }
+    }
+
     pub fn surrounding_box(&self, other: &AABB) -> AABB {
         AABB::new(
             Vector3::new(
@@ -0,0 +1,240 @@
+use crate::aabb::AABB;
+use crate::camera::CameraModel;
+use crate::hitable::HitRecord;
+use crate::ray::Ray;
+use crate::vec3::Vector3;
+use std::f32;
+
+// Arbitrary output variables: images rendered alongside the beauty pass,
+// for compositing and denoising. Apart from the direct and indirect light,
+// they describe the first surface each camera ray hits.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Aov {
+    // Distance from the camera, infinite where rays escape
+    Depth,
+    Normal,
+    Albedo,
+    Position,
+    // Which of the scene's objects was hit, from 1, or 0 for none
+    ObjectId,
+    // Which material was hit, or 0 for none. See Material::id.
+    MaterialId,
+    // Light reaching the camera after at most one bounce, and the rest.
+    // Together they make up the beauty pass.
+    Direct,
+    Indirect,
+}
+
+impl Aov {
+    pub fn parse(name: &str) -> Option<Aov> {
+        match name {
+            "depth" => Some(Aov::Depth),
+            "normal" => Some(Aov::Normal),
+            "albedo" => Some(Aov::Albedo),
+            "position" => Some(Aov::Position),
+            "id" => Some(Aov::ObjectId),
+            "material_id" => Some(Aov::MaterialId),
+            "direct" => Some(Aov::Direct),
+            "indirect" => Some(Aov::Indirect),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Position => "position",
+            Aov::ObjectId => "id",
+            Aov::MaterialId => "material_id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+        }
+    }
+
+    // Channel names in an EXR layer. Single channel passes only use x.
+    pub fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Albedo | Aov::Direct | Aov::Indirect => &["R", "G", "B"],
+        }
+    }
+
+    // Whether each pixel keeps its sample nearest the centre instead of a
+    // filtered average: IDs can't be averaged, and depth is infinite where
+    // rays escape
+    pub fn keeps_nearest(&self) -> bool {
+        matches!(self, Aov::Depth | Aov::ObjectId | Aov::MaterialId)
+    }
+
+    // Whether it holds colours, which are gamma encoded in 8-bit images
+    pub fn is_color(&self) -> bool {
+        matches!(self, Aov::Albedo | Aov::Direct | Aov::Indirect)
+    }
+
+    // The value for camera ray r, which first hit `hit`. Not for direct and
+    // indirect light, which come from tracing the whole path.
+    pub fn first_hit(&self, cam: &dyn CameraModel, r: &Ray, hit: Option<&HitRecord>) -> Vector3 {
+        let hr = match hit {
+            Some(hr) => hr,
+            None if *self == Aov::Depth => return Vector3::new(f32::INFINITY, 0.0, 0.0),
+            None => return Vector3::zero(),
+        };
+        match self {
+            Aov::Depth => Vector3::new(cam.depth(r, &hr.p), 0.0, 0.0),
+            Aov::Normal => hr.normal,
+            Aov::Albedo => hr.material.albedo(),
+            Aov::Position => hr.p,
+            Aov::ObjectId => Vector3::new(hr.object_id as f32, 0.0, 0.0),
+            Aov::MaterialId => Vector3::new(hr.material.id() as f32, 0.0, 0.0),
+            Aov::Direct | Aov::Indirect => Vector3::zero(),
+        }
+    }
+
+    // Maps a finished pass into [0, 1] for 8-bit images: depth from near
+    // to far, positions over their range, and each object ID to its own
+    // colour
+    pub fn for_display(&self, pixels: &[Vector3]) -> Vec<Vector3> {
+        match self {
+            Aov::Depth => {
+                let max = pixels
+                    .iter()
+                    .map(|p| p.x())
+                    .filter(|d| d.is_finite())
+                    .fold(0.0, f32::max);
+                pixels
+                    .iter()
+                    .map(|p| {
+                        let d = if p.x().is_finite() && max > 0.0 {
+                            p.x() / max
+                        } else {
+                            1.0
+                        };
+                        Vector3::new(d, d, d)
+                    })
+                    .collect()
+            }
+            Aov::Normal => pixels
+                .iter()
+                .map(|n| 0.5 * (*n + Vector3::new(1.0, 1.0, 1.0)))
+                .collect(),
+            Aov::Position => {
+                let bounds = pixels
+                    .iter()
+                    .map(|p| AABB::new(*p, *p))
+                    .reduce(|a, b| a.surrounding_box(&b));
+                let (min, max) = match bounds {
+                    Some(bounds) => (bounds.min(), bounds.max()),
+                    None => return Vec::new(),
+                };
+                let range = |axis: usize, p: &Vector3| {
+                    let extent = max.component(axis) - min.component(axis);
+                    if extent > 0.0 {
+                        (p.component(axis) - min.component(axis)) / extent
+                    } else {
+                        0.0
+                    }
+                };
+                pixels
+                    .iter()
+                    .map(|p| Vector3::new(range(0, p), range(1, p), range(2, p)))
+                    .collect()
+            }
+            Aov::ObjectId | Aov::MaterialId => {
+                pixels.iter().map(|p| id_color(p.x() as u32)).collect()
+            }
+            Aov::Albedo | Aov::Direct | Aov::Indirect => pixels.to_vec(),
+        }
+    }
+}
+
+// A colour that differs between neighbouring IDs. Black for no object.
+fn id_color(id: u32) -> Vector3 {
+    if id == 0 {
+        return Vector3::zero();
+    }
+    // MurmurHash3's finaliser, to scatter consecutive IDs
+    let mut h = id;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^= h >> 16;
+    let channel = |shift: u32| 0.2 + 0.8 * ((h >> shift) & 0xff) as f32 / 255.0;
+    Vector3::new(channel(0), channel(8), channel(16))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::film::{Film, Filter};
+    use crate::material::Material;
+    use crate::random::Sampler;
+
+    #[test]
+    fn names_round_trip() {
+        for aov in [Aov::Depth, Aov::Normal, Aov::MaterialId, Aov::Indirect] {
+            assert!(Aov::parse(aov.name()) == Some(aov));
+        }
+        assert!(Aov::parse("beauty").is_none());
+    }
+
+    #[test]
+    fn equal_materials_share_an_id() {
+        let red = Material::Lambertian {
+            albedo: Vector3::new(0.8, 0.1, 0.1),
+        };
+        let metal = Material::Metal {
+            albedo: Vector3::new(0.8, 0.1, 0.1),
+            fuzz: 0.0,
+        };
+        assert_eq!(red.id(), red.clone().id());
+        assert_ne!(red.id(), metal.id());
+        // Lights made separately, rather than by their emission's address
+        let white = Vector3::new(1.0, 1.0, 1.0);
+        let light = Material::diffuse_light(white);
+        assert_eq!(light.id(), Material::diffuse_light(white).id());
+        assert_ne!(light.id(), Material::diffuse_light(2.0 * white).id());
+        assert!(red.id() > 0 && red.id() < 1 << 24);
+    }
+    // The sharpest filter's negative lobes would turn an average of a hit
+    // and a miss into NaN, so depth keeps the sample nearest the centre
+    #[test]
+    fn depth_of_a_half_covered_pixel() {
+        let cam = Camera::new(
+            Vector3::zero(),
+            Vector3::new(0.0, 0.0, -1.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            90.0,
+            1.0,
+            0.0,
+            1.0,
+        );
+        let hr = HitRecord {
+            t: 2.0,
+            p: Vector3::new(0.0, 0.0, -2.0),
+            normal: Vector3::new(0.0, 0.0, 1.0),
+            u: 0.0,
+            v: 0.0,
+            material: Material::Lambertian {
+                albedo: Vector3::new(0.5, 0.5, 0.5),
+            },
+            object_id: 0,
+        };
+        let r = cam.get_ray(0.5, 0.5, &mut Sampler::new(0)).unwrap();
+        let filter = Filter::parse("lanczos", None).unwrap();
+        let mut film = Film::new(3, 3, filter, vec![Aov::Depth.keeps_nearest()]);
+        // The left half of the middle pixel is covered
+        for x in [1.1, 1.3, 1.45, 1.6, 1.9] {
+            let hit = if x < 1.5 { Some(&hr) } else { None };
+            film.add_sample(x, 1.5, &[Aov::Depth.first_hit(&cam, &r, hit)]);
+        }
+        let depths = &film.layers()[0];
+        assert!(depths.iter().all(|d| !d.x().is_nan()));
+        assert!((depths[4].x() - 2.0).abs() < 0.0001);
+    }
+}
@@ -0,0 +1,240 @@
+use crate::distribution::Distribution2D;
+use crate::random::Sampler;
+use crate::ray::Ray;
+use crate::sky::Sky;
+use crate::spectrum;
+use crate::texture;
+use crate::vec3::Vector3;
+use std::f32;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+// What rays that escape the scene see
+#[derive(Clone)]
+pub enum Background {
+    Constant(Vector3),
+    // Blended by the ray's height, as in Ray Tracing in One Weekend
+    Gradient { bottom: Vector3, top: Vector3 },
+    Environment(Arc<EnvironmentMap>),
+    Sky(Sky),
+}
+
+impl Background {
+    pub fn color(&self, r: &Ray) -> Vector3 {
+        let color = match self {
+            Background::Constant(color) => *color,
+            Background::Gradient { bottom, top } => {
+                let t = 0.5 * (r.direction().get_unit_vector().y() + 1.0);
+                (1.0 - t) * *bottom + t * *top
+            }
+            Background::Environment(map) => map.radiance(&r.direction()),
+            Background::Sky(sky) => sky.radiance(&r.direction()),
+        };
+        spectrum::upsample(&color, r.wavelength())
+    }
+
+    // Picks a direction towards the background for light sampling, with
+    // its radiance and pdf. None if the background can't be sampled.
+    pub fn sample(&self, r_in: &Ray, sampler: &mut Sampler) -> Option<(Vector3, Vector3, f32)> {
+        let (direction, radiance, pdf) = match self {
+            Background::Environment(map) => map.sample(sampler)?,
+            Background::Sky(sky) => sky.sample(sampler)?,
+            _ => return None,
+        };
+        Some((
+            direction,
+            spectrum::upsample(&radiance, r_in.wavelength()),
+            pdf,
+        ))
+    }
+
+    // Density with which sample() picks `direction`
+    pub fn pdf(&self, direction: &Vector3) -> f32 {
+        match self {
+            Background::Environment(map) => map.pdf(direction),
+            Background::Sky(sky) => sky.pdf(direction),
+            _ => 0.0,
+        }
+    }
+}
+
+// An equirectangular (latitude-longitude) environment map, importance
+// sampled by luminance
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vector3>,
+    // Around the vertical axis, in radians
+    rotation: f32,
+    intensity: f32,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    pub fn load(path: &Path, rotation: f32, intensity: f32) -> io::Result<EnvironmentMap> {
+        let (width, height, pixels) = texture::load_linear_rgb(path)?;
+        Ok(EnvironmentMap::new(
+            width, height, pixels, rotation, intensity,
+        ))
+    }
+
+    pub fn new(
+        width: usize,
+        height: usize,
+        pixels: Vec<Vector3>,
+        rotation: f32,
+        intensity: f32,
+    ) -> EnvironmentMap {
+        // Rows near the poles cover less solid angle
+        let mut func = Vec::with_capacity(width * height);
+        for v in 0..height {
+            let sin_theta = (f32::consts::PI * (v as f32 + 0.5) / height as f32).sin();
+            for u in 0..width {
+                func.push(spectrum::luminance(&pixels[u + v * width]) * sin_theta);
+            }
+        }
+        EnvironmentMap {
+            width,
+            height,
+            pixels,
+            rotation,
+            intensity,
+            distribution: Distribution2D::new(&func, width, height),
+        }
+    }
+
+    // Image coordinates in [0, 1)^2 of a direction, with v = 0 straight up
+    fn direction_to_uv(&self, direction: &Vector3) -> (f32, f32) {
+        let d = direction.get_unit_vector();
+        let theta = d.y().clamp(-1.0, 1.0).acos();
+        let mut phi = (-d.z()).atan2(d.x()) - self.rotation;
+        phi = phi.rem_euclid(2.0 * f32::consts::PI);
+        (phi / (2.0 * f32::consts::PI), theta / f32::consts::PI)
+    }
+
+    fn uv_to_direction(&self, u: f32, v: f32) -> Vector3 {
+        let theta = v * f32::consts::PI;
+        let phi = u * 2.0 * f32::consts::PI + self.rotation;
+        Vector3::new(
+            theta.sin() * phi.cos(),
+            theta.cos(),
+            -theta.sin() * phi.sin(),
+        )
+    }
+
+    fn lookup(&self, u: f32, v: f32) -> Vector3 {
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+        self.intensity * self.pixels[x + y * self.width]
+    }
+
+    pub fn radiance(&self, direction: &Vector3) -> Vector3 {
+        let (u, v) = self.direction_to_uv(direction);
+        self.lookup(u, v)
+    }
+
+    pub fn sample(&self, sampler: &mut Sampler) -> Option<(Vector3, Vector3, f32)> {
+        let (u, v, map_pdf) = self
+            .distribution
+            .sample_continuous(sampler.uniform(), sampler.uniform());
+        let sin_theta = (v * f32::consts::PI).sin();
+        if map_pdf == 0.0 || sin_theta == 0.0 {
+            return None;
+        }
+        // Change of variables from the image to the sphere of directions
+        let pdf = map_pdf / (2.0 * f32::consts::PI * f32::consts::PI * sin_theta);
+        Some((self.uv_to_direction(u, v), self.lookup(u, v), pdf))
+    }
+
+    pub fn pdf(&self, direction: &Vector3) -> f32 {
+        let (u, v) = self.direction_to_uv(direction);
+        // Slightly negative rather than 0 at the bottom pole, in f32
+        let sin_theta = (v * f32::consts::PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(u, v) / (2.0 * f32::consts::PI * f32::consts::PI * sin_theta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 8;
+    const HEIGHT: usize = 4;
+
+    // Brightest in one corner, dark in another, and turned about the
+    // vertical
+    fn map() -> EnvironmentMap {
+        let pixels = (0..WIDTH * HEIGHT)
+            .map(|i| {
+                let brightness = (i % WIDTH + 2 * (i / WIDTH)) as f32;
+                Vector3::new(brightness, brightness, brightness)
+            })
+            .collect();
+        EnvironmentMap::new(WIDTH, HEIGHT, pixels, 0.7, 1.0)
+    }
+
+    #[test]
+    fn directions_round_trip() {
+        let map = map();
+        for i in 1..20 {
+            for j in 1..20 {
+                let (u, v) = (i as f32 / 20.0, j as f32 / 20.0);
+                let direction = map.uv_to_direction(u, v);
+                assert!((direction.length() - 1.0).abs() < 0.0001);
+                let (u2, v2) = map.direction_to_uv(&direction);
+                assert!((u2 - u).abs() < 0.0001 && (v2 - v).abs() < 0.0001);
+                let back = map.uv_to_direction(u2, v2);
+                assert!((back - direction).length() < 0.0001);
+            }
+        }
+    }
+
+    // The fraction of samples landing in each pixel is what pdf integrates
+    // to over it
+    #[test]
+    fn pdf_matches_sampled_density() {
+        let map = map();
+        let mut sampler = Sampler::new(0);
+        let trials = 100000;
+        let mut histogram = [0; WIDTH * HEIGHT];
+        for _ in 0..trials {
+            let (direction, _, pdf) = map.sample(&mut sampler).unwrap();
+            let (u, v) = map.direction_to_uv(&direction);
+            // In f32, directions near the poles lose too much precision
+            if v > 0.05 && v < 0.95 {
+                assert!((map.pdf(&direction) - pdf).abs() < 0.001 * pdf, "{}", v);
+            }
+            let x = ((u * WIDTH as f32) as usize).min(WIDTH - 1);
+            let y = ((v * HEIGHT as f32) as usize).min(HEIGHT - 1);
+            histogram[x + y * WIDTH] += 1;
+        }
+
+        // Integrated over the sphere by the midpoint rule
+        let steps = 16;
+        let (du, dv) = (1.0 / (WIDTH * steps) as f32, 1.0 / (HEIGHT * steps) as f32);
+        let mut total = 0.0;
+        for (pixel, count) in histogram.iter().enumerate() {
+            let (x, y) = (pixel % WIDTH, pixel / WIDTH);
+            let mut integral = 0.0;
+            for i in 0..steps {
+                for j in 0..steps {
+                    let u = (x * steps + i) as f32 * du + 0.5 * du;
+                    let v = (y * steps + j) as f32 * dv + 0.5 * dv;
+                    let sin_theta = (v * f32::consts::PI).sin();
+                    let solid_angle = 2.0 * f32::consts::PI * f32::consts::PI * sin_theta;
+                    integral += map.pdf(&map.uv_to_direction(u, v)) * solid_angle * du * dv;
+                }
+            }
+            let fraction = *count as f32 / trials as f32;
+            assert!((fraction - integral).abs() < 0.005, "{}", fraction);
+            total += integral;
+        }
+        assert!((total - 1.0).abs() < 0.001, "{}", total);
+        // The dark corner is never sampled
+        assert_eq!(histogram[0], 0);
+    }
+}
@@ -0,0 +1,827 @@
+use crate::camera::CameraModel;
+use crate::hitable::{HitRecord, Hitable};
+use crate::integrator::{Integrator, Radiance, Sample, Splat};
+use crate::light::Light;
+use crate::material::Material;
+use crate::random::Sampler;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::spectrum;
+use crate::vec3::Vector3;
+use std::f32;
+use std::sync::Arc;
+
+// Bidirectional path tracing, as in Veach's thesis and PBRT. A path is
+// traced from the camera and another from a light, then every prefix of
+// one is joined to every prefix of the other. Each of these strategies for
+// a path of a given length is weighted by the balance heuristic over all
+// of them, so caustics and light through small gaps, which the path tracer
+// rarely finds, come from whichever strategy suits them.
+//
+// Densities are per unit area at each vertex, forwards in the direction the
+// path was traced and in reverse for tracing it the other way.
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Camera,
+    Light,
+    Surface,
+}
+
+#[derive(Clone)]
+struct Vertex {
+    kind: Kind,
+    p: Vector3,
+    // Zero in volumes, on the camera and on punctual lights, where there's
+    // no surface to take a cosine with
+    normal: Vector3,
+    // For surfaces and volumes, the ray that found the vertex and its hit
+    hit: Option<(Ray, HitRecord)>,
+    // The light the vertex is on, if any
+    light: Option<usize>,
+    // On a directional light, so infinitely far away
+    infinite: bool,
+    // The path's throughput up to here
+    beta: Vector3,
+    // Scattered specularly, so can't be joined to
+    delta: bool,
+    pdf_fwd: f32,
+    pdf_rev: f32,
+}
+
+impl Vertex {
+    fn new(kind: Kind, p: Vector3, beta: Vector3) -> Vertex {
+        Vertex {
+            kind,
+            p,
+            normal: Vector3::zero(),
+            hit: None,
+            light: None,
+            infinite: false,
+            beta,
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        }
+    }
+
+    fn on_surface(&self) -> bool {
+        self.normal != Vector3::zero()
+    }
+
+    fn connectable(&self) -> bool {
+        match &self.hit {
+            Some((_, hr)) => !hr.material.is_specular(),
+            None => true,
+        }
+    }
+
+    // Turns a density per steradian of going from here to next into one per
+    // unit area at next
+    fn convert_density(&self, pdf: f32, next: &Vertex) -> f32 {
+        if next.infinite {
+            return pdf;
+        }
+        let w = next.p - self.p;
+        let distance_squared = w.length_squared();
+        if distance_squared == 0.0 {
+            return 0.0;
+        }
+        let mut pdf = pdf / distance_squared;
+        if next.on_surface() {
+            pdf *= next.normal.dot(&(w / distance_squared.sqrt())).abs();
+        }
+        pdf
+    }
+
+    // Scattering at a surface or volume vertex, for light between here and
+    // next going towards where the path came from
+    fn f(&self, next: &Vertex, wavelength: Option<f32>) -> Vector3 {
+        match &self.hit {
+            Some((ray, hr)) => {
+                let wo = -ray.direction().get_unit_vector();
+                let wi = (next.p - self.p).get_unit_vector();
+                hr.material.f(hr, &wo, &wi, wavelength)
+            }
+            None => Vector3::zero(),
+        }
+    }
+
+    // Density per unit area at next of going there from here, having come
+    // from prev
+    fn pdf<T: Hitable>(
+        &self,
+        scene: &Scene<T>,
+        cam: &dyn CameraModel,
+        radius: f32,
+        prev: Option<&Vertex>,
+        next: &Vertex,
+    ) -> f32 {
+        match self.kind {
+            Kind::Light => self.pdf_light(scene, radius, next),
+            Kind::Camera => {
+                let r = Ray::new(self.p, next.p - self.p);
+                let pdf_dir = cam.ray_pdf(&r).map_or(0.0, |(_, pdf_dir)| pdf_dir);
+                self.convert_density(pdf_dir, next)
+            }
+            Kind::Surface => {
+                let (prev, (_, hr)) = match (prev, &self.hit) {
+                    (Some(prev), Some(hit)) => (prev, hit),
+                    _ => return 0.0,
+                };
+                let wo = (prev.p - self.p).get_unit_vector();
+                let wi = (next.p - self.p).get_unit_vector();
+                self.convert_density(hr.material.pdf_between(hr, &wo, &wi), next)
+            }
+        }
+    }
+
+    // Density per unit area at next of a path from the light this vertex is
+    // on going there
+    fn pdf_light<T: Hitable>(&self, scene: &Scene<T>, radius: f32, next: &Vertex) -> f32 {
+        let light = match self.light {
+            Some(light) => &scene.lights[light],
+            None => return 0.0,
+        };
+        let w = next.p - self.p;
+        let mut pdf = if self.infinite {
+            light.pdf_emission(&self.normal, &w, radius).0
+        } else {
+            let distance_squared = w.length_squared();
+            if distance_squared == 0.0 {
+                return 0.0;
+            }
+            light.pdf_emission(&self.normal, &w, radius).1 / distance_squared
+        };
+        if next.on_surface() {
+            pdf *= next.normal.dot(&w.get_unit_vector()).abs();
+        }
+        pdf
+    }
+
+    // Density of a light path starting here, including picking the light
+    fn pdf_light_origin<T: Hitable>(&self, scene: &Scene<T>, radius: f32) -> f32 {
+        match self.light {
+            Some(light) => {
+                let pdf_pos = scene.lights[light]
+                    .pdf_emission(&self.normal, &Vector3::zero(), radius)
+                    .0;
+                pdf_pos / scene.lights.len() as f32
+            }
+            None => 0.0,
+        }
+    }
+
+    // Whether the vertex is on a light that paths can't hit
+    fn is_delta_light<T: Hitable>(&self, scene: &Scene<T>) -> bool {
+        self.light
+            .is_some_and(|light| scene.lights[light].is_punctual())
+    }
+}
+
+// Fraction of light getting between a and b
+fn transmittance<T: Hitable>(scene: &Scene<T>, r: &Ray, a: &Vertex, b: &Vertex) -> f32 {
+    let (from, to) = if a.infinite { (b, a) } else { (a, b) };
+    let offset = to.p - from.p;
+    let length = offset.length();
+    let shadow_ray = r.spawn(from.p, offset / length);
+    // Stop short of the surface at the far end. Directional lights are
+    // infinitely far away.
+    let (distance, surfaces) = if to.infinite {
+        (f32::MAX, f32::MAX)
+    } else {
+        (length, length * 0.9999)
+    };
+    let mut transmittance = scene.objects.transmittance(&shadow_ray, 0.001, surfaces);
+    if let Some(fog) = &scene.fog {
+        transmittance *= fog.transmittance(&shadow_ray, 0.001, distance);
+    }
+    transmittance
+}
+
+// Extends path along ray until it escapes, is absorbed, runs out of bounces
+// or has max_vertices. beta is the throughput along ray and pdf_dir the
+// density it was picked with. For camera paths, gives the light from the
+// background where the path escaped, and its bounces.
+#[allow(clippy::too_many_arguments)]
+fn random_walk<T: Hitable>(
+    scene: &Scene<T>,
+    mut ray: Ray,
+    mut beta: Vector3,
+    mut pdf_dir: f32,
+    path: &mut Vec<Vertex>,
+    max_vertices: usize,
+    camera: bool,
+    sampler: &mut Sampler,
+) -> Option<(Vector3, u32)> {
+    let mut bounces = [0; 4];
+    while path.len() < max_vertices {
+        let hr = match scene.hit(&ray, sampler) {
+            Some(hr) => hr,
+            None if camera => {
+                let bounces = path.len() as u32 - 1;
+                return Some((beta * scene.background.color(&ray), bounces));
+            }
+            None => return None,
+        };
+
+        let scattered = hr.material.scatter(&ray, &hr, sampler);
+        let bounce = hr.material.bounce();
+        let material = hr.material.clone();
+        let mut vertex = Vertex::new(Kind::Surface, hr.p, beta);
+        if !matches!(material, Material::Volume { .. }) {
+            vertex.normal = hr.normal;
+        }
+        vertex.light = scene.light_tree.light_index(&hr);
+        let prev = path.last().unwrap();
+        vertex.pdf_fwd = prev.convert_density(pdf_dir, &vertex);
+        vertex.hit = Some((ray.clone(), hr));
+        path.push(vertex);
+
+        let s = match scattered {
+            Some(s) => s,
+            None => break,
+        };
+        if bounces[bounce as usize] >= scene.settings.limits.max(bounce) {
+            break;
+        }
+        bounces[bounce as usize] += 1;
+
+        let n = path.len();
+        let (wo, wi) = (
+            -ray.direction().get_unit_vector(),
+            s.ray.direction().get_unit_vector(),
+        );
+        let pdf_rev = match s.pdf {
+            Some(pdf) => {
+                let (_, hr) = path[n - 1].hit.as_ref().unwrap();
+                pdf_dir = pdf;
+                material.pdf_between(hr, &wi, &wo)
+            }
+            None => {
+                path[n - 1].delta = true;
+                pdf_dir = 0.0;
+                0.0
+            }
+        };
+        path[n - 2].pdf_rev = path[n - 1].convert_density(pdf_rev, &path[n - 2]);
+        beta = beta * s.color;
+        ray = s.ray;
+    }
+    None
+}
+
+// A path from a light picked uniformly, with at most max_vertices
+fn light_subpath<T: Hitable>(
+    scene: &Scene<T>,
+    r: &Ray,
+    center: &Vector3,
+    radius: f32,
+    max_vertices: usize,
+    sampler: &mut Sampler,
+) -> Vec<Vertex> {
+    let mut path = Vec::new();
+    if scene.lights.is_empty() || max_vertices == 0 {
+        return path;
+    }
+    let count = scene.lights.len();
+    let index = sampler.index(count);
+    let pmf = 1.0 / count as f32;
+    let light = &scene.lights[index];
+    let e = match light.sample_emission(center, radius, sampler) {
+        Some(e) => e,
+        None => return path,
+    };
+
+    let radiance = spectrum::upsample(&e.radiance, r.wavelength());
+    let mut vertex = Vertex::new(Kind::Light, e.origin, radiance / (pmf * e.pdf_pos));
+    vertex.normal = e.normal;
+    vertex.light = Some(index);
+    vertex.infinite = matches!(light, Light::Directional { .. });
+    vertex.pdf_fwd = pmf * e.pdf_pos;
+    path.push(vertex);
+
+    let cosine = if e.normal == Vector3::zero() {
+        1.0
+    } else {
+        e.normal.dot(&e.direction).abs()
+    };
+    let beta = radiance * (cosine / (pmf * e.pdf_pos * e.pdf_dir));
+    let ray = r.spawn(e.origin, e.direction);
+    random_walk(
+        scene,
+        ray,
+        beta,
+        e.pdf_dir,
+        &mut path,
+        max_vertices,
+        false,
+        sampler,
+    );
+
+    // Rays from a directional light are spread over the disk they start on,
+    // not over directions
+    if path[0].infinite && path.len() > 1 {
+        let mut pdf = e.pdf_pos;
+        if path[1].on_surface() {
+            pdf *= path[1].normal.dot(&e.direction).abs();
+        }
+        path[1].pdf_fwd = pdf;
+    }
+    path
+}
+
+// Bidirectional path tracing, joining light paths to the camera, which
+// must be the one the rays come from
+pub struct Bdpt {
+    camera: Arc<dyn CameraModel>,
+    max_depth: u32,
+}
+
+impl Bdpt {
+    pub fn new(camera: Arc<dyn CameraModel>, max_depth: u32) -> Bdpt {
+        Bdpt { camera, max_depth }
+    }
+}
+
+impl<T: Hitable> Integrator<T> for Bdpt {
+    fn sample(&self, r: &Ray, scene: &Scene<T>, sampler: &mut Sampler) -> Sample {
+        color(r, self.camera.as_ref(), scene, self.max_depth, sampler)
+    }
+}
+
+// Light arriving along r from every strategy, up to max_depth bounces, and
+// what the light paths splatted onto the image
+fn color<T: Hitable>(
+    r: &Ray,
+    cam: &dyn CameraModel,
+    scene: &Scene<T>,
+    max_depth: u32,
+    sampler: &mut Sampler,
+) -> Sample {
+    let (center, radius) = scene.bounds();
+    let max_depth = max_depth as usize;
+
+    let mut camera_vertex = Vertex::new(Kind::Camera, r.origin(), Vector3::new(1.0, 1.0, 1.0));
+    camera_vertex.pdf_fwd = 1.0;
+    let mut camera_path = vec![camera_vertex];
+    let pdf_dir = cam.ray_pdf(r).map_or(0.0, |(_, pdf_dir)| pdf_dir);
+    let escaped = random_walk(
+        scene,
+        r.clone(),
+        Vector3::new(1.0, 1.0, 1.0),
+        pdf_dir,
+        &mut camera_path,
+        max_depth + 2,
+        true,
+        sampler,
+    );
+    let mut radiance = match escaped {
+        Some((background, bounces)) => Radiance::new(background, bounces),
+        None => Radiance::zero(),
+    };
+    let light_path = light_subpath(scene, r, &center, radius, max_depth + 1, sampler);
+
+    let strategy = Strategy {
+        scene,
+        cam,
+        r,
+        radius,
+        light_path: &light_path,
+        camera_path: &camera_path,
+        joinable: cam.ray_pdf(r).is_some(),
+    };
+    let mut splats = Vec::new();
+    for t in 1..=camera_path.len() {
+        for s in 0..=light_path.len() {
+            if s + t < 2 || s + t - 2 > max_depth || (s == 1 && t == 1) {
+                continue;
+            }
+            if t == 1 && !strategy.joinable {
+                continue;
+            }
+            if let Some((value, image)) = strategy.connect(s, t, sampler) {
+                match image {
+                    Some((image_s, image_t)) => splats.push(Splat {
+                        s: image_s,
+                        t: image_t,
+                        value,
+                    }),
+                    None => radiance = radiance + Radiance::new(value, (s + t - 2) as u32),
+                }
+            }
+        }
+    }
+    let first_hit = camera_path
+        .get(1)
+        .and_then(|v| v.hit.as_ref())
+        .map(|(_, hr)| hr.clone());
+    Sample {
+        radiance,
+        splats,
+        first_hit,
+    }
+}
+
+// The two paths being joined, and what's needed to weigh up the ways of
+// joining them
+struct Strategy<'a, T: Hitable> {
+    scene: &'a Scene<T>,
+    cam: &'a dyn CameraModel,
+    r: &'a Ray,
+    radius: f32,
+    light_path: &'a [Vertex],
+    camera_path: &'a [Vertex],
+    joinable: bool,
+}
+
+impl<'a, T: Hitable> Strategy<'a, T> {
+    // Light from joining the first s vertices of the light path to the
+    // first t of the camera path, weighted, and where on the image it lands
+    // when t is 1
+    fn connect(
+        &self,
+        s: usize,
+        t: usize,
+        sampler: &mut Sampler,
+    ) -> Option<(Vector3, Option<(f32, f32)>)> {
+        let scene = self.scene;
+        let wavelength = self.r.wavelength();
+        let pt = &self.camera_path[t - 1];
+        let mut sampled = None;
+        let mut image = None;
+
+        let value = if s == 0 {
+            // The camera path hit a light by itself
+            let (ray, hr) = pt.hit.as_ref()?;
+            let emitted = pt.beta * hr.material.emitted(ray, hr);
+            // Emitters that aren't lights can't be reached any other way
+            if pt.light.is_none() {
+                return if emitted == Vector3::zero() {
+                    None
+                } else {
+                    Some((emitted, None))
+                };
+            }
+            emitted
+        } else if t == 1 {
+            // The light path seen directly by the camera
+            let qs = &self.light_path[s - 1];
+            if !qs.connectable() {
+                return None;
+            }
+            let sample = self.cam.sample_importance(&qs.p, sampler)?;
+            let camera = Vertex::new(
+                Kind::Camera,
+                sample.lens,
+                Vector3::new(1.0, 1.0, 1.0) * (sample.importance / sample.pdf),
+            );
+            let mut value = qs.beta * qs.f(&camera, wavelength) * camera.beta;
+            if qs.on_surface() {
+                value = value * qs.normal.dot(&(sample.lens - qs.p).get_unit_vector()).abs();
+            }
+            if value == Vector3::zero() {
+                return None;
+            }
+            value = value * transmittance(scene, self.r, qs, &camera);
+            image = Some((sample.s, sample.t));
+            sampled = Some(camera);
+            value
+        } else if s == 1 {
+            // A light sampled from the camera path
+            if !pt.connectable() {
+                return None;
+            }
+            let count = scene.lights.len();
+            if count == 0 {
+                return None;
+            }
+            let index = sampler.index(count);
+            let pmf = 1.0 / count as f32;
+            let sample = scene.lights[index].sample(&pt.p, sampler)?;
+            let infinite = matches!(scene.lights[index], Light::Directional { .. });
+            let p = if infinite {
+                pt.p + sample.direction * (2.0 * self.radius)
+            } else {
+                pt.p + sample.direction * sample.distance
+            };
+            let radiance = spectrum::upsample(&sample.radiance, wavelength)
+                / (pmf * sample.pdf.unwrap_or(1.0));
+            let mut light = Vertex::new(Kind::Light, p, radiance);
+            light.normal = sample.normal;
+            light.light = Some(index);
+            light.infinite = infinite;
+            light.pdf_fwd = light.pdf_light_origin(scene, self.radius);
+
+            let mut value = pt.beta * pt.f(&light, wavelength) * radiance;
+            if pt.on_surface() {
+                value = value * pt.normal.dot(&sample.direction).abs();
+            }
+            if value == Vector3::zero() {
+                return None;
+            }
+            value = value * transmittance(scene, self.r, pt, &light);
+            sampled = Some(light);
+            value
+        } else {
+            let qs = &self.light_path[s - 1];
+            if !qs.connectable() || !pt.connectable() {
+                return None;
+            }
+            let value = qs.beta * qs.f(pt, wavelength) * pt.f(qs, wavelength) * pt.beta;
+            if value == Vector3::zero() {
+                return None;
+            }
+            value * self.geometry(qs, pt)
+        };
+
+        if value == Vector3::zero() {
+            return None;
+        }
+        let weight = if s + t == 2 {
+            1.0
+        } else {
+            self.mis_weight(sampled.as_ref(), s, t)
+        };
+        Some((value * weight, image))
+    }
+
+    // The geometry term between two vertices, with the light getting
+    // between them
+    fn geometry(&self, a: &Vertex, b: &Vertex) -> f32 {
+        let offset = b.p - a.p;
+        let distance_squared = offset.length_squared();
+        let w = offset / distance_squared.sqrt();
+        let mut g = 1.0 / distance_squared;
+        if a.on_surface() {
+            g *= a.normal.dot(&w).abs();
+        }
+        if b.on_surface() {
+            g *= b.normal.dot(&w).abs();
+        }
+        g * transmittance(self.scene, self.r, a, b)
+    }
+
+    // Balance heuristic weight of strategy (s, t) against every other way
+    // of making the same path. sampled replaces the camera vertex when t is
+    // 1 and the light vertex when s is 1.
+    fn mis_weight(&self, sampled: Option<&Vertex>, s: usize, t: usize) -> f32 {
+        let (scene, cam, radius) = (self.scene, self.cam, self.radius);
+        let pt = match (t, sampled) {
+            (1, Some(camera)) => camera,
+            _ => &self.camera_path[t - 1],
+        };
+        let qs = match (s, sampled) {
+            (0, _) => None,
+            (1, Some(light)) => Some(light),
+            _ => Some(&self.light_path[s - 1]),
+        };
+        let pt_minus = if t > 1 {
+            Some(&self.camera_path[t - 2])
+        } else {
+            None
+        };
+        let qs_minus = if s > 1 {
+            Some(&self.light_path[s - 2])
+        } else {
+            None
+        };
+
+        // (pdf_fwd, pdf_rev, delta) of each vertex, with those around the
+        // join as they'd be for this strategy
+        let fields = |v: &Vertex| (v.pdf_fwd, v.pdf_rev, v.delta);
+        let mut camera: Vec<(f32, f32, bool)> = self.camera_path[..t].iter().map(fields).collect();
+        let mut light: Vec<(f32, f32, bool)> = self.light_path[..s].iter().map(fields).collect();
+        camera[t - 1] = (pt.pdf_fwd, 0.0, false);
+        if let Some(qs) = qs {
+            light[s - 1] = (qs.pdf_fwd, 0.0, false);
+        }
+
+        camera[t - 1].1 = match qs {
+            Some(qs) => qs.pdf(scene, cam, radius, qs_minus, pt),
+            None => pt.pdf_light_origin(scene, radius),
+        };
+        if let Some(pt_minus) = pt_minus {
+            camera[t - 2].1 = match qs {
+                Some(qs) => pt.pdf(scene, cam, radius, Some(qs), pt_minus),
+                None => pt.pdf_light(scene, radius, pt_minus),
+            };
+        }
+        if let Some(qs) = qs {
+            light[s - 1].1 = pt.pdf(scene, cam, radius, pt_minus, qs);
+        }
+        if let (Some(qs), Some(qs_minus)) = (qs, qs_minus) {
+            light[s - 2].1 = qs.pdf(scene, cam, radius, Some(pt), qs_minus);
+        }
+
+        // Zero densities come from delta vertices, which are skipped anyway
+        let remap = |pdf: f32| if pdf != 0.0 { pdf } else { 1.0 };
+        let mut sum = 0.0;
+        let mut ratio = 1.0;
+        for i in (1..t).rev() {
+            ratio *= remap(camera[i].1) / remap(camera[i].0);
+            // The light path can only reach the lens when the camera can
+            // be joined to, and the (1, 1) strategy isn't used
+            let possible = i > 1 || (self.joinable && s + t > 2);
+            if !camera[i].2 && !camera[i - 1].2 && possible {
+                sum += ratio;
+            }
+        }
+        ratio = 1.0;
+        for i in (0..s).rev() {
+            ratio *= remap(light[i].1) / remap(light[i].0);
+            let delta_before = if i > 0 {
+                light[i - 1].2
+            } else {
+                let origin = if s == 1 {
+                    qs.unwrap()
+                } else {
+                    &self.light_path[0]
+                };
+                origin.is_delta_light(scene)
+            };
+            if !light[i].2 && !delta_before {
+                sum += ratio;
+            }
+        }
+        1.0 / (1.0 + sum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::background::Background;
+    use crate::bvh::BVHNode;
+    use crate::camera::Camera;
+    use crate::emission::Emission;
+    use crate::film::{Film, Filter};
+    use crate::hitable::HitableList;
+    use crate::integrator::PathTracer;
+    use crate::light::MeshLight;
+    use crate::scene::{PathLimits, RenderSettings};
+    use crate::triangle;
+
+    fn grey() -> Material {
+        Material::Lambertian {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        }
+    }
+
+    // A square of half_size around the origin, facing up
+    fn floor(half_size: f32) -> HitableList {
+        let mut floor = HitableList::new();
+        for t in triangle::quad(
+            Vector3::new(-half_size, 0.0, -half_size),
+            Vector3::new(0.0, 0.0, 2.0 * half_size),
+            Vector3::new(2.0 * half_size, 0.0, 0.0),
+            grey(),
+        ) {
+            floor.add(t);
+        }
+        floor
+    }
+
+    fn build(mut scene: Scene<HitableList>) -> Scene<BVHNode> {
+        scene.background = Background::Constant(Vector3::zero());
+        scene.settings = RenderSettings {
+            light_sampling: true,
+            limits: PathLimits {
+                diffuse: 4,
+                glossy: 4,
+                transmission: 4,
+                volume: 4,
+                roulette_depth: 4,
+            },
+        };
+        scene.build().unwrap()
+    }
+
+    // The mean over a small image rendered by integrator, with splats
+    fn mean_radiance(
+        integrator: &dyn Integrator<BVHNode>,
+        cam: &Camera,
+        scene: &Scene<BVHNode>,
+        samples: u32,
+    ) -> f32 {
+        let size = 8;
+        let mut film = Film::new(size, size, Filter::parse("box", None).unwrap(), vec![false]);
+        let mut sampler = Sampler::new(0);
+        for j in 0..size {
+            for i in 0..size {
+                for _ in 0..samples {
+                    let x = i as f32 + sampler.uniform();
+                    let y = j as f32 + sampler.uniform();
+                    let (s, t) = (x / size as f32, 1.0 - y / size as f32);
+                    let ray = cam.get_ray(s, t, &mut sampler).unwrap();
+                    let sample = integrator.sample(&ray, scene, &mut sampler);
+                    film.add_sample(x, y, &[sample.radiance.total()]);
+                    for splat in sample.splats {
+                        film.add_splat(
+                            splat.s * size as f32,
+                            (1.0 - splat.t) * size as f32,
+                            splat.value / samples as f32,
+                        );
+                    }
+                }
+            }
+        }
+        let pixels = &film.layers()[0];
+        pixels.iter().map(|p| p.x()).sum::<f32>() / pixels.len() as f32
+    }
+
+    fn looking_down(from: Vector3, vfov: f32) -> Camera {
+        Camera::new(
+            from,
+            Vector3::new(from.x(), 0.0, from.z()),
+            Vector3::new(0.0, 0.0, 1.0),
+            vfov,
+            1.0,
+            0.0,
+            2.0,
+        )
+    }
+
+    // A grey floor lit straight from above reflects albedo / π of the
+    // irradiance, however the strategies share it out between pixel
+    // samples and splats
+    #[test]
+    fn lit_floor_has_its_radiance() {
+        let mut scene = Scene::new(floor(2.0));
+        scene.lights = vec![Light::Directional {
+            direction: Vector3::new(0.0, -1.0, 0.0),
+            irradiance: Vector3::new(1.0, 1.0, 1.0) * f32::consts::PI,
+        }];
+        let scene = build(scene);
+        let cam = Arc::new(looking_down(Vector3::new(0.0, 2.0, 0.0), 40.0));
+        let integrator = Bdpt::new(cam.clone(), 4);
+        let mean = mean_radiance(&integrator, &cam, &scene, 256);
+        assert!((mean - 0.5).abs() < 0.02, "{}", mean);
+    }
+
+    // Under a panel light, where paths from the light can hit the camera
+    // path's surfaces and the camera path can hit the panel, the strategies
+    // add up to what the path tracer finds
+    #[test]
+    fn area_light_matches_the_path_tracer() {
+        let mut scene = Scene::new(floor(2.0));
+        scene.add_area_light(MeshLight::new(
+            // Facing down
+            triangle::quad(
+                Vector3::new(-0.5, 1.0, -0.5),
+                Vector3::new(1.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.0, 1.0),
+                grey(),
+            ),
+            Emission {
+                two_sided: false,
+                ..Emission::new(Vector3::new(2.0, 2.0, 2.0))
+            },
+            None,
+        ));
+        let scene = build(scene);
+        // Between the panel and the floor
+        let cam = Arc::new(looking_down(Vector3::new(0.0, 0.9, 0.0), 90.0));
+        let expected = mean_radiance(&PathTracer, &cam, &scene, 256);
+        let mean = mean_radiance(&Bdpt::new(cam.clone(), 4), &cam, &scene, 256);
+        assert!(expected > 0.1);
+        let error = (mean - expected).abs() / expected;
+        assert!(error < 0.03, "{} {}", mean, expected);
+    }
+
+    // Seen in a mirror, the lit floor is as bright as directly, less what
+    // the mirror absorbs. No strategy can join at the mirror, so it all
+    // comes from the camera path.
+    #[test]
+    fn floor_in_a_mirror() {
+        let mut scene = Scene::new(floor(4.0));
+        // Upright and facing back along x
+        for t in triangle::quad(
+            Vector3::new(1.0, 0.0, -2.0),
+            Vector3::new(0.0, 0.0, 4.0),
+            Vector3::new(0.0, 3.0, 0.0),
+            Material::Metal {
+                albedo: Vector3::new(0.8, 0.8, 0.8),
+                fuzz: 0.0,
+            },
+        ) {
+            scene.objects.add(t);
+        }
+        scene.lights = vec![Light::Directional {
+            direction: Vector3::new(0.0, -1.0, 0.0),
+            irradiance: Vector3::new(1.0, 1.0, 1.0) * f32::consts::PI,
+        }];
+        let scene = build(scene);
+        let cam = Arc::new(Camera::new(
+            Vector3::new(0.0, 2.0, 0.0),
+            Vector3::new(1.0, 1.5, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            10.0,
+            1.0,
+            0.0,
+            2.0,
+        ));
+        let mean = mean_radiance(&Bdpt::new(cam.clone(), 4), &cam, &scene, 64);
+        assert!((mean - 0.4).abs() < 0.02, "{}", mean);
+    }
+}
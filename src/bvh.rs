@@ -1,8 +1,10 @@
 use crate::aabb::AABB;
+use crate::hitable::form_solid;
 use crate::hitable::HitRecord;
 use crate::hitable::Hitable;
 use crate::random;
 use crate::ray::Ray;
+use crate::vec3::Vector3;
 use std::cmp::PartialOrd;
 
 // TODO Look into typedefs in Rust, especially for the Box<dyn Hitable + Send>
@@ -14,7 +16,8 @@ use std::cmp::PartialOrd;
 #[derive(Clone)]
 pub struct BVHNode {
     left: Box<dyn Hitable + Send>,
-    right: Box<dyn Hitable + Send>,
+    // Only missing in a node built from a single Hitable
+    right: Option<Box<dyn Hitable + Send>>,
     pub bbox: AABB,
 }
 
@@ -53,34 +56,36 @@ impl BVHNode {
             _ => panic!("Rng generated a bad value."),
         }
 
-        let left;
-        let right;
+        let left: Box<dyn Hitable + Send>;
+        let right: Option<Box<dyn Hitable + Send>>;
         match n {
             1 => {
-                right = l[0].clone();
                 left = l[0].clone();
+                right = None;
             }
             2 => {
                 left = l[0].clone();
-                right = l[1].clone();
+                right = Some(l[1].clone());
             }
             _ => {
                 left = Box::new(BVHNode::new(l[0..(n / 2)].to_vec()));
-                right = Box::new(BVHNode::new(l[(n / 2)..n].to_vec()));
+                right = Some(Box::new(BVHNode::new(l[(n / 2)..n].to_vec())));
             }
         }
 
         let box_left = left.bounding_box();
-        let box_right = right.bounding_box();
+        let box_right = match &right {
+            Some(right) => right.bounding_box(),
+            None => box_left.clone(),
+        };
 
-        if !(box_left.is_some() && box_right.is_some()) {
-            panic!("Failure in bounding bvh construction")
-        }
-
-        BVHNode {
-            left,
-            right,
-            bbox: box_left.unwrap().surrounding_box(&box_right.unwrap()),
+        match (box_left, box_right) {
+            (Some(box_left), Some(box_right)) => BVHNode {
+                left,
+                right,
+                bbox: box_left.surrounding_box(&box_right),
+            },
+            _ => panic!("Failure in bounding bvh construction"),
         }
     }
 }
@@ -89,28 +94,79 @@ impl Hitable for BVHNode {
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
         if self.bbox.hit(r, t_min, t_max) {
             let hit_left = self.left.hit(r, t_min, t_max);
-            let hit_right = self.right.hit(r, t_min, t_max);
+            let hit_right = match &self.right {
+                Some(right) => right.hit(r, t_min, t_max),
+                None => None,
+            };
 
-            if hit_left.is_some() && hit_right.is_some() {
-                let left_rec = hit_left.unwrap();
-                let right_rec = hit_right.unwrap();
-                if left_rec.t < right_rec.t {
-                    Some(left_rec)
-                } else {
-                    Some(right_rec)
+            match (hit_left, hit_right) {
+                (Some(left_rec), Some(right_rec)) => {
+                    if left_rec.t < right_rec.t {
+                        Some(left_rec)
+                    } else {
+                        Some(right_rec)
+                    }
                 }
-            } else if hit_left.is_some() {
-                hit_left
-            } else if hit_right.is_some() {
-                hit_right
-            } else {
-                None
+                (Some(left_rec), None) => Some(left_rec),
+                (None, right_rec) => right_rec,
             }
         } else {
             None
         }
     }
 
+    fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        if !self.bbox.hit(r, t_min, t_max) {
+            return 1.0;
+        }
+        let transmittance = self.left.transmittance(r, t_min, t_max);
+        match &self.right {
+            Some(right) if transmittance > 0.0 => {
+                transmittance * right.transmittance(r, t_min, t_max)
+            }
+            _ => transmittance,
+        }
+    }
+
+    // Like hit(), both children are searched whenever the box is hit
+    fn traversal_cost(&self, r: &Ray, t_min: f32, t_max: f32) -> u32 {
+        if !self.bbox.hit(r, t_min, t_max) {
+            return 1;
+        }
+        let right = match &self.right {
+            Some(right) => right.traversal_cost(r, t_min, t_max),
+            None => 0,
+        };
+        1 + self.left.traversal_cost(r, t_min, t_max) + right
+    }
+
+    fn all_hits(&self, r: &Ray) -> Option<Vec<HitRecord>> {
+        if self.bbox.intersect(r, -f32::MAX, f32::MAX).is_none() {
+            return Some(Vec::new());
+        }
+        let mut hits = self.left.all_hits(r)?;
+        if let Some(right) = &self.right {
+            hits.extend(right.all_hits(r)?);
+            hits.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap());
+        }
+        Some(hits)
+    }
+
+    fn is_solid(&self) -> bool {
+        match &self.right {
+            Some(right) => form_solid(&[self.left.as_ref(), right.as_ref()]),
+            None => self.left.is_solid(),
+        }
+    }
+
+    fn triangles(&self) -> Option<Vec<[Vector3; 3]>> {
+        let mut triangles = self.left.triangles()?;
+        if let Some(right) = &self.right {
+            triangles.extend(right.triangles()?);
+        }
+        Some(triangles)
+    }
+
     fn bounding_box(&self) -> Option<AABB> {
         Some(self.bbox.clone())
     }
@@ -1,17 +1,204 @@
-use crate::random;
+use crate::distribution::Distribution2D;
+use crate::hitable::Hitable;
+use crate::motion::Keyframes;
+use crate::random::Sampler;
 use crate::ray::Ray;
+use crate::spectrum;
+use crate::texture;
 use crate::vec3::Vector3;
 use std::f32;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
 
-#[derive(Copy, Clone)]
+// The shape of the lens opening, which out of focus highlights take on
+#[derive(Clone)]
+pub enum Aperture {
+    Circle,
+    // A regular polygon, as formed by the blades of an iris. Rotation is in
+    // degrees.
+    Polygon { blades: u32, rotation: f32 },
+    // A mask letting light through in proportion to its brightness
+    Image(Arc<ApertureImage>),
+}
+
+impl Aperture {
+    // A point on the aperture, within the unit disk
+    fn sample(&self, sampler: &mut Sampler) -> (f32, f32) {
+        match self {
+            Aperture::Circle => {
+                let p = sampler.in_unit_disk();
+                (p.x(), p.y())
+            }
+            Aperture::Polygon { blades, rotation } => {
+                // Pick one of the triangles fanning out from the centre,
+                // then a point in it
+                let n = (*blades).max(3);
+                let segment = ((sampler.uniform() * n as f32) as u32).min(n - 1);
+                let angle =
+                    |i: u32| rotation.to_radians() + 2.0 * f32::consts::PI * i as f32 / n as f32;
+                let (a0, a1) = (angle(segment), angle(segment + 1));
+                let su = sampler.uniform().sqrt();
+                let b = sampler.uniform();
+                let (w0, w1) = (su * (1.0 - b), su * b);
+                (w0 * a0.cos() + w1 * a1.cos(), w0 * a0.sin() + w1 * a1.sin())
+            }
+            Aperture::Image(image) => image.sample(sampler),
+        }
+    }
+}
+
+// A greyscale aperture mask, stretched over the square around the unit disk.
+// Corners outside the disk are cut off, so it's no bigger than the round
+// aperture it replaces.
+pub struct ApertureImage {
+    distribution: Distribution2D,
+}
+
+impl ApertureImage {
+    pub fn load(path: &Path) -> io::Result<ApertureImage> {
+        let (width, height, pixels) = texture::load_linear_rgb(path)?;
+        let func: Vec<f32> = pixels.iter().map(spectrum::luminance).collect();
+        ApertureImage::new(width, height, func)
+    }
+
+    fn new(width: usize, height: usize, mut func: Vec<f32>) -> io::Result<ApertureImage> {
+        for (i, f) in func.iter_mut().enumerate() {
+            let x = 2.0 * ((i % width) as f32 + 0.5) / width as f32 - 1.0;
+            let y = 2.0 * ((i / width) as f32 + 0.5) / height as f32 - 1.0;
+            if x * x + y * y > 1.0 {
+                *f = 0.0;
+            }
+        }
+        if func.iter().all(|f| *f <= 0.0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "aperture image is black",
+            ));
+        }
+        Ok(ApertureImage {
+            distribution: Distribution2D::new(&func, width, height),
+        })
+    }
+
+    // Pixels on the disk's edge still reach past it, so points there are
+    // tried again
+    fn sample(&self, sampler: &mut Sampler) -> (f32, f32) {
+        loop {
+            let (u, v, _) = self
+                .distribution
+                .sample_continuous(sampler.uniform(), sampler.uniform());
+            // The first row is the top of the image
+            let (x, y) = (2.0 * u - 1.0, 1.0 - 2.0 * v);
+            if x * x + y * y <= 1.0 {
+                return (x, y);
+            }
+        }
+    }
+}
+
+// Maps a point on the image, with (0, 0) at the bottom left and (1, 1) at
+// the top right, to the ray it sees. None if nothing is seen there.
+pub trait CameraModel: Send + Sync {
+    fn get_ray(&self, s: f32, t: f32, sampler: &mut Sampler) -> Option<Ray>;
+
+    // How far p, seen along r, is from the camera, for the depth pass.
+    // Along the view direction where the camera has one, otherwise along
+    // the ray.
+    fn depth(&self, r: &Ray, p: &Vector3) -> f32 {
+        (*p - r.origin()).length()
+    }
+
+    // A point on the lens that sees p, for joining paths traced from lights
+    // to the camera. None if p is out of view, or the camera can't be
+    // joined to.
+    fn sample_importance(&self, _p: &Vector3, _sampler: &mut Sampler) -> Option<ImportanceSample> {
+        None
+    }
+
+    // Densities with which get_ray() picks r's origin, per unit lens area,
+    // and its direction, per steradian. None if the camera can't be joined
+    // to.
+    fn ray_pdf(&self, _r: &Ray) -> Option<(f32, f32)> {
+        None
+    }
+}
+
+// Where a light path joins the camera: a point on the lens, where on the
+// image it's seen, the camera's importance for the ray from there, and the
+// density of the lens point per steradian as seen from the path
+pub struct ImportanceSample {
+    pub lens: Vector3,
+    pub s: f32,
+    pub t: f32,
+    pub importance: f32,
+    pub pdf: f32,
+}
+
+// When the shutter is open, in the scene's units of time. Each ray is sent
+// at a random time in between, so anything moving is blurred.
+#[derive(Clone, Copy)]
+pub struct Shutter {
+    open: f32,
+    close: f32,
+}
+
+impl Shutter {
+    pub fn new(open: f32, close: f32) -> Shutter {
+        Shutter { open, close }
+    }
+
+    pub fn sample(&self, sampler: &mut Sampler) -> f32 {
+        self.open + sampler.uniform() * (self.close - self.open)
+    }
+}
+
+// Where the camera is and how its lens is set over time, for animations
+#[derive(Clone)]
+pub struct CameraTrack {
+    pub lookfrom: Keyframes<Vector3>,
+    pub lookat: Keyframes<Vector3>,
+    // Vertical field of view, in degrees
+    pub vfov: Keyframes<f32>,
+    pub aperture: Keyframes<f32>,
+}
+
+impl CameraTrack {
+    // Standing still
+    pub fn new(lookfrom: Vector3, lookat: Vector3, vfov: f32, aperture: f32) -> CameraTrack {
+        CameraTrack {
+            lookfrom: Keyframes::constant(lookfrom),
+            lookat: Keyframes::constant(lookat),
+            vfov: Keyframes::constant(vfov),
+            aperture: Keyframes::constant(aperture),
+        }
+    }
+}
+
+// Right, up and backwards for a camera at lookfrom facing lookat
+fn basis(lookfrom: Vector3, lookat: Vector3, vup: Vector3) -> (Vector3, Vector3, Vector3) {
+    let w = (lookfrom - lookat).get_unit_vector();
+    let u = (vup.cross(&w)).get_unit_vector();
+    let v = w.cross(&u);
+    (u, v, w)
+}
+
+// A perspective thin lens camera
+#[derive(Clone)]
 pub struct Camera {
     origin: Vector3,
-    lower_left_corner: Vector3,
-    horizontal: Vector3,
-    vertical: Vector3,
     u: Vector3,
     v: Vector3,
+    w: Vector3,
+    half_width: f32,
+    half_height: f32,
+    focus_dist: f32,
     lens_radius: f32,
+    aperture: Aperture,
+    // How far the lens barrel's opening is offset from the aperture at the
+    // corners of the image, in lens radii. Rays outside both are blocked,
+    // giving cat's eye shaped bokeh and darker corners.
+    cat_eye: f32,
 }
 
 impl Camera {
@@ -30,30 +217,348 @@ impl Camera {
         let half_width = aspect * half_height;
 
         let origin = lookfrom;
-        let w = (lookfrom - lookat).get_unit_vector();
-        let u = (vup.cross(&w)).get_unit_vector();
-        let v = w.cross(&u);
+        let (u, v, w) = basis(lookfrom, lookat, vup);
 
         Camera {
             origin,
-            lower_left_corner: origin
-                - half_width * focus_dist * u
-                - half_height * focus_dist * v
-                - focus_dist * w,
-            horizontal: 2.0 * half_width * focus_dist * u,
-            vertical: 2.0 * half_height * focus_dist * v,
             u,
             v,
+            w,
+            half_width,
+            half_height,
+            focus_dist,
             lens_radius,
+            aperture: Aperture::Circle,
+            cat_eye: 0.0,
+        }
+    }
+
+    pub fn with_aperture(mut self, aperture: Aperture) -> Camera {
+        self.aperture = aperture;
+        self
+    }
+
+    pub fn with_cat_eye(mut self, cat_eye: f32) -> Camera {
+        self.cat_eye = cat_eye;
+        self
+    }
+
+    // Focuses on the first surface seen through (s, t) at the time, if
+    // there is one
+    pub fn autofocus<T: Hitable>(mut self, scene: &T, s: f32, t: f32, time: f32) -> Camera {
+        let r = Ray::new(self.origin, self.direction_to(s, t, 1.0)).with_time(time);
+        if let Some(hr) = scene.hit(&r, 0.001, f32::MAX) {
+            // Distance to the plane of focus, not along the ray
+            self.focus_dist = (hr.p - self.origin).dot(&-self.w);
+        }
+        self
+    }
+
+    // Light paths can only be joined to a round lens that the barrel
+    // doesn't cut into
+    fn joinable(&self) -> bool {
+        matches!(self.aperture, Aperture::Circle) && self.cat_eye == 0.0
+    }
+
+    // 1 for a pinhole, to match its importance
+    fn lens_area(&self) -> f32 {
+        if self.lens_radius > 0.0 {
+            f32::consts::PI * self.lens_radius * self.lens_radius
+        } else {
+            1.0
+        }
+    }
+
+    // Where on the image the ray from `lens` along unit `direction` is
+    // seen, if it is
+    fn image_point(&self, lens: &Vector3, direction: &Vector3) -> Option<(f32, f32)> {
+        let cos_theta = direction.dot(&-self.w);
+        if cos_theta <= 0.0 {
+            return None;
+        }
+        let focus = *lens + *direction * (self.focus_dist / cos_theta) - self.origin;
+        let s = (focus.dot(&self.u) / (self.focus_dist * self.half_width) + 1.0) / 2.0;
+        let t = (focus.dot(&self.v) / (self.focus_dist * self.half_height) + 1.0) / 2.0;
+        if (0.0..=1.0).contains(&s) && (0.0..=1.0).contains(&t) {
+            Some((s, t))
+        } else {
+            None
+        }
+    }
+
+    // As in PBRT, spread evenly over the image, for a ray leaving the lens
+    // at cos_theta to the view direction
+    fn importance(&self, cos_theta: f32) -> f32 {
+        let image_area = 4.0 * self.half_width * self.half_height;
+        1.0 / (image_area * self.lens_area() * cos_theta.powi(4))
+    }
+
+    // From the centre of the lens to (s, t) on the plane of focus at
+    // focus_dist
+    fn direction_to(&self, s: f32, t: f32, focus_dist: f32) -> Vector3 {
+        focus_dist
+            * ((2.0 * s - 1.0) * self.half_width * self.u
+                + (2.0 * t - 1.0) * self.half_height * self.v
+                - self.w)
+    }
+}
+
+impl CameraModel for Camera {
+    fn depth(&self, _r: &Ray, p: &Vector3) -> f32 {
+        (*p - self.origin).dot(&-self.w)
+    }
+
+    fn sample_importance(&self, p: &Vector3, sampler: &mut Sampler) -> Option<ImportanceSample> {
+        if !self.joinable() {
+            return None;
         }
+        let (x, y) = self.aperture.sample(sampler);
+        let lens = self.origin + self.lens_radius * (self.u * x + self.v * y);
+        let to_p = *p - lens;
+        let distance = to_p.length();
+        let direction = to_p / distance;
+        let (s, t) = self.image_point(&lens, &direction)?;
+        let cos_theta = direction.dot(&-self.w);
+        Some(ImportanceSample {
+            lens,
+            s,
+            t,
+            importance: self.importance(cos_theta),
+            pdf: distance * distance / (cos_theta * self.lens_area()),
+        })
     }
 
-    pub fn get_ray(&self, s: f32, t: f32) -> Ray {
-        let rd = self.lens_radius * random::random_in_unit_disk();
-        let offset = self.u * rd.x() + self.v * rd.y();
-        Ray::new(
+    fn ray_pdf(&self, r: &Ray) -> Option<(f32, f32)> {
+        if !self.joinable() {
+            return None;
+        }
+        let direction = r.direction().get_unit_vector();
+        if self.image_point(&r.origin(), &direction).is_none() {
+            return Some((0.0, 0.0));
+        }
+        let cos_theta = direction.dot(&-self.w);
+        let image_area = 4.0 * self.half_width * self.half_height;
+        Some((
+            1.0 / self.lens_area(),
+            1.0 / (image_area * cos_theta.powi(3)),
+        ))
+    }
+
+    // None if the lens barrel blocks the ray
+    fn get_ray(&self, s: f32, t: f32, sampler: &mut Sampler) -> Option<Ray> {
+        let (x, y) = self.aperture.sample(sampler);
+        if self.cat_eye > 0.0 {
+            let corner =
+                (self.half_width * self.half_width + self.half_height * self.half_height).sqrt();
+            let shift_x = self.cat_eye * (2.0 * s - 1.0) * self.half_width / corner;
+            let shift_y = self.cat_eye * (2.0 * t - 1.0) * self.half_height / corner;
+            if (x - shift_x).powi(2) + (y - shift_y).powi(2) > 1.0 {
+                return None;
+            }
+        }
+
+        let offset = self.lens_radius * (self.u * x + self.v * y);
+        Some(Ray::new(
             self.origin + offset,
-            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
-        )
+            self.direction_to(s, t, self.focus_dist) - offset,
+        ))
+    }
+}
+
+// Parallel rays, for views without perspective. The image covers
+// view_height of the plane through lookfrom.
+pub struct Orthographic {
+    origin: Vector3,
+    u: Vector3,
+    v: Vector3,
+    w: Vector3,
+    half_width: f32,
+    half_height: f32,
+}
+
+impl Orthographic {
+    pub fn new(
+        lookfrom: Vector3,
+        lookat: Vector3,
+        vup: Vector3,
+        view_height: f32,
+        aspect: f32,
+    ) -> Orthographic {
+        let (u, v, w) = basis(lookfrom, lookat, vup);
+        Orthographic {
+            origin: lookfrom,
+            u,
+            v,
+            w,
+            half_width: aspect * view_height / 2.0,
+            half_height: view_height / 2.0,
+        }
+    }
+}
+
+impl CameraModel for Orthographic {
+    fn depth(&self, _r: &Ray, p: &Vector3) -> f32 {
+        (*p - self.origin).dot(&-self.w)
+    }
+
+    fn get_ray(&self, s: f32, t: f32, _sampler: &mut Sampler) -> Option<Ray> {
+        let offset = (2.0 * s - 1.0) * self.half_width * self.u
+            + (2.0 * t - 1.0) * self.half_height * self.v;
+        Some(Ray::new(self.origin + offset, -self.w))
+    }
+}
+
+// An equidistant fisheye, where the distance from the image centre is
+// proportional to the angle from the view direction. The image circle
+// fills the height of the image and covers fov degrees across.
+pub struct Fisheye {
+    origin: Vector3,
+    u: Vector3,
+    v: Vector3,
+    w: Vector3,
+    half_fov: f32,
+    aspect: f32,
+}
+
+impl Fisheye {
+    pub fn new(lookfrom: Vector3, lookat: Vector3, vup: Vector3, fov: f32, aspect: f32) -> Fisheye {
+        let (u, v, w) = basis(lookfrom, lookat, vup);
+        Fisheye {
+            origin: lookfrom,
+            u,
+            v,
+            w,
+            half_fov: fov.to_radians() / 2.0,
+            aspect,
+        }
+    }
+}
+
+impl CameraModel for Fisheye {
+    // None outside the image circle
+    fn get_ray(&self, s: f32, t: f32, _sampler: &mut Sampler) -> Option<Ray> {
+        let x = (2.0 * s - 1.0) * self.aspect;
+        let y = 2.0 * t - 1.0;
+        let r = (x * x + y * y).sqrt();
+        if r > 1.0 {
+            return None;
+        }
+        let theta = r * self.half_fov;
+        let phi = y.atan2(x);
+        let direction =
+            theta.sin() * (phi.cos() * self.u + phi.sin() * self.v) - theta.cos() * self.w;
+        Some(Ray::new(self.origin, direction))
+    }
+}
+
+// A 360 degree panorama, with longitude across the image and latitude up
+// it. The view direction is at the centre.
+pub struct Equirectangular {
+    origin: Vector3,
+    u: Vector3,
+    v: Vector3,
+    w: Vector3,
+    // For omni-directional stereo, the distance between the eyes. The left
+    // eye's panorama is on the top half of the image and the right eye's on
+    // the bottom.
+    eye_separation: Option<f32>,
+}
+
+impl Equirectangular {
+    pub fn new(lookfrom: Vector3, lookat: Vector3, vup: Vector3) -> Equirectangular {
+        let (u, v, w) = basis(lookfrom, lookat, vup);
+        Equirectangular {
+            origin: lookfrom,
+            u,
+            v,
+            w,
+            eye_separation: None,
+        }
+    }
+
+    pub fn with_stereo(mut self, eye_separation: f32) -> Equirectangular {
+        self.eye_separation = Some(eye_separation);
+        self
+    }
+}
+
+impl CameraModel for Equirectangular {
+    fn get_ray(&self, s: f32, t: f32, _sampler: &mut Sampler) -> Option<Ray> {
+        let (t, eye) = match self.eye_separation {
+            None => (t, 0.0),
+            Some(separation) if t >= 0.5 => (2.0 * t - 1.0, -separation / 2.0),
+            Some(separation) => (2.0 * t, separation / 2.0),
+        };
+        let longitude = (2.0 * s - 1.0) * f32::consts::PI;
+        let latitude = (t - 0.5) * f32::consts::PI;
+        let forward = longitude.sin() * self.u - longitude.cos() * self.w;
+        let direction = latitude.cos() * forward + latitude.sin() * self.v;
+        // Each eye sits on a circle, looking along its tangent, so the
+        // parallax is right whichever way the viewer turns
+        let right = longitude.cos() * self.u + longitude.sin() * self.w;
+        Some(Ray::new(self.origin + eye * right, direction))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn polygon_samples_inside() {
+        let aperture = Aperture::Polygon {
+            blades: 5,
+            rotation: 90.0,
+        };
+        // The inscribed circle of a regular pentagon
+        let apothem = (f32::consts::PI / 5.0).cos();
+        let mut sampler = Sampler::new(0);
+        for _ in 0..1000 {
+            let (x, y) = aperture.sample(&mut sampler);
+            let r = (x * x + y * y).sqrt();
+            assert!(r <= 1.0001);
+            // The pentagon points up, so straight down is an edge
+            if x.abs() < 0.01 && y < 0.0 {
+                assert!(-y <= apothem + 0.0001);
+            }
+        }
+    }
+
+    #[test]
+    fn image_samples_inside() {
+        let white = ApertureImage::new(8, 8, vec![1.0; 64]).unwrap();
+        let aperture = Aperture::Image(Arc::new(white));
+        let mut sampler = Sampler::new(0);
+        for _ in 0..1000 {
+            let (x, y) = aperture.sample(&mut sampler);
+            assert!(x * x + y * y <= 1.0001);
+        }
+        // Nothing left once the corners are cut off
+        let mut corners = vec![0.0; 64];
+        corners[0] = 1.0;
+        assert!(ApertureImage::new(8, 8, corners).is_err());
+    }
+
+    #[test]
+    fn equirectangular_directions() {
+        let cam = Equirectangular::new(
+            Vector3::zero(),
+            Vector3::new(0.0, 0.0, -1.0),
+            Vector3::new(0.0, 1.0, 0.0),
+        );
+        let close = |a: Vector3, b: Vector3| (a - b).length() < 0.0001;
+        let mut sampler = Sampler::new(0);
+        let mut ray = |s, t| cam.get_ray(s, t, &mut sampler).unwrap().direction();
+        assert!(close(ray(0.5, 0.5), Vector3::new(0.0, 0.0, -1.0)));
+        assert!(close(ray(0.75, 0.5), Vector3::new(1.0, 0.0, 0.0)));
+        assert!(close(ray(0.5, 1.0), Vector3::new(0.0, 1.0, 0.0)));
+
+        // Looking forward, the left eye is to the left
+        let stereo = cam.with_stereo(0.1);
+        let left = stereo.get_ray(0.5, 0.75, &mut sampler).unwrap();
+        let right = stereo.get_ray(0.5, 0.25, &mut sampler).unwrap();
+        assert!(close(left.origin(), Vector3::new(-0.05, 0.0, 0.0)));
+        assert!(close(right.origin(), Vector3::new(0.05, 0.0, 0.0)));
+        assert!(close(left.direction(), right.direction()));
     }
 }
@@ -0,0 +1,261 @@
+use crate::aabb::AABB;
+use crate::hitable::{HitRecord, Hitable};
+use crate::ray::Ray;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Operation {
+    Union,
+    Intersection,
+    // The first solid with the second cut out of it
+    Difference,
+}
+
+impl Operation {
+    fn inside(&self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            Operation::Union => in_a || in_b,
+            Operation::Intersection => in_a && in_b,
+            Operation::Difference => in_a && !in_b,
+        }
+    }
+}
+
+// Two solids combined by a boolean operation, e.g. a hole drilled through
+// a part. Either can be another combination. Both need an inside, so must
+// be solids such as spheres, boxes or closed meshes.
+#[derive(Clone)]
+pub struct Csg {
+    operation: Operation,
+    a: Box<dyn Hitable + Send>,
+    b: Box<dyn Hitable + Send>,
+}
+
+impl Csg {
+    pub fn new<A, B>(operation: Operation, a: A, b: B) -> Csg
+    where
+        A: Hitable + 'static + Send,
+        B: Hitable + 'static + Send,
+    {
+        Csg {
+            operation,
+            a: Box::new(a),
+            b: Box::new(b),
+        }
+    }
+
+    pub fn union<A, B>(a: A, b: B) -> Csg
+    where
+        A: Hitable + 'static + Send,
+        B: Hitable + 'static + Send,
+    {
+        Csg::new(Operation::Union, a, b)
+    }
+
+    pub fn intersection<A, B>(a: A, b: B) -> Csg
+    where
+        A: Hitable + 'static + Send,
+        B: Hitable + 'static + Send,
+    {
+        Csg::new(Operation::Intersection, a, b)
+    }
+
+    pub fn difference<A, B>(a: A, b: B) -> Csg
+    where
+        A: Hitable + 'static + Send,
+        B: Hitable + 'static + Send,
+    {
+        Csg::new(Operation::Difference, a, b)
+    }
+
+    // Walks both solids' crossings in order, keeping those where the ray
+    // goes in or out of the combination
+    fn combine(&self, r: &Ray) -> Vec<HitRecord> {
+        let mut crossings: Vec<(HitRecord, bool)> = Vec::new();
+        for (object, is_a) in [(&self.a, true), (&self.b, false)] {
+            crossings.extend(
+                object
+                    .all_hits(r)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|hr| (hr, is_a)),
+            );
+        }
+        crossings.sort_by(|x, y| x.0.t.partial_cmp(&y.0.t).unwrap());
+
+        // How deep inside each solid the ray is. Counting, rather than
+        // flipping in and out, keeps overlapping parts of one solid, e.g. a
+        // mesh's triangles sharing an edge, from cancelling out.
+        let (mut depth_a, mut depth_b) = (0, 0);
+        let mut hits = Vec::new();
+        for (mut hr, is_a) in crossings {
+            let was_inside = self.operation.inside(depth_a > 0, depth_b > 0);
+            let step = if hr.normal.dot(&r.direction()) < 0.0 {
+                1
+            } else {
+                -1
+            };
+            if is_a {
+                depth_a += step;
+            } else {
+                depth_b += step;
+            }
+            if self.operation.inside(depth_a > 0, depth_b > 0) != was_inside {
+                // The cut out solid's surface faces into it, out of what's
+                // left
+                if !is_a && self.operation == Operation::Difference {
+                    hr.normal = -hr.normal;
+                }
+                hits.push(hr);
+            }
+        }
+        hits
+    }
+}
+
+impl Hitable for Csg {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.combine(r)
+            .into_iter()
+            .find(|hr| hr.t > t_min && hr.t < t_max)
+    }
+
+    fn all_hits(&self, r: &Ray) -> Option<Vec<HitRecord>> {
+        Some(self.combine(r))
+    }
+
+    fn traversal_cost(&self, r: &Ray, t_min: f32, t_max: f32) -> u32 {
+        self.a.traversal_cost(r, t_min, t_max) + self.b.traversal_cost(r, t_min, t_max)
+    }
+
+    fn is_solid(&self) -> bool {
+        self.a.is_solid() && self.b.is_solid()
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        self.a.validate()?;
+        self.b.validate()?;
+        if !self.is_solid() {
+            return Err("CSG can only combine solids".to_string());
+        }
+        Ok(())
+    }
+
+    // Never smaller than the combination, though intersections and
+    // differences can be much smaller than their boxes
+    fn bounding_box(&self) -> Option<AABB> {
+        let a = self.a.bounding_box()?;
+        match self.operation {
+            Operation::Union => Some(a.surrounding_box(&self.b.bounding_box()?)),
+            Operation::Intersection => match self.b.bounding_box() {
+                Some(b) => Some(a.overlap(&b).unwrap_or(a)),
+                None => Some(a),
+            },
+            Operation::Difference => Some(a),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cuboid::Cuboid;
+    use crate::hitable::HitableList;
+    use crate::material::Material;
+    use crate::quadric::Quadric;
+    use crate::sphere::Sphere;
+    use crate::triangle::{self, Triangle};
+    use crate::vec3::Vector3;
+
+    fn grey() -> Material {
+        Material::Lambertian {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        }
+    }
+
+    // Where along the x axis the solid's surface is, checking the normals
+    // face out
+    fn crossings(solid: &dyn Hitable) -> Vec<f32> {
+        let r = Ray::new(Vector3::new(-10.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        let hits = solid.all_hits(&r).unwrap();
+        for (i, hr) in hits.iter().enumerate() {
+            let entering = hr.normal.dot(&r.direction()) < 0.0;
+            assert_eq!(entering, i % 2 == 0);
+        }
+        hits.iter().map(|hr| hr.t - 10.0).collect()
+    }
+
+    fn close(a: &[f32], b: &[f32]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| (a - b).abs() < 0.0001)
+    }
+
+    #[test]
+    fn combines_spheres_and_boxes() {
+        let sphere = Sphere::new(Vector3::zero(), 1.0, grey());
+        let cube = Cuboid::new(
+            Vector3::new(0.5, -1.0, -1.0),
+            Vector3::new(2.5, 1.0, 1.0),
+            grey(),
+        );
+        let union = Csg::union(sphere.clone(), cube.clone());
+        assert!(close(&crossings(&union), &[-1.0, 2.5]));
+        let intersection = Csg::intersection(sphere.clone(), cube.clone());
+        assert!(close(&crossings(&intersection), &[0.5, 1.0]));
+        let difference = Csg::difference(sphere.clone(), cube.clone());
+        assert!(close(&crossings(&difference), &[-1.0, 0.5]));
+        // Nested, and cut all the way through
+        let ring = Csg::difference(cube, Sphere::new(Vector3::new(1.5, 0.0, 0.0), 0.5, grey()));
+        assert!(close(&crossings(&ring), &[0.5, 1.0, 2.0, 2.5]));
+        let both = Csg::union(sphere, ring);
+        assert!(close(&crossings(&both), &[-1.0, 1.0, 2.0, 2.5]));
+
+        // The first hit in front of the ray's origin
+        let r = Ray::new(Vector3::zero(), Vector3::new(1.0, 0.0, 0.0));
+        let hr = both.hit(&r, 0.001, f32::MAX).unwrap();
+        assert!((hr.t - 1.0).abs() < 0.0001);
+        assert!(hr.normal.x() > 0.0);
+    }
+
+    #[test]
+    fn combines_closed_meshes() {
+        // A unit cube of triangles facing out, with the ray through the
+        // diagonals its faces are split along
+        let (x, y, z) = (
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            Vector3::new(0.0, 0.0, 1.0),
+        );
+        let (near, far) = (Vector3::new(0.0, -0.5, -0.5), Vector3::new(1.0, 0.5, 0.5));
+        let mut mesh = HitableList::new();
+        for (corner, u, v) in [
+            (near, y, x),
+            (near, z, y),
+            (near, x, z),
+            (far, -x, -y),
+            (far, -y, -z),
+            (far, -z, -x),
+        ] {
+            for t in triangle::quad(corner, u, v, grey()) {
+                mesh.add(t);
+            }
+        }
+        let sphere = Sphere::new(x, 0.5, grey());
+        let difference = Csg::difference(mesh.clone(), sphere.clone());
+        assert!(difference.validate().is_ok());
+        assert!(close(&crossings(&difference), &[0.0, 0.5]));
+        let intersection = Csg::intersection(mesh.clone(), sphere.clone());
+        assert!(close(&crossings(&intersection), &[0.5, 1.0]));
+
+        // Without a face, or turned inside out, it has no inside
+        let mut open = mesh.clone();
+        open.hitables.truncate(open.hitables.len() - 2);
+        assert!(Csg::union(open, sphere.clone()).validate().is_err());
+        let mut flipped = mesh;
+        let last = flipped.hitables.len() - 1;
+        let (c, d) = (far + -z + -x, far + -x);
+        flipped.hitables[last] = Box::new(Triangle::new(far, d, c, grey()));
+        assert!(Csg::union(flipped, sphere.clone()).validate().is_err());
+        // Nor does a surface that goes on
+        let cylinder = Quadric::cylinder(Vector3::zero(), 0.5, 1.0, grey());
+        assert!(Csg::union(cylinder, sphere).validate().is_err());
+    }
+}
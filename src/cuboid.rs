@@ -0,0 +1,111 @@
+use crate::aabb::AABB;
+use crate::hitable::{HitRecord, Hitable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::Vector3;
+use std::f32;
+
+// A solid axis-aligned box
+#[derive(Clone)]
+pub struct Cuboid {
+    bbox: AABB,
+    material: Material,
+}
+
+impl Cuboid {
+    pub fn new(min: Vector3, max: Vector3, material: Material) -> Cuboid {
+        Cuboid {
+            bbox: AABB::new(min, max),
+            material,
+        }
+    }
+
+    // Where the line of r enters and leaves the box, by the slabs between
+    // each pair of faces, with the axis of the face crossed at each
+    fn crossings(&self, r: &Ray) -> Option<((f32, usize), (f32, usize))> {
+        let (mut enter, mut exit) = ((-f32::MAX, 0), (f32::MAX, 0));
+        for axis in 0..3 {
+            let inv_d = 1.0 / r.direction().component(axis);
+            let mut t0 = (self.bbox.min().component(axis) - r.origin().component(axis)) * inv_d;
+            let mut t1 = (self.bbox.max().component(axis) - r.origin().component(axis)) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            if t0 > enter.0 {
+                enter = (t0, axis);
+            }
+            if t1 < exit.0 {
+                exit = (t1, axis);
+            }
+            if exit.0 <= enter.0 {
+                return None;
+            }
+        }
+        Some((enter, exit))
+    }
+
+    // Facing against r where it enters, along r where it leaves
+    fn hit_record(&self, r: &Ray, t: f32, axis: usize, entering: bool) -> HitRecord {
+        let p = r.point_at_parameter(t);
+        let mut normal = [0.0; 3];
+        normal[axis] = if (r.direction().component(axis) < 0.0) == entering {
+            1.0
+        } else {
+            -1.0
+        };
+        // Across the face, from the box's corners
+        let (min, max) = (self.bbox.min(), self.bbox.max());
+        let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+        let across =
+            |i: usize| (p.component(i) - min.component(i)) / (max.component(i) - min.component(i));
+        HitRecord {
+            t,
+            p,
+            normal: Vector3::new(normal[0], normal[1], normal[2]),
+            u: across(a),
+            v: across(b),
+            material: self.material.clone(),
+            object_id: 0,
+        }
+    }
+}
+
+impl Hitable for Cuboid {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let ((t_enter, enter_axis), (t_exit, exit_axis)) = self.crossings(r)?;
+        if t_enter > t_min && t_enter < t_max {
+            Some(self.hit_record(r, t_enter, enter_axis, true))
+        } else if t_exit > t_min && t_exit < t_max {
+            Some(self.hit_record(r, t_exit, exit_axis, false))
+        } else {
+            None
+        }
+    }
+
+    fn all_hits(&self, r: &Ray) -> Option<Vec<HitRecord>> {
+        Some(match self.crossings(r) {
+            Some(((t_enter, enter_axis), (t_exit, exit_axis))) => vec![
+                self.hit_record(r, t_enter, enter_axis, true),
+                self.hit_record(r, t_exit, exit_axis, false),
+            ],
+            None => Vec::new(),
+        })
+    }
+
+    fn is_solid(&self) -> bool {
+        true
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let size = self.bbox.max() - self.bbox.min();
+        if size.x() > 0.0 && size.y() > 0.0 && size.z() > 0.0 {
+            Ok(())
+        } else {
+            Err(format!("Box size {} isn't positive", size))
+        }
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.bbox.clone())
+    }
+}
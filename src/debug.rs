@@ -0,0 +1,188 @@
+use crate::hitable::{HitRecord, Hitable};
+use crate::integrator::{Integrator, Radiance, Sample};
+use crate::random::Sampler;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::vec3::Vector3;
+use std::f32;
+
+// Bounding box and primitive tests at which the traversal cost heatmap is
+// fully red
+const HEAT_MAX: f32 = 200.0;
+
+// Quick, non-physical views of the scene, for finding problems with its
+// geometry or materials
+#[derive(Clone, Copy, PartialEq)]
+pub enum DebugView {
+    // White where nothing is within distance above the surface, by cosine
+    // weighted rays, darkening into creases and corners
+    AmbientOcclusion { distance: f32 },
+    // Normals mapped from [-1, 1] to [0, 1]
+    Normals,
+    // Surface coordinates, repeating every unit. Triangles without texture
+    // coordinates show their barycentric coordinates.
+    Uv,
+    // How many tests finding the first hit takes, from blue through green
+    // to red
+    TraversalCost,
+    // Every material white and lit by a uniform white background, with no
+    // lights. Anything short of white is light lost to absorption or to
+    // paths being cut short. Lights don't scatter, so show black.
+    Furnace,
+}
+
+impl DebugView {
+    // By its --integrator name
+    pub fn parse(name: &str, ao_distance: f32) -> Option<DebugView> {
+        match name {
+            "ao" => Some(DebugView::AmbientOcclusion {
+                distance: ao_distance,
+            }),
+            "normals" => Some(DebugView::Normals),
+            "uv" => Some(DebugView::Uv),
+            "cost" => Some(DebugView::TraversalCost),
+            "furnace" => Some(DebugView::Furnace),
+            _ => None,
+        }
+    }
+}
+
+// The views look through fog, at the surfaces, and report those as the
+// first hit
+impl<T: Hitable> Integrator<T> for DebugView {
+    fn sample(&self, r: &Ray, scene: &Scene<T>, sampler: &mut Sampler) -> Sample {
+        let hit = scene.objects.hit(r, 0.001, f32::MAX);
+        let color = match *self {
+            DebugView::AmbientOcclusion { distance } => {
+                ambient_occlusion(r, hit.as_ref(), scene, distance, sampler)
+            }
+            DebugView::Normals => match &hit {
+                Some(hr) => 0.5 * (hr.normal + Vector3::new(1.0, 1.0, 1.0)),
+                None => Vector3::zero(),
+            },
+            DebugView::Uv => match &hit {
+                Some(hr) => Vector3::new(hr.u - hr.u.floor(), hr.v - hr.v.floor(), 0.0),
+                None => Vector3::zero(),
+            },
+            DebugView::TraversalCost => {
+                let cost = scene.objects.traversal_cost(r, 0.001, f32::MAX);
+                heat(cost as f32 / HEAT_MAX)
+            }
+            DebugView::Furnace => furnace(r, scene, sampler),
+        };
+        Sample::new(Radiance::new(color, 0), hit)
+    }
+}
+
+// One cosine weighted ray from the first hit, white where it escapes, as
+// where nothing is hit at all
+fn ambient_occlusion<T: Hitable>(
+    r: &Ray,
+    hit: Option<&HitRecord>,
+    scene: &Scene<T>,
+    distance: f32,
+    sampler: &mut Sampler,
+) -> Vector3 {
+    let hr = match hit {
+        Some(hr) => hr,
+        None => return Vector3::new(1.0, 1.0, 1.0),
+    };
+    // Facing back along r
+    let normal = if hr.normal.dot(&r.direction()) > 0.0 {
+        -hr.normal
+    } else {
+        hr.normal
+    };
+    let direction = (normal + sampler.unit_vector()).get_unit_vector();
+    let occlusion_ray = r.spawn(hr.p, direction);
+    match scene.objects.hit(&occlusion_ray, 0.001, distance) {
+        Some(_) => Vector3::zero(),
+        None => Vector3::new(1.0, 1.0, 1.0),
+    }
+}
+
+// Blue at 0, green at 0.5 and red from 1
+fn heat(t: f32) -> Vector3 {
+    let t = t.clamp(0.0, 1.0);
+    if t < 0.5 {
+        Vector3::new(0.0, 2.0 * t, 1.0 - 2.0 * t)
+    } else {
+        Vector3::new(2.0 * t - 1.0, 2.0 - 2.0 * t, 0.0)
+    }
+}
+
+// The fraction of paths from r that make it out to the background, within
+// the path limits
+fn furnace<T: Hitable>(r: &Ray, scene: &Scene<T>, sampler: &mut Sampler) -> Vector3 {
+    let mut ray = r.clone();
+    let mut bounces = [0; 4];
+    loop {
+        let hr = match scene.hit(&ray, sampler) {
+            Some(hr) => hr,
+            None => return Vector3::new(1.0, 1.0, 1.0),
+        };
+        let s = match hr.material.scatter(&ray, &hr, sampler) {
+            Some(s) => s,
+            None => return Vector3::zero(),
+        };
+        let bounce = hr.material.bounce();
+        if bounces[bounce as usize] >= scene.settings.limits.max(bounce) {
+            return Vector3::zero();
+        }
+        bounces[bounce as usize] += 1;
+        ray = s.ray;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::background::Background;
+    use crate::hitable::HitableList;
+    use crate::material::Material;
+    use crate::scene::PathLimits;
+    use crate::triangle;
+
+    #[test]
+    fn views_of_an_open_floor() {
+        let mut floor = HitableList::new();
+        for t in triangle::quad(
+            Vector3::new(-1.0, 0.0, -1.0),
+            Vector3::new(0.0, 0.0, 2.0),
+            Vector3::new(2.0, 0.0, 0.0),
+            Material::Lambertian {
+                albedo: Vector3::new(0.2, 0.2, 0.2),
+            },
+        ) {
+            floor.add(t);
+        }
+        let mut scene = Scene::new(floor);
+        scene.background = Background::Constant(Vector3::zero());
+        scene.settings.limits = PathLimits {
+            diffuse: 4,
+            glossy: 4,
+            transmission: 4,
+            volume: 4,
+            roulette_depth: 4,
+        };
+        let down = Ray::new(Vector3::new(0.1, 1.0, 0.2), Vector3::new(0.0, -1.0, 0.0));
+        let close = |a: Vector3, b: Vector3| (a - b).length() < 0.0001;
+        let white = Vector3::new(1.0, 1.0, 1.0);
+        let mut sampler = Sampler::new(0);
+        let mut color = |view: DebugView| view.sample(&down, &scene, &mut sampler).radiance.total();
+
+        let normals = DebugView::parse("normals", 1.0).unwrap();
+        assert!(close(color(normals), Vector3::new(0.5, 1.0, 0.5)));
+        // Nothing above the floor to occlude it, or to keep light in
+        for name in ["ao", "furnace"] {
+            let view = DebugView::parse(name, 1.0).unwrap();
+            for _ in 0..10 {
+                assert!(close(color(view), white), "{}", name);
+            }
+        }
+        let cost = DebugView::parse("cost", 1.0).unwrap();
+        assert!(close(color(cost), heat(2.0 / HEAT_MAX)));
+        assert!(close(heat(0.0), Vector3::new(0.0, 0.0, 1.0)));
+        assert!(close(heat(1.5), Vector3::new(1.0, 0.0, 0.0)));
+    }
+}
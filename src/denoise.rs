@@ -0,0 +1,286 @@
+use crate::spectrum;
+use crate::vec3::Vector3;
+use std::f32;
+
+// Edge-avoiding à-trous wavelet filter, as in Dammertz et al. and SVGF.
+// Each pass blurs with a 5x5 B3 spline whose taps spread twice as far as
+// the last, so five passes cover 61 pixels across. Taps are weighted down
+// where the normal, albedo or luminance differ from the centre's, the last
+// relative to the noise expected there from the variance.
+
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+const ITERATIONS: u32 = 5;
+// How many standard deviations of luminance difference halve a weight, more
+// or less
+const SIGMA_LUMINANCE: f32 = 4.0;
+// Exponent on the cosine between normals
+const SIGMA_NORMAL: f32 = 128.0;
+// Squared distance between albedos that weights a tap down by 1/e
+const SIGMA_ALBEDO: f32 = 0.02;
+// Albedo is divided out before filtering, so the texture stays sharp. This
+// keeps black surfaces from blowing up.
+const MIN_ALBEDO: f32 = 0.01;
+
+// Denoises an image with rows from the top, given the variance of each
+// pixel's luminance estimate and the average albedo and normal seen through
+// it. Pixels where nothing was hit should have zero normals.
+pub fn denoise(
+    color: &[Vector3],
+    variance: &[f32],
+    albedo: &[Vector3],
+    normal: &[Vector3],
+    width: u32,
+    height: u32,
+) -> Vec<Vector3> {
+    let (width, height) = (width as usize, height as usize);
+    let albedo: Vec<Vector3> = albedo
+        .iter()
+        .map(|a| {
+            Vector3::new(
+                a.x().max(MIN_ALBEDO),
+                a.y().max(MIN_ALBEDO),
+                a.z().max(MIN_ALBEDO),
+            )
+        })
+        .collect();
+
+    // Averaged over a pixel, normals can be shorter than 1, which would
+    // weight down even the centre tap
+    let normal: Vec<Vector3> = normal
+        .iter()
+        .map(|n| {
+            if n.length_squared() > 0.0 {
+                n.get_unit_vector()
+            } else {
+                *n
+            }
+        })
+        .collect();
+
+    // The light arriving at each surface, with its variance
+    let mut irradiance: Vec<Vector3> = color
+        .iter()
+        .zip(albedo.iter())
+        .map(|(c, a)| Vector3::new(c.x() / a.x(), c.y() / a.y(), c.z() / a.z()))
+        .collect();
+    let mut variance: Vec<f32> = variance
+        .iter()
+        .zip(albedo.iter())
+        .map(|(v, a)| v / spectrum::luminance(a).powi(2))
+        .collect();
+
+    for iteration in 0..ITERATIONS {
+        let step = 1 << iteration;
+        let smoothed = blur_variance(&variance, width, height);
+        let mut next_irradiance = Vec::with_capacity(irradiance.len());
+        let mut next_variance = Vec::with_capacity(variance.len());
+        for y in 0..height {
+            for x in 0..width {
+                let p = x + y * width;
+                let luminance_p = spectrum::luminance(&irradiance[p]);
+                let sigma_l = SIGMA_LUMINANCE * smoothed[p].sqrt() + 0.0001;
+
+                let mut sum_weight = 0.0;
+                let mut sum_irradiance = Vector3::zero();
+                let mut sum_variance = 0.0;
+                for (dy, ky) in KERNEL.iter().enumerate() {
+                    let qy = y as isize + (dy as isize - 2) * step;
+                    if qy < 0 || qy >= height as isize {
+                        continue;
+                    }
+                    for (dx, kx) in KERNEL.iter().enumerate() {
+                        let qx = x as isize + (dx as isize - 2) * step;
+                        if qx < 0 || qx >= width as isize {
+                            continue;
+                        }
+                        let q = qx as usize + qy as usize * width;
+
+                        let luminance_q = spectrum::luminance(&irradiance[q]);
+                        let w_l = (-(luminance_p - luminance_q).abs() / sigma_l).exp();
+                        let w_n = normal_weight(&normal[p], &normal[q]);
+                        let w_a = (-(albedo[p] - albedo[q]).length_squared() / SIGMA_ALBEDO).exp();
+                        let w = kx * ky * w_l * w_n * w_a;
+
+                        sum_weight += w;
+                        sum_irradiance = sum_irradiance + w * irradiance[q];
+                        sum_variance += w * w * variance[q];
+                    }
+                }
+                // The centre always counts, so sum_weight > 0
+                next_irradiance.push(sum_irradiance / sum_weight);
+                next_variance.push(sum_variance / (sum_weight * sum_weight));
+            }
+        }
+        irradiance = next_irradiance;
+        variance = next_variance;
+    }
+
+    irradiance
+        .iter()
+        .zip(albedo.iter())
+        .map(|(e, a)| *e * *a)
+        .collect()
+}
+
+// Zero normals, where nothing was hit, only match each other
+fn normal_weight(n_p: &Vector3, n_q: &Vector3) -> f32 {
+    match (n_p.length_squared() > 0.0, n_q.length_squared() > 0.0) {
+        (false, false) => 1.0,
+        (true, true) => n_p.dot(n_q).max(0.0).powf(SIGMA_NORMAL),
+        _ => 0.0,
+    }
+}
+
+// A 3x3 Gaussian blur, to steady the variance estimates that set how much
+// luminance differences count
+fn blur_variance(variance: &[f32], width: usize, height: usize) -> Vec<f32> {
+    let kernel = [0.25, 0.5, 0.25];
+    let mut blurred = Vec::with_capacity(variance.len());
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = 0.0;
+            let mut sum_weight = 0.0;
+            for (dy, ky) in kernel.iter().enumerate() {
+                for (dx, kx) in kernel.iter().enumerate() {
+                    let qx = x as isize + dx as isize - 1;
+                    let qy = y as isize + dy as isize - 1;
+                    if qx >= 0 && qx < width as isize && qy >= 0 && qy < height as isize {
+                        sum += kx * ky * variance[qx as usize + qy as usize * width];
+                        sum_weight += kx * ky;
+                    }
+                }
+            }
+            blurred.push(sum / sum_weight);
+        }
+    }
+    blurred
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::BVHNode;
+    use crate::camera::{Camera, CameraModel};
+    use crate::emission::Emission;
+    use crate::hitable::HitableList;
+    use crate::integrator::{Integrator, PathTracer};
+    use crate::light::MeshLight;
+    use crate::material::Material;
+    use crate::random::Sampler;
+    use crate::scene::Scene;
+    use crate::sphere::Sphere;
+    use crate::triangle;
+
+    const WIDTH: u32 = 32;
+    const HEIGHT: u32 = 24;
+
+    // A red ball on a grey floor, under a panel light, with the background
+    // behind
+    fn scene() -> Scene<BVHNode> {
+        let mut objects = HitableList::new();
+        let grey = Material::Lambertian {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        };
+        for t in triangle::quad(
+            Vector3::new(-2.0, 0.0, -2.0),
+            Vector3::new(0.0, 0.0, 4.0),
+            Vector3::new(4.0, 0.0, 0.0),
+            grey.clone(),
+        ) {
+            objects.add(t);
+        }
+        let red = Material::Lambertian {
+            albedo: Vector3::new(0.8, 0.3, 0.3),
+        };
+        objects.add(Sphere::new(Vector3::new(0.0, 0.5, 0.0), 0.5, red));
+        let mut scene = Scene::new(objects);
+        scene.add_area_light(MeshLight::new(
+            triangle::quad(
+                Vector3::new(-1.5, 2.0, -0.5),
+                Vector3::new(1.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.0, 1.0),
+                grey,
+            ),
+            Emission::new(Vector3::new(4.0, 4.0, 4.0)),
+            None,
+        ));
+        scene.build().unwrap()
+    }
+
+    // Renders the scene with the path tracer, giving each pixel's mean,
+    // the variance of its luminance, and its average albedo and normal
+    type Passes = (Vec<Vector3>, Vec<f32>, Vec<Vector3>, Vec<Vector3>);
+    fn render(scene: &Scene<BVHNode>, samples: u32, seed: u64) -> Passes {
+        let cam = Camera::new(
+            Vector3::new(0.0, 1.5, 3.5),
+            Vector3::new(0.0, 0.3, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            50.0,
+            WIDTH as f32 / HEIGHT as f32,
+            0.0,
+            3.5,
+        );
+        let mut sampler = Sampler::new(seed);
+        let mut passes: Passes = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let mut sum = [Vector3::zero(); 3];
+                let mut sum_squares = 0.0;
+                for _ in 0..samples {
+                    let u = (x as f32 + sampler.uniform()) / WIDTH as f32;
+                    let v = ((HEIGHT - 1 - y) as f32 + sampler.uniform()) / HEIGHT as f32;
+                    let r = cam.get_ray(u, v, &mut sampler).unwrap();
+                    let sample = PathTracer.sample(&r, scene, &mut sampler);
+                    let radiance = sample.radiance.total();
+                    sum[0] = sum[0] + radiance;
+                    sum_squares += spectrum::luminance(&radiance).powi(2);
+                    if let Some(hr) = sample.first_hit {
+                        sum[1] = sum[1] + hr.material.albedo();
+                        sum[2] = sum[2] + hr.normal;
+                    }
+                }
+                let n = samples as f32;
+                let mean = sum[0] / n;
+                let variance = (sum_squares / n - spectrum::luminance(&mean).powi(2)).max(0.0);
+                passes.0.push(mean);
+                passes.1.push(variance / n);
+                passes.2.push(sum[1] / n);
+                passes.3.push(sum[2] / n);
+            }
+        }
+        passes
+    }
+
+    fn mean_squared_error(a: &[Vector3], b: &[Vector3]) -> f32 {
+        let sum: f32 = a.iter().zip(b).map(|(a, b)| (*a - *b).length_squared()).sum();
+        sum / a.len() as f32
+    }
+
+    #[test]
+    fn closer_to_reference_than_noisy() {
+        let scene = scene();
+        let reference = render(&scene, 1024, 0).0;
+        let (noisy, variance, albedo, normal) = render(&scene, 4, 1);
+
+        let denoised = denoise(&noisy, &variance, &albedo, &normal, WIDTH, HEIGHT);
+        let noisy_error = mean_squared_error(&noisy, &reference);
+        let denoised_error = mean_squared_error(&denoised, &reference);
+        assert!(
+            denoised_error < 0.3 * noisy_error,
+            "denoised {} noisy {}",
+            denoised_error,
+            noisy_error
+        );
+
+        // A clean image stays as it was
+        let clean = denoise(
+            &reference,
+            &vec![0.0; reference.len()],
+            &albedo,
+            &normal,
+            WIDTH,
+            HEIGHT,
+        );
+        assert!(mean_squared_error(&clean, &reference) < 0.001);
+    }
+}
@@ -0,0 +1,144 @@
+// Piecewise constant distributions for importance sampling tabulated
+// functions, as in PBRT
+
+#[derive(Clone)]
+pub struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    func_int: f32,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f32>) -> Distribution1D {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 1..=n {
+            cdf[i] = cdf[i - 1] + func[i - 1].abs() / n as f32;
+        }
+        let func_int = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate().skip(1) {
+            // Fall back to uniform if the function is zero everywhere
+            *c = if func_int == 0.0 {
+                i as f32 / n as f32
+            } else {
+                *c / func_int
+            };
+        }
+        Distribution1D {
+            func,
+            cdf,
+            func_int,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.func_int
+    }
+
+    // Index of the segment containing u
+    fn find_segment(&self, u: f32) -> usize {
+        // Last cdf entry that is <= u
+        let i = self.cdf.partition_point(|c| *c <= u);
+        i.saturating_sub(1).min(self.count() - 1)
+    }
+
+    // Returns a value in [0, 1), its pdf and the segment it fell in
+    pub fn sample_continuous(&self, u: f32) -> (f32, f32, usize) {
+        let offset = self.find_segment(u);
+        let mut du = u - self.cdf[offset];
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        if width > 0.0 {
+            du /= width;
+        }
+        let pdf = if self.func_int > 0.0 {
+            self.func[offset] / self.func_int
+        } else {
+            1.0
+        };
+        ((offset as f32 + du) / self.count() as f32, pdf, offset)
+    }
+
+    // Picks a segment with probability proportional to its value, returning
+    // it with that probability
+    pub fn sample_discrete(&self, u: f32) -> (usize, f32) {
+        let offset = self.find_segment(u);
+        (offset, self.cdf[offset + 1] - self.cdf[offset])
+    }
+}
+
+// Samples (u, v) in [0, 1)^2 from a function tabulated on a grid, with v
+// picking the row
+#[derive(Clone)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    // func holds nv rows of nu values
+    pub fn new(func: &[f32], nu: usize, nv: usize) -> Distribution2D {
+        let conditional: Vec<Distribution1D> = func
+            .chunks(nu)
+            .take(nv)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|d| d.integral()).collect());
+        Distribution2D {
+            conditional,
+            marginal,
+        }
+    }
+
+    pub fn sample_continuous(&self, u0: f32, u1: f32) -> (f32, f32, f32) {
+        let (v, pdf_v, row) = self.marginal.sample_continuous(u1);
+        let (u, pdf_u, _) = self.conditional[row].sample_continuous(u0);
+        (u, v, pdf_u * pdf_v)
+    }
+
+    pub fn pdf(&self, u: f32, v: f32) -> f32 {
+        let nu = self.conditional[0].count();
+        let nv = self.marginal.count();
+        let iu = ((u * nu as f32) as usize).min(nu - 1);
+        let iv = ((v * nv as f32) as usize).min(nv - 1);
+        if self.marginal.integral() > 0.0 {
+            self.conditional[iv].func[iu] / self.marginal.integral()
+        } else {
+            1.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_proportional_to_function() {
+        let d = Distribution1D::new(vec![1.0, 3.0, 0.0, 4.0]);
+        assert_eq!(d.integral(), 2.0);
+
+        // Segments get cdf widths 1/8, 3/8, 0 and 4/8
+        let (x, pdf, offset) = d.sample_continuous(0.3);
+        assert_eq!(offset, 1);
+        assert!((pdf - 1.5).abs() < 0.0001);
+        assert!((x - (1.0 + (0.3 - 0.125) / 0.375) / 4.0).abs() < 0.0001);
+
+        assert_eq!(d.sample_discrete(0.3), (1, 0.375));
+        assert_eq!(d.sample_discrete(0.7).0, 3);
+    }
+
+    #[test]
+    fn pdf_matches_sample() {
+        let func = vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0];
+        let d = Distribution2D::new(&func, 3, 2);
+        for i in 0..10 {
+            let (u, v, pdf) = d.sample_continuous(i as f32 / 10.0 + 0.05, 0.37);
+            assert!(pdf > 0.0);
+            assert!((d.pdf(u, v) - pdf).abs() < 0.0001);
+        }
+    }
+}
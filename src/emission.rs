@@ -0,0 +1,269 @@
+use crate::spectrum;
+use crate::texture::Texture;
+use crate::vec3::Vector3;
+use std::f32;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Luminous efficacy at 555nm, where the eye is most sensitive
+const LUMENS_PER_WATT: f32 = 683.0;
+
+// Total power leaving an emitter
+#[derive(Clone, Copy)]
+pub enum Power {
+    // Averaged over the r, g and b channels
+    Watts(f32),
+    // Weighted by the eye's sensitivity
+    Lumens(f32),
+}
+
+// How strongly a light shines at each angle from its normal, e.g. from a
+// manufacturer's IES file. Scales the radiance of an otherwise diffuse
+// emitter.
+#[derive(Clone)]
+pub struct AngularProfile {
+    // Ascending, in radians, starting at 0
+    angles: Vec<f32>,
+    values: Vec<f32>,
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+impl AngularProfile {
+    // Angles are in degrees. Values are normalized so the brightest is 1.
+    pub fn new(angles: Vec<f32>, values: Vec<f32>) -> AngularProfile {
+        let max = values.iter().cloned().fold(0.0, f32::max);
+        AngularProfile {
+            angles: angles.iter().map(|a| a.to_radians()).collect(),
+            values: values
+                .iter()
+                .map(|v| if max > 0.0 { v / max } else { 0.0 })
+                .collect(),
+        }
+    }
+
+    pub fn load_ies(path: &Path) -> io::Result<AngularProfile> {
+        AngularProfile::parse_ies(&fs::read_to_string(path)?)
+    }
+
+    // Reads the candela table of an IESNA LM-63 file, averaged over the
+    // horizontal angles. Vertical angle 0 is along the emitter's normal.
+    fn parse_ies(text: &str) -> io::Result<AngularProfile> {
+        let mut lines = text.lines();
+        let tilt = lines
+            .by_ref()
+            .find(|line| line.trim_start().starts_with("TILT="))
+            .ok_or_else(|| invalid_data("missing TILT line"))?;
+        let mut numbers = Vec::new();
+        for line in lines {
+            for token in line.split(|c: char| c.is_whitespace() || c == ',') {
+                if !token.is_empty() {
+                    numbers.push(
+                        token
+                            .parse::<f32>()
+                            .map_err(|_| invalid_data("expected a number"))?,
+                    );
+                }
+            }
+        }
+
+        // TILT=INCLUDE puts the lamp's tilt table before the photometry:
+        // its geometry, a count, then that many angles and factors
+        let mut i = 0;
+        if tilt.trim() == "TILT=INCLUDE" {
+            let count = *numbers.get(1).ok_or_else(|| invalid_data("truncated"))? as usize;
+            i = 2 + 2 * count;
+        } else if tilt.trim() != "TILT=NONE" {
+            return Err(invalid_data("tilt files aren't supported"));
+        }
+
+        let header = numbers
+            .get(i..i + 13)
+            .ok_or_else(|| invalid_data("truncated header"))?;
+        let n_vertical = header[3] as usize;
+        let n_horizontal = header[4] as usize;
+        i += 13;
+        let vertical = numbers
+            .get(i..i + n_vertical)
+            .ok_or_else(|| invalid_data("truncated angles"))?
+            .to_vec();
+        i += n_vertical + n_horizontal;
+        let candelas = numbers
+            .get(i..i + n_vertical * n_horizontal)
+            .ok_or_else(|| invalid_data("truncated candela values"))?;
+
+        // Stored one horizontal angle at a time
+        let values = (0..n_vertical)
+            .map(|v| {
+                (0..n_horizontal)
+                    .map(|h| candelas[h * n_vertical + v])
+                    .sum::<f32>()
+                    / n_horizontal as f32
+            })
+            .collect();
+        Ok(AngularProfile::new(vertical, values))
+    }
+
+    // Linearly interpolated, and 0 past the last angle
+    pub fn value(&self, cos_theta: f32) -> f32 {
+        let theta = cos_theta.clamp(-1.0, 1.0).acos();
+        let i = self.angles.partition_point(|a| *a <= theta);
+        if i == 0 {
+            return self.values.first().cloned().unwrap_or(0.0);
+        }
+        if i == self.angles.len() {
+            return if theta <= self.angles[i - 1] {
+                self.values[i - 1]
+            } else {
+                0.0
+            };
+        }
+        let t = (theta - self.angles[i - 1]) / (self.angles[i] - self.angles[i - 1]);
+        (1.0 - t) * self.values[i - 1] + t * self.values[i]
+    }
+
+    // Integral of value() * cos(theta) over the hemisphere, which is pi for
+    // a diffuse emitter
+    fn cosine_integral(&self) -> f32 {
+        let steps = 256;
+        let d_theta = f32::consts::FRAC_PI_2 / steps as f32;
+        let sum: f32 = (0..steps)
+            .map(|i| {
+                let theta = (i as f32 + 0.5) * d_theta;
+                self.value(theta.cos()) * theta.cos() * theta.sin()
+            })
+            .sum();
+        2.0 * f32::consts::PI * sum * d_theta
+    }
+}
+
+// Light given off by a surface
+#[derive(Clone)]
+pub struct Emission {
+    pub color: Vector3,
+    // One-sided emitters only shine on the side their normal faces
+    pub two_sided: bool,
+    // Multiplies color over the surface
+    pub texture: Option<Texture>,
+    pub profile: Option<AngularProfile>,
+}
+
+impl Emission {
+    // Diffuse, from both sides
+    pub fn new(color: Vector3) -> Emission {
+        Emission {
+            color,
+            two_sided: true,
+            texture: None,
+            profile: None,
+        }
+    }
+
+    // Radiance at (u, v) leaving at cos_theta to the normal
+    pub fn radiance(&self, u: f32, v: f32, cos_theta: f32) -> Vector3 {
+        if !self.two_sided && cos_theta <= 0.0 {
+            return Vector3::zero();
+        }
+        let mut radiance = self.color;
+        if let Some(texture) = &self.texture {
+            radiance = radiance * texture.value(u, v);
+        }
+        if let Some(profile) = &self.profile {
+            radiance = radiance * profile.value(cos_theta.abs());
+        }
+        radiance
+    }
+
+    // Describes the emission for hashing into material IDs
+    pub fn id_words(&self) -> Vec<u32> {
+        let color = &self.color;
+        let mut words = vec![
+            color.x().to_bits(),
+            color.y().to_bits(),
+            color.z().to_bits(),
+            self.two_sided as u32,
+        ];
+        if let Some(texture) = &self.texture {
+            words.extend(texture.id_words());
+        }
+        if let Some(profile) = &self.profile {
+            let points = profile.angles.iter().chain(&profile.values);
+            words.extend(points.map(|a| a.to_bits()));
+        }
+        words
+    }
+
+    // Colour averaged over the texture
+    fn average_color(&self) -> Vector3 {
+        match &self.texture {
+            Some(texture) => self.color * texture.average(),
+            None => self.color,
+        }
+    }
+
+    // Power given off per unit area, for each unit of radiance
+    fn flux_per_radiance(&self) -> f32 {
+        let sides = if self.two_sided { 2.0 } else { 1.0 };
+        let cosine_integral = match &self.profile {
+            Some(profile) => profile.cosine_integral(),
+            None => f32::consts::PI,
+        };
+        sides * cosine_integral
+    }
+
+    // Luminance of the power `area` of this emitter gives off
+    pub fn luminous_power(&self, area: f32) -> f32 {
+        spectrum::luminance(&self.average_color()) * area * self.flux_per_radiance()
+    }
+
+    // Scales the colour so that `area` of this emitter gives off `power`
+    pub fn with_power(mut self, power: Power, area: f32) -> Emission {
+        let color = self.average_color();
+        let (target, per_color) = match power {
+            Power::Watts(watts) => (watts, (color.r() + color.g() + color.b()) / 3.0),
+            Power::Lumens(lumens) => (lumens / LUMENS_PER_WATT, spectrum::luminance(&color)),
+        };
+        let flux = per_color * area * self.flux_per_radiance();
+        if flux > 0.0 {
+            self.color = self.color * (target / flux);
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn power_sets_flux() {
+        // A one-sided diffuse emitter gives off pi * L * area
+        let emission = Emission {
+            two_sided: false,
+            ..Emission::new(Vector3::new(1.0, 1.0, 1.0))
+        }
+        .with_power(Power::Watts(10.0), 2.0);
+        assert!((emission.color.r() - 10.0 / (2.0 * f32::consts::PI)).abs() < 0.0001);
+        assert_eq!(emission.radiance(0.0, 0.0, -0.5).r(), 0.0);
+    }
+
+    #[test]
+    fn parse_ies_profile() {
+        let text = "IESNA:LM-63-2002\n\
+                    [TEST] made up\n\
+                    TILT=NONE\n\
+                    1 1000 1 3 2 1 1 0.1 0.1 0\n\
+                    1 1 50\n\
+                    0 45 90\n\
+                    0 90\n\
+                    200 100 0\n\
+                    100 100 0\n";
+        let profile = AngularProfile::parse_ies(text).unwrap();
+        assert_eq!(profile.value(1.0), 1.0);
+        assert!((profile.value(45f32.to_radians().cos()) - 2.0 / 3.0).abs() < 0.0001);
+        assert!(profile.value(0.0).abs() < 0.0001);
+    }
+}
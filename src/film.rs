@@ -0,0 +1,307 @@
+use crate::vec3::Vector3;
+use std::f32;
+
+// How much a sample counts towards pixels around it, by distance. Separable,
+// so a sample's weight for a pixel is the filter across times the filter
+// down. Radii are in pixels.
+#[derive(Clone, Copy)]
+pub enum Filter {
+    // Every sample in reach counts the same. With a radius of 0.5, each
+    // pixel just averages its own samples.
+    Box { radius: f32 },
+    // Falling off linearly to the edge
+    Tent { radius: f32 },
+    // Shifted down to reach zero at the radius. Larger alpha falls off
+    // faster.
+    Gaussian { radius: f32, alpha: f32 },
+    // Mitchell and Netravali's cubic. Sharper than a Gaussian, with a little
+    // ringing from its negative lobes.
+    Mitchell { radius: f32, b: f32, c: f32 },
+    // A windowed sinc with as many lobes as its radius. The sharpest, and
+    // rings the most.
+    Lanczos { radius: f32 },
+}
+
+impl Filter {
+    // By name, with the radius or else the filter's usual one
+    pub fn parse(name: &str, radius: Option<f32>) -> Option<Filter> {
+        match name {
+            "box" => Some(Filter::Box {
+                radius: radius.unwrap_or(0.5),
+            }),
+            "tent" => Some(Filter::Tent {
+                radius: radius.unwrap_or(1.0),
+            }),
+            "gaussian" => Some(Filter::Gaussian {
+                radius: radius.unwrap_or(1.5),
+                alpha: 2.0,
+            }),
+            "mitchell" => Some(Filter::Mitchell {
+                radius: radius.unwrap_or(2.0),
+                b: 1.0 / 3.0,
+                c: 1.0 / 3.0,
+            }),
+            "lanczos" => Some(Filter::Lanczos {
+                radius: radius.unwrap_or(3.0),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn radius(&self) -> f32 {
+        match self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::Lanczos { radius } => *radius,
+        }
+    }
+
+    pub fn evaluate(&self, dx: f32, dy: f32) -> f32 {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let x = x.abs();
+        let radius = self.radius();
+        if x > radius {
+            return 0.0;
+        }
+        match *self {
+            Filter::Box { .. } => 1.0,
+            Filter::Tent { .. } => radius - x,
+            Filter::Gaussian { alpha, .. } => {
+                ((-alpha * x * x).exp() - (-alpha * radius * radius).exp()).max(0.0)
+            }
+            Filter::Mitchell { b, c, .. } => {
+                // The cubic spans [-2, 2]
+                let x = 2.0 * x / radius;
+                if x < 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x.powi(3)
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                        + (6.0 - 2.0 * b))
+                        / 6.0
+                } else {
+                    ((-b - 6.0 * c) * x.powi(3)
+                        + (6.0 * b + 30.0 * c) * x * x
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c))
+                        / 6.0
+                }
+            }
+            Filter::Lanczos { radius } => sinc(x) * sinc(x / radius),
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x < 0.00001 {
+        1.0
+    } else {
+        let x = f32::consts::PI * x;
+        x.sin() / x
+    }
+}
+
+// Collects samples into pixels, for an image with rows from the top. Each
+// sample has a value for every layer, e.g. the beauty pass and AOVs, and is
+// splatted into every pixel its filter reaches, weighted by distance from
+// the pixel's centre. Layers of IDs and the like, which can't be blended,
+// keep the sample nearest each pixel's centre instead.
+#[derive(Clone)]
+pub struct Film {
+    width: u32,
+    height: u32,
+    filter: Filter,
+    nearest: Vec<bool>,
+    // Weighted sums for each layer, then each pixel
+    sums: Vec<Vec<Vector3>>,
+    weights: Vec<f32>,
+    // How far the sample kept for the nearest layers was from the centre
+    nearest_distance: Vec<f32>,
+    // Light carried to the camera by paths traced from lights
+    splats: Vec<Vector3>,
+}
+
+impl Film {
+    // nearest says for each layer whether to keep the nearest sample
+    pub fn new(width: u32, height: u32, filter: Filter, nearest: Vec<bool>) -> Film {
+        let pixels = (width * height) as usize;
+        Film {
+            width,
+            height,
+            filter,
+            sums: vec![vec![Vector3::zero(); pixels]; nearest.len()],
+            nearest,
+            weights: vec![0.0; pixels],
+            nearest_distance: vec![f32::INFINITY; pixels],
+            splats: vec![Vector3::zero(); pixels],
+        }
+    }
+
+    // Adds a sample at (x, y) in pixels, from the top left, so pixel (i, j)
+    // covers [i, i + 1) x [j, j + 1)
+    pub fn add_sample(&mut self, x: f32, y: f32, values: &[Vector3]) {
+        // Pixel centres are at half integers
+        let (x, y) = (x - 0.5, y - 0.5);
+        let radius = self.filter.radius();
+        let x0 = (x - radius).ceil().max(0.0) as u32;
+        let x1 = ((x + radius).floor() as i64).min(self.width as i64 - 1);
+        let y0 = (y - radius).ceil().max(0.0) as u32;
+        let y1 = ((y + radius).floor() as i64).min(self.height as i64 - 1);
+        for j in y0 as i64..=y1 {
+            for i in x0 as i64..=x1 {
+                let weight = self.filter.evaluate(i as f32 - x, j as f32 - y);
+                if weight == 0.0 {
+                    continue;
+                }
+                let pixel = (i + j * self.width as i64) as usize;
+                self.weights[pixel] += weight;
+                for ((layer, nearest), value) in self.sums.iter_mut().zip(&self.nearest).zip(values)
+                {
+                    if !nearest {
+                        layer[pixel] = layer[pixel] + weight * *value;
+                    }
+                }
+            }
+        }
+
+        // Only for the pixel the sample is in
+        let (i, j) = ((x + 0.5).floor(), (y + 0.5).floor());
+        if i < 0.0 || j < 0.0 || i >= self.width as f32 || j >= self.height as f32 {
+            return;
+        }
+        let pixel = (i as u32 + j as u32 * self.width) as usize;
+        let distance = (x - i).powi(2) + (y - j).powi(2);
+        if distance < self.nearest_distance[pixel] {
+            self.nearest_distance[pixel] = distance;
+            for ((layer, nearest), value) in self.sums.iter_mut().zip(&self.nearest).zip(values) {
+                if *nearest {
+                    layer[pixel] = *value;
+                }
+            }
+        }
+    }
+
+    // Adds light that reached the camera from a path traced from a light,
+    // at (x, y) as for add_sample(). Unlike samples, splats aren't averaged
+    // but added to the first layer as they are, so should already be
+    // divided by the number of samples per pixel.
+    pub fn add_splat(&mut self, x: f32, y: f32, value: Vector3) {
+        let (i, j) = (x.floor(), y.floor());
+        if i < 0.0 || j < 0.0 || i >= self.width as f32 || j >= self.height as f32 {
+            return;
+        }
+        let pixel = (i as u32 + j as u32 * self.width) as usize;
+        self.splats[pixel] = self.splats[pixel] + value;
+    }
+
+    // Adds in another film's samples, e.g. one from another thread
+    pub fn merge(&mut self, other: &Film) {
+        for pixel in 0..self.weights.len() {
+            self.weights[pixel] += other.weights[pixel];
+            self.splats[pixel] = self.splats[pixel] + other.splats[pixel];
+        }
+        for (layer, nearest) in self.nearest.iter().enumerate() {
+            if *nearest {
+                continue;
+            }
+            for pixel in 0..self.weights.len() {
+                self.sums[layer][pixel] = self.sums[layer][pixel] + other.sums[layer][pixel];
+            }
+        }
+        for pixel in 0..self.weights.len() {
+            if other.nearest_distance[pixel] < self.nearest_distance[pixel] {
+                self.nearest_distance[pixel] = other.nearest_distance[pixel];
+                for (layer, nearest) in self.nearest.iter().enumerate() {
+                    if *nearest {
+                        self.sums[layer][pixel] = other.sums[layer][pixel];
+                    }
+                }
+            }
+        }
+    }
+
+    // The finished pixels of each layer
+    pub fn layers(&self) -> Vec<Vec<Vector3>> {
+        let mut layers: Vec<Vec<Vector3>> = self
+            .sums
+            .iter()
+            .zip(self.nearest.iter())
+            .map(|(sums, nearest)| {
+                if *nearest {
+                    return sums.clone();
+                }
+                sums.iter()
+                    .zip(self.weights.iter())
+                    .map(|(sum, weight)| {
+                        if *weight != 0.0 {
+                            *sum / *weight
+                        } else {
+                            Vector3::zero()
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        if let Some(first) = layers.first_mut() {
+            for (pixel, splat) in first.iter_mut().zip(self.splats.iter()) {
+                *pixel = *pixel + *splat;
+            }
+        }
+        layers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn box_keeps_samples_in_their_pixel() {
+        let filter = Filter::parse("box", None).unwrap();
+        let mut film = Film::new(3, 2, filter, vec![false]);
+        film.add_sample(1.2, 0.7, &[Vector3::new(1.0, 0.0, 0.0)]);
+        film.add_sample(1.9, 0.1, &[Vector3::new(0.0, 1.0, 0.0)]);
+        film.add_sample(0.5, 1.5, &[Vector3::new(0.0, 0.0, 4.0)]);
+        let pixels = &film.layers()[0];
+        let close = |a: Vector3, b: Vector3| (a - b).length() < 0.0001;
+        assert!(close(pixels[1], Vector3::new(0.5, 0.5, 0.0)));
+        assert!(close(pixels[3], Vector3::new(0.0, 0.0, 4.0)));
+        assert!(close(pixels[0], Vector3::zero()));
+    }
+
+    #[test]
+    fn wide_filters_blend_neighbours() {
+        for name in ["tent", "gaussian", "mitchell", "lanczos"] {
+            let filter = Filter::parse(name, None).unwrap();
+            assert!(filter.evaluate(0.0, 0.0) > filter.evaluate(0.5, 0.0));
+            assert_eq!(filter.evaluate(filter.radius() + 0.1, 0.0), 0.0);
+
+            // A bright sample towards one pixel's edge spills into the next
+            let mut film = Film::new(4, 1, filter, vec![false]);
+            for i in 0..4 {
+                film.add_sample(i as f32 + 0.5, 0.5, &[Vector3::zero()]);
+            }
+            film.add_sample(1.8, 0.5, &[Vector3::new(1.0, 1.0, 1.0)]);
+            let pixels = &film.layers()[0];
+            assert!(pixels[1].x() > pixels[2].x(), "{}", name);
+            assert!(pixels[2].x() > 0.0, "{}", name);
+        }
+    }
+
+    #[test]
+    fn nearest_layers_are_not_blended() {
+        let filter = Filter::parse("tent", None).unwrap();
+        let mut film = Film::new(2, 1, filter, vec![false, true]);
+        let mut other = film.clone();
+        film.add_sample(0.2, 0.5, &[Vector3::zero(), Vector3::new(3.0, 0.0, 0.0)]);
+        other.add_sample(0.6, 0.4, &[Vector3::zero(), Vector3::new(7.0, 0.0, 0.0)]);
+        other.add_sample(1.5, 0.5, &[Vector3::zero(), Vector3::new(2.0, 0.0, 0.0)]);
+        film.merge(&other);
+        let ids = &film.layers()[1];
+        assert_eq!(ids[0].x(), 7.0);
+        assert_eq!(ids[1].x(), 2.0);
+    }
+}
@@ -0,0 +1,407 @@
+use crate::aabb::AABB;
+use crate::hitable::{HitRecord, Hitable};
+use crate::material::Material;
+use crate::perlin::Perlin;
+use crate::ray::Ray;
+use crate::vec3::Vector3;
+use std::f32;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+// How far past their edges triangles and boxes still count as hit, so
+// rays can't slip through the cracks between them
+const EPSILON: f32 = 0.00001;
+
+// Heights sampled on a regular grid, from 0 at the bottom to 1 at the top.
+// Stored x fastest, then z.
+pub struct HeightMap {
+    nx: usize,
+    nz: usize,
+    heights: Vec<f32>,
+}
+
+impl HeightMap {
+    pub fn new(nx: usize, nz: usize, heights: Vec<f32>) -> HeightMap {
+        assert!(nx >= 2 && nz >= 2);
+        assert_eq!(heights.len(), nx * nz);
+        HeightMap { nx, nz, heights }
+    }
+
+    // From a greyscale image, white at the top. The image's top row is
+    // along -z, as on a map seen from above.
+    pub fn load(path: &Path) -> io::Result<HeightMap> {
+        let image = image::open(path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?
+            .into_luma16();
+        let (nx, nz) = (image.width() as usize, image.height() as usize);
+        if nx < 2 || nz < 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Height map {}x{} is too small", nx, nz),
+            ));
+        }
+        let heights = image
+            .pixels()
+            .map(|p| f32::from(p[0]) / f32::from(u16::MAX))
+            .collect();
+        Ok(HeightMap::new(nx, nz, heights))
+    }
+
+    // Rolling hills, from several octaves of noise
+    pub fn procedural(resolution: usize) -> HeightMap {
+        let noise = Perlin::new();
+        let mut heights = Vec::with_capacity(resolution * resolution);
+        for z in 0..resolution {
+            for x in 0..resolution {
+                let mut p = Vector3::new(x as f32, 0.37, z as f32) * (3.0 / resolution as f32);
+                let (mut sum, mut weight) = (0.0, 0.5);
+                for _ in 0..5 {
+                    sum += weight * noise.noise(&p);
+                    weight *= 0.5;
+                    p = p * 2.0;
+                }
+                heights.push((0.5 + sum).clamp(0.0, 1.0));
+            }
+        }
+        HeightMap::new(resolution, resolution, heights)
+    }
+}
+
+// Lowest and highest points over blocks of cells, each level's blocks
+// twice the size of the last's
+struct Level {
+    width: usize,
+    ranges: Vec<(f32, f32)>,
+}
+
+// Shared between copies of a heightfield
+struct Grid {
+    nx: usize,
+    nz: usize,
+    // Of each sample, in world space
+    positions: Vec<Vector3>,
+    normals: Vec<Vector3>,
+    // From single cells up to one block covering them all
+    levels: Vec<Level>,
+}
+
+// Where a ray crosses one of the triangles, with the barycentric
+// coordinates of its second and third corners
+struct Crossing {
+    t: f32,
+    corners: [usize; 3],
+    b1: f32,
+    b2: f32,
+}
+
+// Terrain from a height map, split into two triangles per cell. Rather
+// than putting millions of triangles in the BVH, rays find cells by going
+// down a quadtree of the height ranges over blocks of cells, nearest
+// first, skipping blocks they pass over or under.
+#[derive(Clone)]
+pub struct Heightfield {
+    grid: Arc<Grid>,
+    bbox: AABB,
+    material: Material,
+}
+
+impl Heightfield {
+    // Stretched over the box from min to min + size, with heights of 0 at
+    // its bottom and 1 at its top
+    pub fn new(map: &HeightMap, min: Vector3, size: Vector3, material: Material) -> Heightfield {
+        let (nx, nz) = (map.nx, map.nz);
+        let spacing_x = size.x() / (nx - 1) as f32;
+        let spacing_z = size.z() / (nz - 1) as f32;
+        let positions: Vec<Vector3> = map
+            .heights
+            .iter()
+            .enumerate()
+            .map(|(k, h)| {
+                let (i, j) = (k % nx, k / nx);
+                min + Vector3::new(i as f32 * spacing_x, h * size.y(), j as f32 * spacing_z)
+            })
+            .collect();
+
+        // Across the slopes between neighbours, one sided at the edges
+        let height = |i: usize, j: usize| positions[i + j * nx].y();
+        let mut normals = Vec::with_capacity(nx * nz);
+        for j in 0..nz {
+            for i in 0..nx {
+                let (left, right) = (i.saturating_sub(1), (i + 1).min(nx - 1));
+                let (back, front) = (j.saturating_sub(1), (j + 1).min(nz - 1));
+                let dx = (height(right, j) - height(left, j)) / ((right - left) as f32 * spacing_x);
+                let dz = (height(i, front) - height(i, back)) / ((front - back) as f32 * spacing_z);
+                normals.push(Vector3::new(-dx, 1.0, -dz).get_unit_vector());
+            }
+        }
+
+        let mut ranges = Vec::with_capacity((nx - 1) * (nz - 1));
+        for j in 0..nz - 1 {
+            for i in 0..nx - 1 {
+                let corners = [
+                    height(i, j),
+                    height(i + 1, j),
+                    height(i, j + 1),
+                    height(i + 1, j + 1),
+                ];
+                let low = corners.iter().cloned().fold(f32::MAX, f32::min);
+                let high = corners.iter().cloned().fold(-f32::MAX, f32::max);
+                ranges.push((low, high));
+            }
+        }
+        let mut levels = vec![Level {
+            width: nx - 1,
+            ranges,
+        }];
+        let mut depth = nz - 1;
+        while levels.last().unwrap().width > 1 || depth > 1 {
+            let below = levels.last().unwrap();
+            let (width, next_depth) = (below.width.div_ceil(2), depth.div_ceil(2));
+            let mut ranges = vec![(f32::MAX, -f32::MAX); width * next_depth];
+            for j in 0..depth {
+                for i in 0..below.width {
+                    let (low, high) = below.ranges[i + j * below.width];
+                    let range = &mut ranges[i / 2 + (j / 2) * width];
+                    *range = (range.0.min(low), range.1.max(high));
+                }
+            }
+            levels.push(Level { width, ranges });
+            depth = next_depth;
+        }
+
+        // With the same thickness as the blocks
+        let (low, high) = levels.last().unwrap().ranges[0];
+        Heightfield {
+            bbox: AABB::new(
+                Vector3::new(min.x(), low - EPSILON, min.z()),
+                Vector3::new(min.x() + size.x(), high + EPSILON, min.z() + size.z()),
+            ),
+            grid: Arc::new(Grid {
+                nx,
+                nz,
+                positions,
+                normals,
+                levels,
+            }),
+            material,
+        }
+    }
+
+    // The box around block (i, j) of a level, a little taller than its
+    // range so flat blocks have some thickness
+    fn block(&self, level: usize, i: usize, j: usize) -> AABB {
+        let grid = &self.grid;
+        let (low, high) = grid.levels[level].ranges[i + j * grid.levels[level].width];
+        let first = grid.positions[(i << level) + (j << level) * grid.nx];
+        let (last_i, last_j) = (
+            ((i + 1) << level).min(grid.nx - 1),
+            ((j + 1) << level).min(grid.nz - 1),
+        );
+        let last = grid.positions[last_i + last_j * grid.nx];
+        AABB::new(
+            Vector3::new(first.x(), low - EPSILON, first.z()),
+            Vector3::new(last.x(), high + EPSILON, last.z()),
+        )
+    }
+
+    // The nearest crossing of r with a triangle between t_min and t_max,
+    // and how many blocks were tried
+    fn trace(&self, r: &Ray, t_min: f32, t_max: f32) -> (Option<Crossing>, u32) {
+        let grid = &self.grid;
+        let top = grid.levels.len() - 1;
+        let mut stack = Vec::new();
+        if let Some((t_enter, _)) = self.block(top, 0, 0).intersect(r, t_min, t_max) {
+            stack.push((top, 0, 0, t_enter));
+        }
+        let (mut nearest, mut closest, mut visits) = (None, t_max, 0);
+        while let Some((level, i, j, t_enter)) = stack.pop() {
+            if t_enter > closest {
+                continue;
+            }
+            visits += 1;
+            if level == 0 {
+                let corner = i + j * grid.nx;
+                let (a, b, c, d) = (corner, corner + 1, corner + grid.nx + 1, corner + grid.nx);
+                for corners in [[a, b, c], [a, c, d]] {
+                    if let Some((t, b1, b2)) = self.crossing(r, corners) {
+                        if t > t_min && t < closest {
+                            closest = t;
+                            nearest = Some(Crossing { t, corners, b1, b2 });
+                        }
+                    }
+                }
+                continue;
+            }
+            // The blocks within this one the ray passes through, pushed so
+            // the nearest comes off the stack first
+            let below = &grid.levels[level - 1];
+            let depth = below.ranges.len() / below.width;
+            let mut children = Vec::with_capacity(4);
+            for (ci, cj) in [
+                (2 * i, 2 * j),
+                (2 * i + 1, 2 * j),
+                (2 * i, 2 * j + 1),
+                (2 * i + 1, 2 * j + 1),
+            ] {
+                if ci < below.width && cj < depth {
+                    if let Some((t, _)) = self.block(level - 1, ci, cj).intersect(r, t_min, closest)
+                    {
+                        children.push((level - 1, ci, cj, t));
+                    }
+                }
+            }
+            children.sort_by(|x, y| y.3.partial_cmp(&x.3).unwrap());
+            stack.extend(children);
+        }
+        (nearest, visits)
+    }
+
+    // Where the line of r crosses a triangle, as in Triangle
+    fn crossing(&self, r: &Ray, corners: [usize; 3]) -> Option<(f32, f32, f32)> {
+        let [v0, v1, v2] = corners.map(|k| self.grid.positions[k]);
+        let (edge1, edge2) = (v1 - v0, v2 - v0);
+        let pvec = r.direction().cross(&edge2);
+        let det = edge1.dot(&pvec);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let tvec = r.origin() - v0;
+        let b1 = tvec.dot(&pvec) * inv_det;
+        if !(-EPSILON..=1.0 + EPSILON).contains(&b1) {
+            return None;
+        }
+        let qvec = tvec.cross(&edge1);
+        let b2 = r.direction().dot(&qvec) * inv_det;
+        if b2 < -EPSILON || b1 + b2 > 1.0 + EPSILON {
+            return None;
+        }
+        Some((edge2.dot(&qvec) * inv_det, b1, b2))
+    }
+}
+
+impl Hitable for Heightfield {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let Crossing { t, corners, b1, b2 } = self.trace(r, t_min, t_max).0?;
+        let p = r.point_at_parameter(t);
+        // Blended from the corners', for smooth shading over the cells
+        let [n0, n1, n2] = corners.map(|k| self.grid.normals[k]);
+        let normal = ((1.0 - b1 - b2) * n0 + b1 * n1 + b2 * n2).get_unit_vector();
+        // Across the terrain, with v = 0 at +z so textures of the height
+        // map's image line up with it
+        let (min, max) = (self.bbox.min(), self.bbox.max());
+        Some(HitRecord {
+            t,
+            p,
+            normal,
+            u: ((p.x() - min.x()) / (max.x() - min.x())).clamp(0.0, 1.0),
+            v: ((max.z() - p.z()) / (max.z() - min.z())).clamp(0.0, 1.0),
+            material: self.material.clone(),
+            object_id: 0,
+        })
+    }
+
+    // Each block tried
+    fn traversal_cost(&self, r: &Ray, t_min: f32, t_max: f32) -> u32 {
+        self.trace(r, t_min, t_max).1
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let size = self.bbox.max() - self.bbox.min();
+        if size.x() > 0.0 && size.z() > 0.0 {
+            Ok(())
+        } else {
+            Err(format!("Heightfield size {} isn't positive", size))
+        }
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.bbox.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hitable::HitableList;
+    use crate::triangle::Triangle;
+
+    fn grey() -> Material {
+        Material::Lambertian {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        }
+    }
+
+    #[test]
+    fn matches_its_triangles() {
+        // Uneven sides, so the quadtree has blocks cut short at the edges
+        let (nx, nz) = (13, 6);
+        let heights = (0..nx * nz)
+            .map(|k| ((k * 7919) % 101) as f32 / 100.0)
+            .collect();
+        let map = HeightMap::new(nx, nz, heights);
+        let terrain = Heightfield::new(
+            &map,
+            Vector3::new(-3.0, 0.0, -2.0),
+            Vector3::new(6.0, 1.0, 3.0),
+            grey(),
+        );
+        let mut triangles = HitableList::new();
+        let corner = |i: usize, j: usize| terrain.grid.positions[i + j * nx];
+        for j in 0..nz - 1 {
+            for i in 0..nx - 1 {
+                let (a, b, c, d) = (
+                    corner(i, j),
+                    corner(i + 1, j),
+                    corner(i + 1, j + 1),
+                    corner(i, j + 1),
+                );
+                triangles.add(Triangle::new(a, b, c, grey()));
+                triangles.add(Triangle::new(a, c, d, grey()));
+            }
+        }
+
+        for k in 0..500 {
+            let angle = k as f32 * 0.37;
+            let origin = Vector3::new(5.0 * angle.cos(), 2.0 + (k % 3) as f32, 4.0 * angle.sin());
+            let target = Vector3::new(
+                ((k * 13) % 60) as f32 / 10.0 - 3.0,
+                0.3,
+                ((k * 29) % 30) as f32 / 10.0 - 2.0,
+            );
+            let r = Ray::new(origin, target - origin);
+            let expected = triangles.hit(&r, 0.001, f32::MAX).map(|hr| hr.t);
+            let found = terrain.hit(&r, 0.001, f32::MAX).map(|hr| hr.t);
+            match (expected, found) {
+                (Some(a), Some(b)) => assert!((a - b).abs() < 0.0001),
+                (None, None) => {}
+                _ => panic!("{:?} != {:?} for ray {}", found, expected, k),
+            }
+        }
+    }
+
+    #[test]
+    fn smooth_normals_and_uvs() {
+        // A ramp rising along x, flat along z
+        let heights = (0..16).map(|k| (k % 4) as f32 / 3.0).collect();
+        let terrain = Heightfield::new(
+            &HeightMap::new(4, 4, heights),
+            Vector3::zero(),
+            Vector3::new(3.0, 3.0, 3.0),
+            grey(),
+        );
+        let r = Ray::new(Vector3::new(1.5, 10.0, 0.75), Vector3::new(0.0, -1.0, 0.0));
+        let hr = terrain.hit(&r, 0.001, f32::MAX).unwrap();
+        assert!((hr.p.y() - 1.5).abs() < 0.0001);
+        let slope = Vector3::new(-1.0, 1.0, 0.0).get_unit_vector();
+        assert!((hr.normal - slope).length() < 0.0001);
+        assert!((hr.u - 0.5).abs() < 0.0001);
+        assert!((hr.v - 0.75).abs() < 0.0001);
+        // Passing over the top, and from underneath
+        let over = Ray::new(Vector3::new(-1.0, 3.5, 1.0), Vector3::new(1.0, 0.0, 0.0));
+        assert!(terrain.hit(&over, 0.001, f32::MAX).is_none());
+        let up = Ray::new(Vector3::new(2.5, -1.0, 2.5), Vector3::new(0.0, 1.0, 0.0));
+        assert!((terrain.hit(&up, 0.001, f32::MAX).unwrap().p.y() - 2.5).abs() < 0.0001);
+    }
+}
@@ -1,6 +1,7 @@
 use crate::aabb::AABB;
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::triangle;
 use crate::vec3::Vector3;
 
 #[derive(Clone)]
@@ -8,12 +9,60 @@ pub struct HitRecord {
     pub t: f32,
     pub p: Vector3,
     pub normal: Vector3,
+    // Surface coordinates, for textures
+    pub u: f32,
+    pub v: f32,
     pub material: Material,
+    // Which of the scene's objects was hit, from 1, if they are tagged
+    pub object_id: u32,
 }
 
 pub trait Hitable: HitableClone {
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
     fn bounding_box(&self) -> Option<AABB>;
+
+    // Fraction of light getting through along the ray between t_min and
+    // t_max, for shadow rays. Surfaces block it entirely, media override
+    // this to let some through.
+    fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        if self.hit(r, t_min, t_max).is_some() {
+            0.0
+        } else {
+            1.0
+        }
+    }
+
+    // How many bounding box and primitive tests finding the hit along r
+    // takes, for the traversal cost heatmap
+    fn traversal_cost(&self, _r: &Ray, _t_min: f32, _t_max: f32) -> u32 {
+        1
+    }
+
+    // Every place the whole line of r crosses the surface, in order, for
+    // solids combined by CSG. Normals face out of the solid, so against r
+    // where it enters. None for objects without an inside.
+    fn all_hits(&self, _r: &Ray) -> Option<Vec<HitRecord>> {
+        None
+    }
+
+    // Whether the object has an inside, so can be combined by CSG: closed
+    // surfaces such as spheres and boxes, closed meshes, and combinations
+    // of them
+    fn is_solid(&self) -> bool {
+        false
+    }
+
+    // The corners of the object's triangles, if it's made of nothing else,
+    // for finding closed meshes
+    fn triangles(&self) -> Option<Vec<[Vector3; 3]>> {
+        None
+    }
+
+    // Why the object can't be rendered, e.g. a sphere with no radius.
+    // Checked when the scene is built.
+    fn validate(&self) -> Result<(), String> {
+        Ok(())
+    }
 }
 
 pub trait HitableClone {
@@ -50,10 +99,6 @@ impl HitableList {
     pub fn add<T: Hitable + 'static + Send>(&mut self, item: T) {
         self.hitables.push(Box::new(item));
     }
-
-    pub fn len(&mut self) -> usize {
-        self.hitables.len()
-    }
 }
 
 impl Hitable for HitableList {
@@ -61,19 +106,63 @@ impl Hitable for HitableList {
         let mut temp_rec: Option<HitRecord> = None;
         let mut closest_so_far = t_max;
         for hitable in self.hitables.iter() {
-            match hitable.hit(r, t_min, closest_so_far) {
-                Some(hr) => {
-                    closest_so_far = hr.t;
-                    temp_rec = Some(hr);
-                }
-                None => (),
+            if let Some(hr) = hitable.hit(r, t_min, closest_so_far) {
+                closest_so_far = hr.t;
+                temp_rec = Some(hr);
             }
         }
         temp_rec
     }
 
+    fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        let mut transmittance = 1.0;
+        for hitable in self.hitables.iter() {
+            transmittance *= hitable.transmittance(r, t_min, t_max);
+            if transmittance == 0.0 {
+                break;
+            }
+        }
+        transmittance
+    }
+
+    fn traversal_cost(&self, r: &Ray, t_min: f32, t_max: f32) -> u32 {
+        self.hitables
+            .iter()
+            .map(|hitable| hitable.traversal_cost(r, t_min, t_max))
+            .sum()
+    }
+
+    // A closed mesh's triangles, or solids
+    fn all_hits(&self, r: &Ray) -> Option<Vec<HitRecord>> {
+        let mut hits = Vec::new();
+        for hitable in self.hitables.iter() {
+            hits.extend(hitable.all_hits(r)?);
+        }
+        hits.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap());
+        Some(hits)
+    }
+
+    fn is_solid(&self) -> bool {
+        let parts: Vec<&dyn Hitable> = self.hitables.iter().map(|h| h.as_ref() as _).collect();
+        form_solid(&parts)
+    }
+
+    fn triangles(&self) -> Option<Vec<[Vector3; 3]>> {
+        let mut triangles = Vec::new();
+        for hitable in self.hitables.iter() {
+            triangles.extend(hitable.triangles()?);
+        }
+        Some(triangles)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        self.hitables
+            .iter()
+            .try_for_each(|hitable| hitable.validate())
+    }
+
     fn bounding_box(&self) -> Option<AABB> {
-        if self.hitables.len() < 1 {
+        if self.hitables.is_empty() {
             return None;
         }
 
@@ -90,6 +179,78 @@ impl Hitable for HitableList {
             };
         }
 
-        return Some(list_box);
+        Some(list_box)
+    }
+}
+
+// Whether parts make a solid together: each is one, or between them
+// they're the triangles of a closed mesh
+pub fn form_solid(parts: &[&dyn Hitable]) -> bool {
+    if parts.is_empty() {
+        return false;
+    }
+    if parts.iter().all(|part| part.is_solid()) {
+        return true;
+    }
+    let mut triangles = Vec::new();
+    for part in parts {
+        match part.triangles() {
+            Some(more) => triangles.extend(more),
+            None => return false,
+        }
+    }
+    triangle::is_closed(&triangles)
+}
+
+// Marks hits on an object with an ID, for the object ID pass
+#[derive(Clone)]
+pub struct Tagged {
+    id: u32,
+    object: Box<dyn Hitable + Send>,
+}
+
+impl Tagged {
+    pub fn new(id: u32, object: Box<dyn Hitable + Send>) -> Tagged {
+        Tagged { id, object }
+    }
+}
+
+impl Hitable for Tagged {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let mut hr = self.object.hit(r, t_min, t_max)?;
+        hr.object_id = self.id;
+        Some(hr)
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        self.object.transmittance(r, t_min, t_max)
+    }
+
+    fn traversal_cost(&self, r: &Ray, t_min: f32, t_max: f32) -> u32 {
+        self.object.traversal_cost(r, t_min, t_max)
+    }
+
+    fn all_hits(&self, r: &Ray) -> Option<Vec<HitRecord>> {
+        let mut hits = self.object.all_hits(r)?;
+        for hr in hits.iter_mut() {
+            hr.object_id = self.id;
+        }
+        Some(hits)
+    }
+
+    fn is_solid(&self) -> bool {
+        self.object.is_solid()
+    }
+
+    fn triangles(&self) -> Option<Vec<[Vector3; 3]>> {
+        self.object.triangles()
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        self.object.validate()
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        self.object.bounding_box()
     }
 }
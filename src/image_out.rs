@@ -2,9 +2,12 @@ use std::io::{stdout, Write};
 
 use std::path::Path;
 
+use crate::vec3::Vector3;
+
+extern crate exr;
 extern crate image;
 
-pub fn write_image(output_path: Option<&str>, image: &Vec<u8>, imgx: u32, imgy: u32) {
+pub fn write_image(output_path: Option<&str>, image: &[u8], imgx: u32, imgy: u32) {
     match output_path {
         Some(x) => {
             let output_image: image::ImageBuffer<image::Rgb<u8>, Vec<u8>> =
@@ -18,8 +21,97 @@ pub fn write_image(output_path: Option<&str>, image: &Vec<u8>, imgx: u32, imgy:
     }
 }
 
+// 8-bit RGB, clamped, and gamma encoded for colours
+pub fn to_rgb8(pixels: &[Vector3], gamma: bool) -> Vec<u8> {
+    let encode = |c: f32| {
+        let c = c.clamp(0.0, 1.0);
+        (255.99 * if gamma { c.sqrt() } else { c }) as u8
+    };
+    pixels
+        .iter()
+        .flat_map(|p| [encode(p.r()), encode(p.g()), encode(p.b())])
+        .collect()
+}
+
+// A linear floating point EXR with a layer for each (name, channels,
+// pixels), rows from the top. Single channel layers take x. A single layer
+// is left unnamed, so its channels are just R, G and B.
+pub fn write_exr(
+    path: &str,
+    width: u32,
+    height: u32,
+    layers: &[(&str, &[&str], &[Vector3])],
+) -> exr::error::UnitResult {
+    use exr::prelude::*;
+
+    let size = Vec2(width as usize, height as usize);
+    let count = layers.len();
+    let layers: Vec<_> = layers
+        .iter()
+        .map(|(name, channel_names, pixels)| {
+            let channels = channel_names
+                .iter()
+                .enumerate()
+                .map(|(axis, channel)| {
+                    let samples = pixels.iter().map(|p| p.component(axis)).collect();
+                    AnyChannel::new(*channel, FlatSamples::F32(samples))
+                })
+                .collect();
+            Layer::new(
+                size,
+                LayerAttributes {
+                    layer_name: if count == 1 {
+                        None
+                    } else {
+                        Some(Text::from(*name))
+                    },
+                    ..LayerAttributes::default()
+                },
+                Encoding::FAST_LOSSLESS,
+                AnyChannels::sort(channels),
+            )
+        })
+        .collect();
+    let attributes = ImageAttributes::new(IntegerBounds::from_dimensions(size));
+    Image::from_layers(attributes, layers).write().to_file(path)
+}
+
+// The file name for a frame of an animation. A printf style %d in the
+// pattern, with an optional zero padded width like %04d, is replaced by
+// the frame number. None if there isn't one.
+pub fn frame_path(pattern: &str, frame: u32) -> Option<String> {
+    let start = pattern.find('%')?;
+    let rest = &pattern[start + 1..];
+    let end = rest.find('d')?;
+    let width = match &rest[..end] {
+        "" => 0,
+        width => width.parse().ok()?,
+    };
+    Some(format!(
+        "{}{:0width$}{}",
+        &pattern[..start],
+        frame,
+        &rest[end + 1..],
+        width = width
+    ))
+}
+
+// The file name for an extra pass: out.png becomes out_depth.png
+pub fn pass_path(path: &str, pass: &str) -> String {
+    let path = Path::new(path);
+    match path.extension() {
+        Some(extension) => format!(
+            "{}_{}.{}",
+            path.with_extension("").display(),
+            pass,
+            extension.to_string_lossy()
+        ),
+        None => format!("{}_{}", path.display(), pass),
+    }
+}
+
 pub fn create_image(
-    image: &Vec<u8>,
+    image: &[u8],
     imgx: u32,
     imgy: u32,
 ) -> image::ImageBuffer<image::Rgb<u8>, Vec<u8>> {
@@ -27,25 +119,38 @@ pub fn create_image(
 }
 
 // Write image to stdout using the ppm 3 format, with subpixel values 0-255
-fn write_image_stdout(image: &Vec<u8>, imgx: u32, imgy: u32) {
+fn write_image_stdout(image: &[u8], imgx: u32, imgy: u32) {
     let mut std_writer = Box::new(stdout());
 
-    match write!(std_writer, "P3\n{} {}\n{}\n\n", imgx, imgy, 255) {
-        Err(e) => panic!("Failed write: {}", e),
-        Ok(_) => (),
+    if let Err(e) = write!(std_writer, "P3\n{} {}\n{}\n\n", imgx, imgy, 255) {
+        panic!("Failed write: {}", e);
     }
 
     // TODO Make this less akward
     for chunk in image.chunks(3) {
         for c in chunk {
-            match write!(std_writer, "{} ", c) {
-                Err(e) => panic!("Failed write: {}", e),
-                Ok(_) => (),
+            if let Err(e) = write!(std_writer, "{} ", c) {
+                panic!("Failed write: {}", e);
             }
         }
-        match write!(std_writer, "\n",) {
-            Err(e) => panic!("Failed write: {}", e),
-            Ok(_) => (),
+        if let Err(e) = writeln!(std_writer) {
+            panic!("Failed write: {}", e);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbered_frames() {
+        assert_eq!(frame_path("out_%04d.png", 7).unwrap(), "out_0007.png");
+        assert_eq!(frame_path("%d.png", 12).unwrap(), "12.png");
+        assert!(frame_path("out.png", 1).is_none());
+        assert_eq!(
+            pass_path("frames/out_0007.png", "depth"),
+            "frames/out_0007_depth.png"
+        );
+    }
+}
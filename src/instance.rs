@@ -0,0 +1,210 @@
+use crate::aabb::AABB;
+use crate::hitable::{HitRecord, Hitable};
+use crate::motion::{Keyframes, Lerp};
+use crate::ray::Ray;
+use crate::vec3::Vector3;
+
+// Places an object: scaled, then turned about the y axis, then moved.
+// Rotation is in degrees, so keyframes can spin more than one turn. Scale
+// must be positive.
+#[derive(Clone)]
+pub struct Transform {
+    pub translation: Vector3,
+    pub rotation: f32,
+    pub scale: f32,
+}
+
+// Turns v by angle radians about the y axis
+fn rotate_y(v: &Vector3, angle: f32) -> Vector3 {
+    let (sin, cos) = angle.sin_cos();
+    Vector3::new(cos * v.x() + sin * v.z(), v.y(), -sin * v.x() + cos * v.z())
+}
+
+impl Transform {
+    pub fn new(translation: Vector3, rotation: f32, scale: f32) -> Transform {
+        Transform {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    fn point_to_object(&self, p: &Vector3) -> Vector3 {
+        rotate_y(&(*p - self.translation), -self.rotation.to_radians()) / self.scale
+    }
+
+    fn vector_to_object(&self, v: &Vector3) -> Vector3 {
+        rotate_y(v, -self.rotation.to_radians()) / self.scale
+    }
+}
+
+impl Lerp for Transform {
+    fn lerp(&self, other: &Transform, t: f32) -> Transform {
+        Transform {
+            translation: self.translation.lerp(&other.translation, t),
+            rotation: self.rotation.lerp(&other.rotation, t),
+            scale: self.scale.lerp(&other.scale, t),
+        }
+    }
+}
+
+// The eight corners of a box
+fn corners(bbox: &AABB) -> impl Iterator<Item = Vector3> {
+    let (min, max) = (bbox.min(), bbox.max());
+    (0..8).map(move |i| {
+        Vector3::new(
+            if i & 1 != 0 { max.x() } else { min.x() },
+            if i & 2 != 0 { max.y() } else { min.y() },
+            if i & 4 != 0 { max.z() } else { min.z() },
+        )
+    })
+}
+
+fn bounds(points: impl Iterator<Item = Vector3>) -> Option<AABB> {
+    points
+        .map(|p| AABB::new(p, p))
+        .reduce(|a, b| a.surrounding_box(&b))
+}
+
+// A hit found along the ray in the object's space, back where r hit it
+fn to_world(r: &Ray, transform: &Transform, mut hr: HitRecord) -> HitRecord {
+    hr.p = r.point_at_parameter(hr.t);
+    hr.normal = rotate_y(&hr.normal, transform.rotation.to_radians());
+    hr
+}
+
+// An object, possibly shared with other instances, placed in the scene by
+// a transform that can change over time
+#[derive(Clone)]
+pub struct Instance {
+    object: Box<dyn Hitable + Send>,
+    transform: Keyframes<Transform>,
+}
+
+impl Instance {
+    pub fn new(object: Box<dyn Hitable + Send>, transform: Keyframes<Transform>) -> Instance {
+        Instance { object, transform }
+    }
+
+    // The ray in the object's space, at the ray's time. Distances along it
+    // are the same as along r.
+    fn to_object(&self, r: &Ray) -> (Ray, Transform) {
+        let transform = self.transform.at(r.time());
+        let ray = r.spawn(
+            transform.point_to_object(&r.origin()),
+            transform.vector_to_object(&r.direction()),
+        );
+        (ray, transform)
+    }
+}
+
+impl Hitable for Instance {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let (ray, transform) = self.to_object(r);
+        let hr = self.object.hit(&ray, t_min, t_max)?;
+        Some(to_world(r, &transform, hr))
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        let (ray, _) = self.to_object(r);
+        self.object.transmittance(&ray, t_min, t_max)
+    }
+
+    fn traversal_cost(&self, r: &Ray, t_min: f32, t_max: f32) -> u32 {
+        let (ray, _) = self.to_object(r);
+        self.object.traversal_cost(&ray, t_min, t_max)
+    }
+
+    fn all_hits(&self, r: &Ray) -> Option<Vec<HitRecord>> {
+        let (ray, transform) = self.to_object(r);
+        let hits = self.object.all_hits(&ray)?;
+        Some(
+            hits.into_iter()
+                .map(|hr| to_world(r, &transform, hr))
+                .collect(),
+        )
+    }
+
+    fn is_solid(&self) -> bool {
+        self.object.is_solid()
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        self.object.validate()
+    }
+
+    // Covers everywhere the object goes between the keyframes
+    fn bounding_box(&self) -> Option<AABB> {
+        let object_box = self.object.bounding_box()?;
+        self.transform
+            .segments()
+            .into_iter()
+            .filter_map(|(start, end)| {
+                // Where rotation alone takes the object. If it turns at all
+                // between the keys, each corner could be anywhere on its
+                // circle about the axis.
+                let turned = if start.rotation == end.rotation {
+                    let angle = start.rotation.to_radians();
+                    bounds(corners(&object_box).map(|c| rotate_y(&c, angle)))?
+                } else {
+                    let radius = corners(&object_box)
+                        .map(|c| (c.x() * c.x() + c.z() * c.z()).sqrt())
+                        .fold(0.0, f32::max);
+                    AABB::new(
+                        Vector3::new(-radius, object_box.min().y(), -radius),
+                        Vector3::new(radius, object_box.max().y(), radius),
+                    )
+                };
+                // Scale and translation change linearly along the segment,
+                // so their extremes are at its ends
+                let mut points = Vec::new();
+                for c in corners(&turned) {
+                    for s in [start.scale, end.scale] {
+                        for t in [start.translation, end.translation] {
+                            points.push(t + s * c);
+                        }
+                    }
+                }
+                bounds(points.into_iter())
+            })
+            .reduce(|a, b| a.surrounding_box(&b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Material;
+    use crate::sphere::Sphere;
+
+    #[test]
+    fn moving_instance() {
+        let sphere = Sphere::new(
+            Vector3::new(1.0, 0.0, 0.0),
+            0.5,
+            Material::Dielectric { ri: 1.5 },
+        );
+        // Half a turn and doubling in size, while moving up
+        let instance = Instance::new(
+            Box::new(sphere),
+            Keyframes::new(vec![
+                (0.0, Transform::new(Vector3::zero(), 0.0, 1.0)),
+                (1.0, Transform::new(Vector3::new(0.0, 2.0, 0.0), 180.0, 2.0)),
+            ]),
+        );
+
+        // At the end, the sphere has radius 1 around (-2, 2, 0)
+        let down = Vector3::new(0.0, -1.0, 0.0);
+        let r = Ray::new(Vector3::new(-2.0, 5.0, 0.0), down).with_time(1.0);
+        let hr = instance.hit(&r, 0.001, f32::MAX).unwrap();
+        assert!((hr.t - 2.0).abs() < 0.0001);
+        assert!((hr.normal - Vector3::new(0.0, 1.0, 0.0)).length() < 0.0001);
+        // Half way, it's around (0, 1, -1.5) with radius 0.75
+        let r = Ray::new(Vector3::new(0.0, 5.0, -1.5), down).with_time(0.5);
+        assert!((instance.hit(&r, 0.001, f32::MAX).unwrap().t - 3.25).abs() < 0.0001);
+
+        let bbox = instance.bounding_box().unwrap();
+        assert!(bbox.min().x() <= -3.0 && bbox.max().x() >= 1.5);
+        assert!(bbox.min().y() <= -0.5 && bbox.max().y() >= 3.0);
+    }
+}
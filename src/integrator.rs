@@ -0,0 +1,563 @@
+use crate::camera::Shutter;
+use crate::hitable::{HitRecord, Hitable};
+use crate::light::LightSample;
+use crate::random::Sampler;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::spectrum;
+use crate::vec3::Vector3;
+use std::f32;
+use std::ops::{Add, Mul};
+
+// A light transport algorithm, picked at runtime with --integrator. Its
+// random choices, and those of the materials, lights and backgrounds it
+// samples, come from the sampler it's given.
+pub trait Integrator<T: Hitable>: Send + Sync {
+    // Light arriving along r, a ray from the camera, and what it hit first.
+    // Integrators rendering whole images only give the first hit, for the
+    // AOVs.
+    fn sample(&self, r: &Ray, scene: &Scene<T>, sampler: &mut Sampler) -> Sample;
+
+    // For integrators that can't work a sample at a time: the whole image,
+    // with rows from the top, rendered with the given samples per pixel.
+    // None for the rest, which are asked for samples instead.
+    fn render(
+        &self,
+        _scene: &Scene<T>,
+        _shutter: &Shutter,
+        _width: u32,
+        _height: u32,
+        _samples: u32,
+        _sampler: &mut Sampler,
+    ) -> Option<Vec<Vector3>> {
+        None
+    }
+}
+
+// What an integrator found along a camera ray: the light arriving, split
+// into direct and indirect for their AOVs, light from paths traced from
+// lights that lands elsewhere on the image, and the first surface or fog
+// the ray hit, which the other AOVs describe
+pub struct Sample {
+    pub radiance: Radiance,
+    pub splats: Vec<Splat>,
+    pub first_hit: Option<HitRecord>,
+}
+
+impl Sample {
+    pub fn new(radiance: Radiance, first_hit: Option<HitRecord>) -> Sample {
+        Sample {
+            radiance,
+            splats: Vec::new(),
+            first_hit,
+        }
+    }
+}
+
+// Light along a path, split by whether it reached the camera after at most
+// one bounce
+#[derive(Clone, Copy)]
+pub struct Radiance {
+    pub direct: Vector3,
+    pub indirect: Vector3,
+}
+
+impl Radiance {
+    pub fn new(radiance: Vector3, bounces: u32) -> Radiance {
+        if bounces <= 1 {
+            Radiance {
+                direct: radiance,
+                indirect: Vector3::zero(),
+            }
+        } else {
+            Radiance {
+                direct: Vector3::zero(),
+                indirect: radiance,
+            }
+        }
+    }
+
+    pub fn zero() -> Radiance {
+        Radiance {
+            direct: Vector3::zero(),
+            indirect: Vector3::zero(),
+        }
+    }
+
+    pub fn total(&self) -> Vector3 {
+        self.direct + self.indirect
+    }
+}
+
+impl Add for Radiance {
+    type Output = Radiance;
+
+    fn add(self, other: Radiance) -> Radiance {
+        Radiance {
+            direct: self.direct + other.direct,
+            indirect: self.indirect + other.indirect,
+        }
+    }
+}
+
+impl Mul<Radiance> for Vector3 {
+    type Output = Radiance;
+
+    fn mul(self, radiance: Radiance) -> Radiance {
+        Radiance {
+            direct: self * radiance.direct,
+            indirect: self * radiance.indirect,
+        }
+    }
+}
+
+// Light reaching the image at (s, t) from a path traced from a light, which
+// belongs to no pixel's samples. Only in the beauty pass, not in any AOV.
+pub struct Splat {
+    pub s: f32,
+    pub t: f32,
+    pub value: Vector3,
+}
+
+// Power heuristic for multiple importance sampling
+pub fn mis_weight(pdf: f32, other_pdf: f32) -> f32 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b == 0.0 {
+        0.0
+    } else {
+        a / (a + b)
+    }
+}
+
+// Light reaching a hit directly from the background, by sampling it
+pub fn sample_background<T: Hitable>(
+    r: &Ray,
+    hr: &HitRecord,
+    scene: &Scene<T>,
+    sampler: &mut Sampler,
+) -> Vector3 {
+    let (direction, radiance, light_pdf) = match scene.background.sample(r, sampler) {
+        Some(sample) => sample,
+        None => return Vector3::zero(),
+    };
+    let f = hr.material.eval(r, hr, &direction);
+    if f == Vector3::zero() {
+        return Vector3::zero();
+    }
+
+    let shadow_ray = r.spawn(hr.p, direction);
+    let transmittance = scene.objects.transmittance(&shadow_ray, 0.001, f32::MAX);
+    let weight = mis_weight(light_pdf, hr.material.pdf(r, hr, &direction));
+    f * radiance * (transmittance * weight / light_pdf)
+}
+
+// Light arriving at a hit from one light sample, which was picked with
+// probability pmf
+fn light_contribution<T: Hitable>(
+    r: &Ray,
+    hr: &HitRecord,
+    scene: &Scene<T>,
+    sample: &LightSample,
+    pmf: f32,
+) -> Vector3 {
+    let f = hr.material.eval(r, hr, &sample.direction);
+    if f == Vector3::zero() {
+        return Vector3::zero();
+    }
+
+    let shadow_ray = r.spawn(hr.p, sample.direction);
+    // Stop short of area lights' own surfaces
+    let mut transmittance =
+        scene
+            .objects
+            .transmittance(&shadow_ray, 0.001, sample.distance * 0.9999);
+    if let Some(fog) = &scene.fog {
+        transmittance *= fog.transmittance(&shadow_ray, 0.001, sample.distance);
+    }
+    let weight = match sample.pdf {
+        Some(pdf) => {
+            let light_pdf = pmf * pdf;
+            mis_weight(light_pdf, hr.material.pdf(r, hr, &sample.direction)) / light_pdf
+        }
+        None => 1.0 / pmf,
+    };
+    f * spectrum::upsample(&sample.radiance, r.wavelength()) * (transmittance * weight)
+}
+
+// Light reaching a hit from the scene's lights. Punctual lights can't be
+// hit by scattered rays, so they are all sampled every time. One area light
+// is picked from the light tree, only when the background is also sampled.
+pub fn sample_lights<T: Hitable>(
+    r: &Ray,
+    hr: &HitRecord,
+    scene: &Scene<T>,
+    light_sampled: bool,
+    sampler: &mut Sampler,
+) -> Vector3 {
+    let mut total = Vector3::zero();
+    for light in scene.lights.iter().filter(|light| light.is_punctual()) {
+        if let Some(sample) = light.sample(&hr.p, sampler) {
+            total = total + light_contribution(r, hr, scene, &sample, 1.0);
+        }
+    }
+
+    if light_sampled {
+        if let Some((index, pmf)) = scene.light_tree.sample(&hr.p, sampler) {
+            if let Some(sample) = scene.lights[index].sample(&hr.p, sampler) {
+                total = total + light_contribution(r, hr, scene, &sample, pmf);
+            }
+        }
+    }
+    total
+}
+
+// Paths traced from the camera, with Russian roulette. Lights and the
+// background are also sampled where --light_sampling says to, weighted
+// against hitting them by MIS.
+pub struct PathTracer;
+
+impl<T: Hitable> Integrator<T> for PathTracer {
+    fn sample(&self, r: &Ray, scene: &Scene<T>, sampler: &mut Sampler) -> Sample {
+        let mut first_hit = None;
+        let radiance = path_radiance(r, scene, sampler, &mut first_hit);
+        Sample::new(radiance, first_hit)
+    }
+}
+
+// Light arriving along r, and what it hit first
+fn path_radiance<T: Hitable>(
+    r: &Ray,
+    scene: &Scene<T>,
+    sampler: &mut Sampler,
+    first_hit: &mut Option<HitRecord>,
+) -> Radiance {
+    let mut ray = r.clone();
+    let mut radiance = Radiance::zero();
+    // What's left of the light along the path so far
+    let mut throughput = Vector3::new(1.0, 1.0, 1.0);
+    // The density the ray was scattered with, if the background and area
+    // lights were also light sampled where it started
+    let mut bsdf_pdf = None;
+    let mut depth = 0;
+    // Of each kind, in the order of Bounce
+    let mut bounces = [0; 4];
+
+    loop {
+        let hit = scene.hit(&ray, sampler);
+        if depth == 0 {
+            *first_hit = hit.clone();
+        }
+        let hr = match hit {
+            Some(hr) => hr,
+            None => {
+                let mut background = scene.background.color(&ray);
+                if let Some(pdf) = bsdf_pdf {
+                    background =
+                        background * mis_weight(pdf, scene.background.pdf(&ray.direction()));
+                }
+                return radiance + Radiance::new(throughput * background, depth);
+            }
+        };
+
+        let mut emitted = hr.material.emitted(&ray, &hr);
+        if let Some(pdf) = bsdf_pdf {
+            let light_pdf = scene.light_tree.pdf(&scene.lights, &ray, &hr);
+            emitted = emitted * mis_weight(pdf, light_pdf);
+        }
+        radiance = radiance + Radiance::new(throughput * emitted, depth);
+
+        let s = match hr.material.scatter(&ray, &hr, sampler) {
+            Some(s) => s,
+            None => return radiance,
+        };
+        let bounce = hr.material.bounce();
+        if bounces[bounce as usize] >= scene.settings.limits.max(bounce) {
+            return radiance;
+        }
+        bounces[bounce as usize] += 1;
+
+        // Only scattering with a known density can be combined with light
+        // sampling
+        let light_sampled = scene.settings.light_sampling && s.pdf.is_some();
+        let mut direct = sample_lights(&ray, &hr, scene, light_sampled, sampler);
+        if light_sampled {
+            direct = direct + sample_background(&ray, &hr, scene, sampler);
+        }
+        depth += 1;
+        radiance = radiance + Radiance::new(throughput * direct, depth);
+
+        throughput = throughput * s.color;
+        bsdf_pdf = if light_sampled { s.pdf } else { None };
+        ray = s.ray;
+
+        if depth >= scene.settings.limits.roulette_depth {
+            let survival = throughput
+                .x()
+                .max(throughput.y())
+                .max(throughput.z())
+                .min(0.95);
+            if sampler.uniform() >= survival {
+                return radiance;
+            }
+            throughput = throughput / survival;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aabb::AABB;
+    use crate::background::Background;
+    use crate::bdpt::Bdpt;
+    use crate::bvh::BVHNode;
+    use crate::camera::{Camera, CameraModel};
+    use crate::emission::Emission;
+    use crate::hitable::HitableList;
+    use crate::light::MeshLight;
+    use crate::material::{Material, PhaseFunction};
+    use crate::medium::{Fog, GridMedium};
+    use crate::photon::Sppm;
+    use crate::sphere::Sphere;
+    use crate::triangle;
+    use crate::voxel::VoxelGrid;
+    use std::sync::Arc;
+
+    fn grey() -> Material {
+        Material::Lambertian {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        }
+    }
+
+    // A glass ball on a grey floor, under a panel light
+    fn scene() -> Scene<HitableList> {
+        let mut objects = HitableList::new();
+        for t in triangle::quad(
+            Vector3::new(-2.0, 0.0, -2.0),
+            Vector3::new(0.0, 0.0, 4.0),
+            Vector3::new(4.0, 0.0, 0.0),
+            grey(),
+        ) {
+            objects.add(t);
+        }
+        objects.add(Sphere::new(
+            Vector3::new(0.0, 0.5, 0.0),
+            0.5,
+            Material::Dielectric { ri: 1.5 },
+        ));
+        let mut scene = Scene::new(objects);
+        scene.add_area_light(MeshLight::new(
+            triangle::quad(
+                Vector3::new(-0.5, 2.0, -0.5),
+                Vector3::new(1.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.0, 1.0),
+                grey(),
+            ),
+            Emission::new(Vector3::new(4.0, 4.0, 4.0)),
+            None,
+        ));
+        scene.settings.light_sampling = true;
+        scene
+    }
+
+    fn fog() -> Fog {
+        Fog::new(0.3, Vector3::new(0.8, 0.8, 0.8), PhaseFunction::Isotropic)
+    }
+
+    // At the ball, the floor beside it, and over the floor's edge
+    fn rays() -> Vec<Ray> {
+        let from = Vector3::new(0.0, 1.0, 3.0);
+        [
+            Vector3::new(0.0, 0.5, 0.0),
+            Vector3::new(1.0, 0.0, 0.5),
+            Vector3::new(0.0, 0.0, -3.0),
+        ]
+        .iter()
+        .map(|at| Ray::new(from, *at - from))
+        .collect()
+    }
+
+    #[test]
+    fn samples_repeat_with_their_seed() {
+        for fog in [None, Some(fog())] {
+            let mut scene = scene();
+            scene.fog = fog;
+            let scene = scene.build().unwrap();
+            for r in rays() {
+                let radiance = |seed| {
+                    let sample = PathTracer.sample(&r, &scene, &mut Sampler::new(seed));
+                    (sample.radiance.direct, sample.radiance.indirect)
+                };
+                let seeds: Vec<_> = (0..8).map(radiance).collect();
+                for (seed, first) in seeds.iter().enumerate() {
+                    assert!(radiance(seed as u64) == *first);
+                }
+                // Each seed follows its own path
+                assert!(seeds.iter().any(|other| *other != seeds[0]));
+            }
+        }
+    }
+
+    // The first hit is where the scene says the ray hits, whether on a
+    // surface or in the fog, for every integrator
+    #[test]
+    fn first_hit_is_the_scenes_hit() {
+        let cam: Arc<dyn CameraModel> = Arc::new(Camera::new(
+            Vector3::new(0.0, 1.0, 3.0),
+            Vector3::zero(),
+            Vector3::new(0.0, 1.0, 0.0),
+            60.0,
+            1.0,
+            0.0,
+            3.0,
+        ));
+        let integrators: Vec<Box<dyn Integrator<BVHNode>>> = vec![
+            Box::new(PathTracer),
+            Box::new(Bdpt::new(cam.clone(), 4)),
+            Box::new(Sppm::new(cam, 100, 0.1)),
+        ];
+        for fog in [None, Some(fog())] {
+            let in_fog = fog.is_some();
+            let mut scene = scene();
+            scene.fog = fog;
+            let scene = scene.build().unwrap();
+            let mut fog_hits = 0;
+            for integrator in integrators.iter() {
+                for r in rays() {
+                    for seed in 0..16 {
+                        let sample = integrator.sample(&r, &scene, &mut Sampler::new(seed));
+                        let hit = scene.hit(&r, &mut Sampler::new(seed));
+                        match (sample.first_hit, hit) {
+                            (Some(first), Some(hit)) => {
+                                assert_eq!(first.t, hit.t);
+                                assert!(first.p == hit.p);
+                                if matches!(hit.material, Material::Volume { .. }) {
+                                    fog_hits += 1;
+                                }
+                            }
+                            (None, None) => (),
+                            _ => panic!("First hit doesn't match the scene's"),
+                        }
+                    }
+                }
+            }
+            assert_eq!(fog_hits > 0, in_fog);
+        }
+    }
+    // Mean light along r over seeds 0..samples
+    fn mean_radiance(r: &Ray, scene: &Scene<BVHNode>, samples: u64) -> Vector3 {
+        let total = (0..samples).fold(Vector3::zero(), |total, seed| {
+            let sample = PathTracer.sample(r, scene, &mut Sampler::new(seed));
+            total + sample.radiance.total()
+        });
+        total / samples as f32
+    }
+
+    // With no diffuse bounces, a diffuse floor is black, but light still
+    // comes off a mirror beside it
+    #[test]
+    fn limits_stop_their_own_bounces() {
+        let mut objects = HitableList::new();
+        let mirror = Material::Metal {
+            albedo: Vector3::new(0.8, 0.8, 0.8),
+            fuzz: 0.0,
+        };
+        for (x, material) in [(-2.0, grey()), (0.0, mirror)] {
+            for t in triangle::quad(
+                Vector3::new(x, 0.0, -2.0),
+                Vector3::new(0.0, 0.0, 4.0),
+                Vector3::new(2.0, 0.0, 0.0),
+                material,
+            ) {
+                objects.add(t);
+            }
+        }
+        let mut scene = Scene::new(objects);
+        scene.add_area_light(MeshLight::new(
+            triangle::quad(
+                Vector3::new(-1.5, 2.0, -0.5),
+                Vector3::new(3.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.0, 1.0),
+                grey(),
+            ),
+            Emission::new(Vector3::new(4.0, 4.0, 4.0)),
+            None,
+        ));
+        let down = Vector3::new(0.0, -1.0, 0.0);
+        let floor = Ray::new(Vector3::new(-1.0, 1.0, 0.0), down);
+        let mirror = Ray::new(Vector3::new(1.0, 1.0, 0.0), down);
+        for light_sampling in [false, true] {
+            let mut scene = scene.clone();
+            scene.settings.light_sampling = light_sampling;
+            scene.settings.limits.diffuse = 0;
+            let scene = scene.build().unwrap();
+            assert!(mean_radiance(&floor, &scene, 64) == Vector3::zero());
+            let reflected = mean_radiance(&mirror, &scene, 64).x();
+            assert!((reflected - 0.8 * 4.0).abs() < 0.0001, "{}", reflected);
+        }
+        let scene = scene.build().unwrap();
+        assert!(mean_radiance(&floor, &scene, 64).x() > 0.0);
+    }
+
+    // Ending paths at random and weighting up the survivors leaves the
+    // mean where it was
+    #[test]
+    fn roulette_keeps_the_mean() {
+        let mut scene = scene();
+        scene.settings.limits.roulette_depth = 1;
+        let roulette = scene.clone().build().unwrap();
+        scene.settings.limits.roulette_depth = 1000;
+        let scene = scene.build().unwrap();
+        for r in rays() {
+            let expected = mean_radiance(&r, &scene, 4000);
+            let mean = mean_radiance(&r, &roulette, 4000);
+            let error = (mean - expected).length() / expected.length();
+            assert!(error < 0.03, "{}", error);
+        }
+    }
+    // A glowing medium that absorbs but doesn't scatter gives off its
+    // emission times the fraction of light it stops, which only comes out
+    // right if emission is picked up before the albedo ends the path
+    #[test]
+    fn emissive_medium_glows() {
+        let grid = VoxelGrid::new(1, 1, 1, vec![1.0], vec![1.0]);
+        let corner = Vector3::new(1.0, 1.0, 1.0);
+        let mut objects = HitableList::new();
+        objects.add(GridMedium::new(
+            AABB::new(-corner, corner),
+            Arc::new(grid),
+            0.5,
+            Vector3::zero(),
+            PhaseFunction::Isotropic,
+            Vector3::new(2.0, 2.0, 2.0),
+        ));
+        let mut scene = Scene::new(objects);
+        scene.background = Background::Constant(Vector3::zero());
+        let scene = scene.build().unwrap();
+
+        let from = Vector3::new(-5.0, 0.0, 0.0);
+        let cam: Arc<dyn CameraModel> = Arc::new(Camera::new(
+            from,
+            Vector3::zero(),
+            Vector3::new(0.0, 1.0, 0.0),
+            60.0,
+            1.0,
+            0.0,
+            5.0,
+        ));
+        let integrators: Vec<Box<dyn Integrator<BVHNode>>> =
+            vec![Box::new(PathTracer), Box::new(Bdpt::new(cam, 4))];
+        let r = Ray::new(from, Vector3::new(1.0, 0.0, 0.0));
+        // Straight through the box, with a density of 0.5 over 2 units
+        let expected = 2.0 * (1.0 - (-1.0f32).exp());
+        for integrator in integrators {
+            let samples = 8000;
+            let total = (0..samples).fold(0.0, |total, seed| {
+                let sample = integrator.sample(&r, &scene, &mut Sampler::new(seed));
+                total + sample.radiance.total().x()
+            });
+            let mean = total / samples as f32;
+            assert!((mean - expected).abs() < 0.05, "{}", mean);
+        }
+    }
+}
@@ -0,0 +1,576 @@
+use crate::aabb::AABB;
+use crate::distribution::Distribution1D;
+use crate::emission::{Emission, Power};
+use crate::hitable::{HitRecord, Hitable};
+use crate::material::Material;
+use crate::random::Sampler;
+use crate::ray::Ray;
+use crate::sphere::{self, Sphere};
+use crate::triangle::Triangle;
+use crate::vec3::Vector3;
+use std::f32;
+use std::sync::Arc;
+
+// Lights in a scene. Punctual lights have no area, so can only be reached
+// by shadow rays. Intensities are RGB, like material colours.
+#[derive(Clone)]
+pub enum Light {
+    // Shines equally in all directions, falling off with the square of the
+    // distance
+    Point {
+        position: Vector3,
+        intensity: Vector3,
+    },
+    // A point light limited to a cone, fading out between the inner and
+    // outer angles, in degrees from its axis
+    Spot {
+        position: Vector3,
+        direction: Vector3,
+        intensity: Vector3,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+    // Parallel light from infinitely far away, e.g. the sun. direction is
+    // the way the light travels, and irradiance what a surface facing it
+    // receives.
+    Directional {
+        direction: Vector3,
+        irradiance: Vector3,
+    },
+    // Emissive geometry that is also in the scene, sampled by area
+    Area(Arc<MeshLight>),
+    Sphere(Arc<SphereLight>),
+}
+
+// Light arriving at a point: where from, how far away the light is, and
+// how much of it there is
+pub struct LightSample {
+    pub direction: Vector3,
+    pub distance: f32,
+    pub radiance: Vector3,
+    // Density the direction was picked with, None for punctual lights
+    pub pdf: Option<f32>,
+    // The light's surface normal at the sampled point, zero for punctual
+    // lights
+    pub normal: Vector3,
+}
+
+// A ray of light leaving a light, to start a path traced from it. The
+// radiance, or intensity for punctual lights, is along the ray. Densities
+// are per unit area for the origin and per steradian for the direction.
+pub struct EmissionSample {
+    pub origin: Vector3,
+    pub direction: Vector3,
+    // Zero for punctual lights
+    pub normal: Vector3,
+    pub radiance: Vector3,
+    pub pdf_pos: f32,
+    pub pdf_dir: f32,
+}
+
+// Cosine weighted around the unit vector n
+fn cosine_direction(n: &Vector3, sampler: &mut Sampler) -> Vector3 {
+    let p = sampler.in_unit_disk();
+    let z = (1.0 - p.x() * p.x() - p.y() * p.y()).max(0.0).sqrt();
+    let (u, v) = n.orthonormal_basis();
+    p.x() * u + p.y() * v + z * *n
+}
+
+// Smooth falloff from 0 at cos_outer to 1 at cos_inner, as in PBRT
+fn smooth_step(cos_outer: f32, cos_inner: f32, cos_theta: f32) -> f32 {
+    if cos_inner <= cos_outer {
+        return if cos_theta >= cos_inner { 1.0 } else { 0.0 };
+    }
+    let t = ((cos_theta - cos_outer) / (cos_inner - cos_outer)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+impl Light {
+    // Light reaching p, ignoring anything in the way. None if it doesn't
+    // shine on p at all.
+    pub fn sample(&self, p: &Vector3, sampler: &mut Sampler) -> Option<LightSample> {
+        match self {
+            Light::Point {
+                position,
+                intensity,
+            } => {
+                let to_light = *position - *p;
+                // No direction to it, and infinitely bright
+                if to_light.length_squared() == 0.0 {
+                    return None;
+                }
+                let distance = to_light.length();
+                Some(LightSample {
+                    direction: to_light / distance,
+                    distance,
+                    radiance: *intensity / (distance * distance),
+                    pdf: None,
+                    normal: Vector3::zero(),
+                })
+            }
+            Light::Spot {
+                position,
+                direction,
+                intensity,
+                inner_angle,
+                outer_angle,
+            } => {
+                let to_light = *position - *p;
+                // No direction to it, and infinitely bright
+                if to_light.length_squared() == 0.0 {
+                    return None;
+                }
+                let distance = to_light.length();
+                let cos_theta = direction.get_unit_vector().dot(&(-to_light / distance));
+                let falloff = smooth_step(
+                    outer_angle.to_radians().cos(),
+                    inner_angle.to_radians().cos(),
+                    cos_theta,
+                );
+                if falloff == 0.0 {
+                    return None;
+                }
+                Some(LightSample {
+                    direction: to_light / distance,
+                    distance,
+                    radiance: *intensity * (falloff / (distance * distance)),
+                    pdf: None,
+                    normal: Vector3::zero(),
+                })
+            }
+            Light::Directional {
+                direction,
+                irradiance,
+            } => Some(LightSample {
+                direction: -direction.get_unit_vector(),
+                distance: f32::MAX,
+                radiance: *irradiance,
+                pdf: None,
+                normal: Vector3::zero(),
+            }),
+            Light::Area(mesh) => mesh.sample(p, sampler),
+            Light::Sphere(sphere) => sphere.sample(p, sampler),
+        }
+    }
+
+    // Picks a ray of light leaving the light, for paths traced from it.
+    // Directional lights shine through a disk as wide as the sphere, given
+    // by its centre and radius, that bounds the scene. None if the light
+    // gives nothing off that way.
+    pub fn sample_emission(
+        &self,
+        center: &Vector3,
+        radius: f32,
+        sampler: &mut Sampler,
+    ) -> Option<EmissionSample> {
+        let sample = match self {
+            Light::Point {
+                position,
+                intensity,
+            } => EmissionSample {
+                origin: *position,
+                direction: sampler.unit_vector(),
+                normal: Vector3::zero(),
+                radiance: *intensity,
+                pdf_pos: 1.0,
+                pdf_dir: 1.0 / (4.0 * f32::consts::PI),
+            },
+            Light::Spot {
+                position,
+                direction,
+                intensity,
+                inner_angle,
+                outer_angle,
+            } => {
+                // Uniformly within the outer cone
+                let cos_outer = outer_angle.to_radians().cos();
+                let cos_theta = 1.0 - sampler.uniform() * (1.0 - cos_outer);
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let phi = 2.0 * f32::consts::PI * sampler.uniform();
+                let w = direction.get_unit_vector();
+                let (u, v) = w.orthonormal_basis();
+                let falloff =
+                    smooth_step(cos_outer, inner_angle.to_radians().cos(), cos_theta);
+                EmissionSample {
+                    origin: *position,
+                    direction: sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * w,
+                    normal: Vector3::zero(),
+                    radiance: *intensity * falloff,
+                    pdf_pos: 1.0,
+                    pdf_dir: self.pdf_emission(&Vector3::zero(), &w, radius).1,
+                }
+            }
+            Light::Directional {
+                direction,
+                irradiance,
+            } => {
+                let w = direction.get_unit_vector();
+                let (u, v) = w.orthonormal_basis();
+                let p = sampler.in_unit_disk();
+                EmissionSample {
+                    origin: *center + radius * (p.x() * u + p.y() * v - w),
+                    direction: w,
+                    normal: Vector3::zero(),
+                    radiance: *irradiance,
+                    pdf_pos: 1.0 / (f32::consts::PI * radius * radius),
+                    pdf_dir: 1.0,
+                }
+            }
+            Light::Area(mesh) => mesh.sample_emission(sampler),
+            Light::Sphere(sphere) => sphere.sample_emission(sampler),
+        };
+        if sample.radiance == Vector3::zero() || sample.pdf_dir == 0.0 {
+            None
+        } else {
+            Some(sample)
+        }
+    }
+
+    // Densities with which sample_emission() picks a ray's origin and its
+    // direction, given the light's normal there. radius bounds the scene
+    // as for sample_emission(). A directional light's only direction has
+    // density 1.
+    pub fn pdf_emission(&self, normal: &Vector3, direction: &Vector3, radius: f32) -> (f32, f32) {
+        let direction = direction.get_unit_vector();
+        match self {
+            Light::Point { .. } => (1.0, 1.0 / (4.0 * f32::consts::PI)),
+            Light::Spot {
+                direction: axis,
+                outer_angle,
+                ..
+            } => {
+                let cos_outer = outer_angle.to_radians().cos();
+                if direction.dot(&axis.get_unit_vector()) < cos_outer || cos_outer >= 1.0 {
+                    (1.0, 0.0)
+                } else {
+                    (1.0, 1.0 / (2.0 * f32::consts::PI * (1.0 - cos_outer)))
+                }
+            }
+            Light::Directional { .. } => (1.0 / (f32::consts::PI * radius * radius), 1.0),
+            Light::Area(mesh) => {
+                let cosine = normal.dot(&direction);
+                if mesh.emission.two_sided {
+                    (1.0 / mesh.area, 0.5 * cosine.abs() / f32::consts::PI)
+                } else {
+                    (1.0 / mesh.area, cosine.max(0.0) / f32::consts::PI)
+                }
+            }
+            Light::Sphere(sphere) => {
+                let area = 4.0 * f32::consts::PI * sphere.radius * sphere.radius;
+                (1.0 / area, normal.dot(&direction).max(0.0) / f32::consts::PI)
+            }
+        }
+    }
+
+    pub fn is_punctual(&self) -> bool {
+        !matches!(self, Light::Area(_) | Light::Sphere(_))
+    }
+
+    // Bounds of the light's surfaces
+    pub fn bounding_box(&self) -> Option<AABB> {
+        match self {
+            Light::Area(mesh) => Some(mesh.bbox.clone()),
+            Light::Sphere(sphere) => sphere.sphere.bounding_box(),
+            _ => None,
+        }
+    }
+
+    // Roughly how much light it gives off, for choosing between lights
+    pub fn power(&self) -> f32 {
+        match self {
+            Light::Area(mesh) => mesh.emission.luminous_power(mesh.area),
+            Light::Sphere(sphere) => {
+                // Only the outside of a sphere can be seen
+                let area = 4.0 * f32::consts::PI * sphere.radius * sphere.radius;
+                sphere.emission.luminous_power(area) / 2.0
+            }
+            _ => 0.0,
+        }
+    }
+
+    // Density with which sample() would pick the direction of r, which
+    // hit this light's surface at hr
+    pub fn pdf(&self, r: &Ray, hr: &HitRecord) -> f32 {
+        match self {
+            Light::Area(mesh) => {
+                let distance = hr.t * r.direction().length();
+                let cosine = hr.normal.dot(&r.direction().get_unit_vector()).abs();
+                if cosine == 0.0 {
+                    0.0
+                } else {
+                    distance * distance / (cosine * mesh.area)
+                }
+            }
+            Light::Sphere(sphere) => sphere.pdf(&r.origin()),
+            _ => 0.0,
+        }
+    }
+}
+
+// Any triangle mesh turned into a light. Points on it are sampled
+// uniformly by area.
+pub struct MeshLight {
+    triangles: Vec<Triangle>,
+    distribution: Distribution1D,
+    area: f32,
+    bbox: AABB,
+    emission: Arc<Emission>,
+}
+
+impl MeshLight {
+    // The triangles' materials are replaced by the emission, scaled to
+    // give off `power` in total if it is given
+    pub fn new(triangles: Vec<Triangle>, emission: Emission, power: Option<Power>) -> MeshLight {
+        let areas: Vec<f32> = triangles.iter().map(|t| t.area()).collect();
+        let area = areas.iter().sum();
+        let emission = Arc::new(match power {
+            Some(power) => emission.with_power(power, area),
+            None => emission,
+        });
+        let bbox = triangles
+            .iter()
+            .filter_map(|t| t.bounding_box())
+            .reduce(|a, b| a.surrounding_box(&b))
+            .expect("A mesh light needs at least one triangle");
+
+        let material = Material::DiffuseLight {
+            emission: emission.clone(),
+            light: None,
+        };
+        MeshLight {
+            triangles: triangles
+                .into_iter()
+                .map(|t| t.with_material(material.clone()))
+                .collect(),
+            distribution: Distribution1D::new(areas),
+            area,
+            bbox,
+            emission,
+        }
+    }
+
+    // The same light, with its surfaces marked as the scene's light index
+    pub fn numbered(self, index: Option<usize>) -> MeshLight {
+        let material = Material::DiffuseLight {
+            emission: self.emission.clone(),
+            light: index,
+        };
+        MeshLight {
+            triangles: self
+                .triangles
+                .into_iter()
+                .map(|t| t.with_material(material.clone()))
+                .collect(),
+            ..self
+        }
+    }
+
+    // With the light's material, to be added to the scene
+    pub fn triangles(&self) -> &[Triangle] {
+        &self.triangles
+    }
+
+    // A point by area, and a cosine weighted direction from the side it
+    // was picked on
+    fn sample_emission(&self, sampler: &mut Sampler) -> EmissionSample {
+        let (index, _) = self.distribution.sample_discrete(sampler.uniform());
+        let triangle = &self.triangles[index];
+        let (origin, (u, v)) = triangle.sample(sampler.uniform(), sampler.uniform());
+        let normal = triangle.normal();
+        let side = if self.emission.two_sided && sampler.uniform() < 0.5 {
+            -normal
+        } else {
+            normal
+        };
+        let direction = cosine_direction(&side, sampler);
+        let cos_theta = normal.dot(&direction);
+        let sides = if self.emission.two_sided { 0.5 } else { 1.0 };
+        EmissionSample {
+            origin,
+            direction,
+            normal,
+            radiance: self.emission.radiance(u, v, cos_theta),
+            pdf_pos: 1.0 / self.area,
+            pdf_dir: sides * cos_theta.abs() / f32::consts::PI,
+        }
+    }
+
+    fn sample(&self, p: &Vector3, sampler: &mut Sampler) -> Option<LightSample> {
+        let (index, _) = self.distribution.sample_discrete(sampler.uniform());
+        let triangle = &self.triangles[index];
+        let (point, (u, v)) = triangle.sample(sampler.uniform(), sampler.uniform());
+
+        let to_light = point - *p;
+        let distance = to_light.length();
+        if distance == 0.0 {
+            return None;
+        }
+        let direction = to_light / distance;
+        let cos_theta = -triangle.normal().dot(&direction);
+        let radiance = self.emission.radiance(u, v, cos_theta);
+        if cos_theta == 0.0 || radiance == Vector3::zero() {
+            return None;
+        }
+        // Converted from per unit area to per unit solid angle
+        Some(LightSample {
+            direction,
+            distance,
+            radiance,
+            pdf: Some(distance * distance / (cos_theta.abs() * self.area)),
+            normal: triangle.normal(),
+        })
+    }
+}
+
+// An emissive sphere, sampled by the cone of directions it covers as seen
+// from the shading point, as in Ray Tracing: The Rest of Your Life
+pub struct SphereLight {
+    center: Vector3,
+    radius: f32,
+    emission: Arc<Emission>,
+    sphere: Sphere,
+}
+
+impl SphereLight {
+    pub fn new(center: Vector3, radius: f32, emission: Emission) -> SphereLight {
+        let emission = Arc::new(emission);
+        SphereLight {
+            center,
+            radius,
+            sphere: Sphere::new(
+                center,
+                radius,
+                Material::DiffuseLight {
+                    emission: emission.clone(),
+                    light: None,
+                },
+            ),
+            emission,
+        }
+    }
+
+    // The same light, with its surface marked as the scene's light index
+    pub fn numbered(self, index: Option<usize>) -> SphereLight {
+        let material = Material::DiffuseLight {
+            emission: self.emission.clone(),
+            light: index,
+        };
+        SphereLight {
+            sphere: Sphere::new(self.center, self.radius, material),
+            ..self
+        }
+    }
+
+    // With the light's material, to be added to the scene
+    pub fn sphere(&self) -> &Sphere {
+        &self.sphere
+    }
+
+    // Cosine of the half angle of the cone the sphere covers from p. None
+    // from inside the sphere.
+    fn cos_theta_max(&self, p: &Vector3) -> Option<f32> {
+        let distance_squared = (self.center - *p).length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            None
+        } else {
+            Some((1.0 - radius_squared / distance_squared).sqrt())
+        }
+    }
+
+    fn pdf(&self, p: &Vector3) -> f32 {
+        match self.cos_theta_max(p) {
+            Some(cos_theta_max) if cos_theta_max < 1.0 => {
+                1.0 / (2.0 * f32::consts::PI * (1.0 - cos_theta_max))
+            }
+            _ => 0.0,
+        }
+    }
+
+    // A point by area, and a cosine weighted direction out from it
+    fn sample_emission(&self, sampler: &mut Sampler) -> EmissionSample {
+        let normal = sampler.unit_vector();
+        let direction = cosine_direction(&normal, sampler);
+        let cos_theta = normal.dot(&direction);
+        let (u, v) = sphere::sphere_uv(&normal);
+        EmissionSample {
+            origin: self.center + self.radius * normal,
+            direction,
+            normal,
+            radiance: self.emission.radiance(u, v, cos_theta),
+            pdf_pos: 1.0 / (4.0 * f32::consts::PI * self.radius * self.radius),
+            pdf_dir: cos_theta.max(0.0) / f32::consts::PI,
+        }
+    }
+
+    fn sample(&self, p: &Vector3, sampler: &mut Sampler) -> Option<LightSample> {
+        let cos_theta_max = self.cos_theta_max(p)?;
+        let cos_theta = 1.0 + sampler.uniform() * (cos_theta_max - 1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * f32::consts::PI * sampler.uniform();
+        let w = (self.center - *p).get_unit_vector();
+        let (u, v) = w.orthonormal_basis();
+        let direction = sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * w;
+
+        // Where the direction meets the sphere
+        let hr = self.sphere.hit(&Ray::new(*p, direction), 0.0, f32::MAX)?;
+        let (tex_u, tex_v) = sphere::sphere_uv(&hr.normal);
+        let radiance = self
+            .emission
+            .radiance(tex_u, tex_v, -hr.normal.dot(&direction));
+        let pdf = self.pdf(p);
+        if radiance == Vector3::zero() || pdf == 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction,
+            distance: hr.t,
+            radiance,
+            pdf: Some(pdf),
+            normal: hr.normal,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn point_light_falls_off_with_distance() {
+        let light = Light::Point {
+            position: Vector3::new(0.0, 4.0, 0.0),
+            intensity: Vector3::new(16.0, 16.0, 16.0),
+        };
+        let mut sampler = Sampler::new(0);
+        let mut sample_at = |p: Vector3| light.sample(&p, &mut sampler);
+        let sample = sample_at(Vector3::zero()).unwrap();
+        assert_eq!(sample.distance, 4.0);
+        assert!(sample.direction == Vector3::new(0.0, 1.0, 0.0));
+        assert!(sample.radiance == Vector3::new(1.0, 1.0, 1.0));
+        // Nothing at the light itself
+        assert!(sample_at(Vector3::new(0.0, 4.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn spot_light_cone() {
+        let light = Light::Spot {
+            position: Vector3::new(0.0, 1.0, 0.0),
+            direction: Vector3::new(0.0, -1.0, 0.0),
+            intensity: Vector3::new(1.0, 1.0, 1.0),
+            inner_angle: 20.0,
+            outer_angle: 30.0,
+        };
+        let mut sampler = Sampler::new(0);
+        let mut sample_at = |p: Vector3| light.sample(&p, &mut sampler);
+        // Straight below, at 25 degrees, and outside the cone
+        let full = sample_at(Vector3::zero()).unwrap().radiance.x();
+        let edge = 25f32.to_radians().tan();
+        let partial = sample_at(Vector3::new(edge, 0.0, 0.0)).unwrap();
+        assert_eq!(full, 1.0);
+        assert!(partial.radiance.x() * partial.distance * partial.distance < 0.9);
+        assert!(partial.radiance.x() > 0.0);
+        assert!(sample_at(Vector3::new(1.0, 0.0, 0.0)).is_none());
+        assert!(sample_at(Vector3::new(0.0, 1.0, 0.0)).is_none());
+    }
+}
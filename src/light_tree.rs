@@ -0,0 +1,269 @@
+use crate::aabb::AABB;
+use crate::hitable::HitRecord;
+use crate::light::Light;
+use crate::material::Material;
+use crate::random::Sampler;
+use crate::ray::Ray;
+use crate::vec3::Vector3;
+
+// A cluster of lights, with their combined bounds and power
+enum LightTreeNode {
+    Leaf {
+        light: usize,
+        bbox: AABB,
+        power: f32,
+    },
+    Interior {
+        left: Box<LightTreeNode>,
+        right: Box<LightTreeNode>,
+        bbox: AABB,
+        power: f32,
+    },
+}
+
+impl LightTreeNode {
+    fn new(
+        lights: &mut [(usize, AABB, f32)],
+        trail: u64,
+        depth: u32,
+        trails: &mut [u64],
+    ) -> LightTreeNode {
+        if lights.len() == 1 {
+            let (light, bbox, power) = lights[0].clone();
+            trails[light] = trail;
+            return LightTreeNode::Leaf { light, bbox, power };
+        }
+
+        // Split at the median along the axis the lights' centres spread
+        // furthest in
+        let centre = |bbox: &AABB| 0.5 * (bbox.min() + bbox.max());
+        let bounds = lights
+            .iter()
+            .map(|(_, bbox, _)| AABB::new(centre(bbox), centre(bbox)))
+            .reduce(|a, b| a.surrounding_box(&b))
+            .unwrap();
+        let extent = bounds.max() - bounds.min();
+        let axis = if extent.x() > extent.y() && extent.x() > extent.z() {
+            0
+        } else if extent.y() > extent.z() {
+            1
+        } else {
+            2
+        };
+        lights.sort_by(|a, b| {
+            centre(&a.1)
+                .component(axis)
+                .partial_cmp(&centre(&b.1).component(axis))
+                .unwrap()
+        });
+
+        let (left, right) = lights.split_at_mut(lights.len() / 2);
+        let left = LightTreeNode::new(left, trail, depth + 1, trails);
+        let right = LightTreeNode::new(right, trail | (1 << depth), depth + 1, trails);
+        LightTreeNode::Interior {
+            bbox: left.bbox().surrounding_box(right.bbox()),
+            power: left.power() + right.power(),
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    fn bbox(&self) -> &AABB {
+        match self {
+            LightTreeNode::Leaf { bbox, .. } | LightTreeNode::Interior { bbox, .. } => bbox,
+        }
+    }
+
+    fn power(&self) -> f32 {
+        match self {
+            LightTreeNode::Leaf { power, .. } | LightTreeNode::Interior { power, .. } => *power,
+        }
+    }
+
+    // Estimate of how much the cluster lights p: its power over the squared
+    // distance to it, but no closer than the cluster's own size
+    fn importance(&self, p: &Vector3) -> f32 {
+        let bbox = self.bbox();
+        let centre = 0.5 * (bbox.min() + bbox.max());
+        let distance_squared = (*p - centre).length_squared();
+        let size_squared = (bbox.max() - bbox.min()).length_squared() / 4.0;
+        let importance = self.power() / distance_squared.max(size_squared);
+        if importance.is_finite() {
+            importance
+        } else {
+            0.0
+        }
+    }
+}
+
+// Probability of going left out of the two children, given their importance
+fn left_probability(left: f32, right: f32) -> Option<f32> {
+    if left + right > 0.0 {
+        Some(left / (left + right))
+    } else {
+        None
+    }
+}
+
+// Picks one of the scene's area lights for a shading point, favouring
+// bright and nearby ones. A bounding volume hierarchy over the lights
+// estimates how much each cluster contributes, so only one path down the
+// tree is followed.
+pub struct LightTree {
+    root: Option<LightTreeNode>,
+    // The way down to each light: bit i set means right at depth i
+    trails: Vec<u64>,
+}
+
+impl LightTree {
+    pub fn new(lights: &[Light]) -> LightTree {
+        let mut leaves = Vec::new();
+        for (index, light) in lights.iter().enumerate() {
+            if let Some(bbox) = light.bounding_box() {
+                leaves.push((index, bbox, light.power()));
+            }
+        }
+
+        let mut trails = vec![0; lights.len()];
+        let root = if leaves.is_empty() {
+            None
+        } else {
+            Some(LightTreeNode::new(&mut leaves, 0, 0, &mut trails))
+        };
+        LightTree { root, trails }
+    }
+
+    // A light's index, and the probability it was picked with
+    pub fn sample(&self, p: &Vector3, sampler: &mut Sampler) -> Option<(usize, f32)> {
+        let mut node = self.root.as_ref()?;
+        let mut pmf = 1.0;
+        loop {
+            match node {
+                LightTreeNode::Leaf { light, .. } => return Some((*light, pmf)),
+                LightTreeNode::Interior { left, right, .. } => {
+                    let p_left = left_probability(left.importance(p), right.importance(p))?;
+                    if sampler.uniform() < p_left {
+                        pmf *= p_left;
+                        node = left;
+                    } else {
+                        pmf *= 1.0 - p_left;
+                        node = right;
+                    }
+                }
+            }
+        }
+    }
+
+    // Probability that sample(p) picks the light
+    fn pmf(&self, p: &Vector3, light: usize) -> f32 {
+        let mut node = match &self.root {
+            Some(root) => root,
+            None => return 0.0,
+        };
+        let mut pmf = 1.0;
+        let mut depth = 0;
+        loop {
+            match node {
+                LightTreeNode::Leaf { light: leaf, .. } => {
+                    return if *leaf == light { pmf } else { 0.0 };
+                }
+                LightTreeNode::Interior { left, right, .. } => {
+                    let p_left = match left_probability(left.importance(p), right.importance(p)) {
+                        Some(p_left) => p_left,
+                        None => return 0.0,
+                    };
+                    if self.trails[light] & (1 << depth) == 0 {
+                        pmf *= p_left;
+                        node = left;
+                    } else {
+                        pmf *= 1.0 - p_left;
+                        node = right;
+                    }
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    // The light whose surface was hit at hr, if it is one
+    pub fn light_index(&self, hr: &HitRecord) -> Option<usize> {
+        match &hr.material {
+            Material::DiffuseLight { light, .. } => *light,
+            _ => None,
+        }
+    }
+
+    // Density with which light sampling from the start of r would have
+    // picked its direction, given it hit a light's surface at hr. 0 if it
+    // isn't a sampled light.
+    pub fn pdf(&self, lights: &[Light], r: &Ray, hr: &HitRecord) -> f32 {
+        match self.light_index(hr) {
+            Some(light) => self.pmf(&r.origin(), light) * lights[light].pdf(r, hr),
+            None => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emission::Emission;
+    use crate::hitable::{Hitable, HitableList};
+    use crate::light::SphereLight;
+    use crate::scene::Scene;
+    use crate::sphere::Sphere;
+    use std::f32;
+    use std::sync::Arc;
+
+    #[test]
+    fn pmf_matches_sample() {
+        let lights: Vec<Light> = (0..7)
+            .map(|i| {
+                Light::Sphere(Arc::new(SphereLight::new(
+                    Vector3::new(i as f32 * 2.0, 1.0, 0.0),
+                    0.5,
+                    Emission::new(Vector3::new(1.0, 1.0, 1.0) * (i + 1) as f32),
+                )))
+            })
+            .collect();
+        let tree = LightTree::new(&lights);
+        let p = Vector3::new(3.0, 0.0, 1.0);
+
+        let total: f32 = (0..lights.len()).map(|i| tree.pmf(&p, i)).sum();
+        assert!((total - 1.0).abs() < 0.0001);
+        let mut sampler = Sampler::new(0);
+        for _ in 0..20 {
+            let (light, pmf) = tree.sample(&p, &mut sampler).unwrap();
+            assert!((tree.pmf(&p, light) - pmf).abs() < 0.0001);
+        }
+        // The nearby lights are picked more often than the far, brighter one
+        assert!(tree.pmf(&p, 1) > tree.pmf(&p, 6));
+    }
+    // Identical lights are still told apart, and emissive surfaces that
+    // aren't lights aren't mistaken for one
+    #[test]
+    fn hits_find_their_light() {
+        let white = Vector3::new(1.0, 1.0, 1.0);
+        let mut objects = HitableList::new();
+        objects.add(Sphere::new(
+            Vector3::new(0.0, 0.0, -4.0),
+            0.5,
+            Material::diffuse_light(white),
+        ));
+        let mut scene = Scene::new(objects);
+        for x in [-2.0, 2.0] {
+            let center = Vector3::new(x, 0.0, 0.0);
+            scene.add_sphere_light(SphereLight::new(center, 0.5, Emission::new(white)));
+        }
+        let scene = scene.build().unwrap();
+        let from = Vector3::new(0.0, 0.0, 4.0);
+        let hit = |target: Vector3| {
+            let r = Ray::new(from, target - from);
+            let hr = scene.objects.hit(&r, 0.001, f32::MAX).unwrap();
+            scene.light_tree.light_index(&hr)
+        };
+        assert_eq!(hit(Vector3::new(-2.0, 0.0, 0.0)), Some(0));
+        assert_eq!(hit(Vector3::new(2.0, 0.0, 0.0)), Some(1));
+        assert_eq!(hit(Vector3::new(0.0, 0.0, -4.0)), None);
+    }
+}
@@ -1,134 +1,186 @@
 use std::f32;
+use std::ops::Range;
+use std::path::Path;
 
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::thread;
 
 extern crate clap;
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 
+use crate::aov::Aov;
+use crate::background::{Background, EnvironmentMap};
+use crate::bdpt::Bdpt;
 use crate::bvh::BVHNode;
-use crate::camera::Camera;
-use crate::hitable::Hitable;
-use crate::hitable::HitableList;
-use crate::material::Material;
-use crate::ray::Ray;
-use crate::sphere::Sphere;
+use crate::camera::{
+    Aperture, ApertureImage, Camera, CameraModel, Equirectangular, Fisheye, Orthographic, Shutter,
+};
+use crate::debug::DebugView;
+use crate::emission::AngularProfile;
+use crate::film::{Film, Filter};
+use crate::heightfield::HeightMap;
+use crate::hitable::{Hitable, Tagged};
+use crate::integrator::{Integrator, PathTracer, Radiance, Sample};
+use crate::material::PhaseFunction;
+use crate::medium::Fog;
+use crate::photon::Sppm;
+use crate::random::Sampler;
+use crate::scene::{PathLimits, RenderSettings, Scene};
+use crate::sky::Sky;
+use crate::texture::{ImageTexture, Texture};
 use crate::vec3::Vector3;
+use crate::voxel::VoxelGrid;
 
 mod aabb;
+mod aov;
+mod background;
+mod bdpt;
 mod bvh;
 mod camera;
+mod csg;
+mod cuboid;
+mod debug;
+mod denoise;
+mod distribution;
+mod emission;
+mod film;
+mod heightfield;
 mod hitable;
 mod image_out;
+mod instance;
+mod integrator;
+mod light;
+mod light_tree;
 mod material;
+mod medium;
+mod metaballs;
+mod motion;
+mod perlin;
+mod photon;
+mod polynomial;
+mod quadric;
 mod random;
 mod ray;
+mod scene;
+mod scenes;
+mod sdf;
+mod sky;
+mod spectrum;
 mod sphere;
+mod texture;
+mod torus;
 mod triangle;
 mod vec3;
+mod voxel;
 
-fn color<T: Hitable>(r: &Ray, scene_object: &T, depth: u32) -> Vector3 {
-    match scene_object.hit(r, 0.001, f32::MAX) {
-        Some(hr) => {
-            if depth < 50 {
-                let scatter = hr.material.scatter(r, &hr);
-                match scatter {
-                    Some(s) => hr.material.emitted() + s.color * color(&s.ray, scene_object, depth + 1),
-                    None => hr.material.emitted()
-                }
-            } else {
-                Vector3::zero()
-            }
+// The camera at a time in the scene's camera track, with the lens and
+// projection options applied. The aperture image is loaded once for all the
+// frames.
+fn make_camera(
+    matches: &ArgMatches,
+    scene: &Scene<BVHNode>,
+    aperture_image: Option<&Arc<ApertureImage>>,
+    time: f32,
+    x_res: u32,
+    y_res: u32,
+) -> Arc<dyn CameraModel> {
+    let track = &scene.camera;
+    let mut lookfrom = track.lookfrom.at(time);
+    let lookat = track.lookat.at(time);
+    if let Some(frames) = matches.value_of("turntable") {
+        // Around the vertical axis through lookat
+        let frames: f32 = frames.parse().unwrap();
+        let (sin, cos) = (2.0 * f32::consts::PI * time / frames).sin_cos();
+        let offset = lookfrom - lookat;
+        lookfrom = lookat
+            + Vector3::new(
+                cos * offset.x() + sin * offset.z(),
+                offset.y(),
+                -sin * offset.x() + cos * offset.z(),
+            );
+    }
+    let vup = Vector3::new(0.0, 1.0, 0.0);
+    let dist_to_focus = (lookfrom - lookat).length();
+    let aspect = x_res as f32 / y_res as f32;
+    let projection = matches.value_of("projection").unwrap();
+    let fov: f32 = match matches.value_of("fov") {
+        Some(fov) => fov.parse().unwrap(),
+        None if projection == "fisheye" => 180.0,
+        None => track.vfov.at(time),
+    };
+    match projection {
+        "orthographic" => {
+            // Frames the focus distance as the perspective camera would.
+            // The rays start on a plane as big as the view, so it's moved
+            // back to keep its edges out of the scene.
+            let view_height = 2.0 * (fov.to_radians() / 2.0).tan() * dist_to_focus;
+            Arc::new(Orthographic::new(
+                lookat + 2.0 * (lookfrom - lookat),
+                lookat,
+                vup,
+                view_height,
+                aspect,
+            ))
         }
-        // Color background
-        None => {
-            Vector3::new(0.2, 0.2, 0.2)
+        "fisheye" => Arc::new(Fisheye::new(lookfrom, lookat, vup, fov, aspect)),
+        "equirectangular" => Arc::new(Equirectangular::new(lookfrom, lookat, vup)),
+        "ods" => Arc::new(
+            Equirectangular::new(lookfrom, lookat, vup)
+                .with_stereo(matches.value_of("eye_separation").unwrap().parse().unwrap()),
+        ),
+        _ => {
+            let aperture = match matches.value_of("aperture") {
+                Some(aperture) => aperture.parse().unwrap(),
+                None => track.aperture.at(time),
+            };
+            let mut cam = Camera::new(lookfrom, lookat, vup, fov, aspect, aperture, dist_to_focus)
+                .with_cat_eye(matches.value_of("cat_eye").unwrap().parse().unwrap());
+            if let Some(image) = aperture_image {
+                cam = cam.with_aperture(Aperture::Image(Arc::clone(image)));
+            } else if let Some(blades) = matches.value_of("blades") {
+                cam = cam.with_aperture(Aperture::Polygon {
+                    blades: blades.parse().unwrap(),
+                    rotation: matches.value_of("blade_rotation").unwrap().parse().unwrap(),
+                });
+            }
+            if let Some(pixel) = matches.value_of("focus_pixel") {
+                let (x, y) = pixel
+                    .split_once(',')
+                    .and_then(|(x, y)| {
+                        Some((x.trim().parse::<f32>().ok()?, y.trim().parse::<f32>().ok()?))
+                    })
+                    .unwrap_or_else(|| panic!("Expected the focus pixel as x,y, got {}", pixel));
+                // Pixel rows count down from the top
+                cam = cam.autofocus(
+                    &scene.objects,
+                    (x + 0.5) / x_res as f32,
+                    1.0 - (y + 0.5) / y_res as f32,
+                    time,
+                );
+            }
+            Arc::new(cam)
         }
     }
 }
 
-fn random_scene() -> HitableList {
-    use random::random_in_unit_interval as RandUnit;
-    let mut hitable_list = HitableList::new();
-
-    hitable_list.add(Sphere::new(
-        Vector3::new(0.0, -1000.0, 0.0),
-        1000.0,
-        Material::Lambertian {
-            albedo: Vector3::new(0.5, 0.5, 0.5),
-        },
-    ));
+// Parses "start..end"
+fn parse_frames(value: &str) -> Option<Range<u32>> {
+    let (start, end) = value.split_once("..")?;
+    Some(start.trim().parse().ok()?..end.trim().parse().ok()?)
+}
 
-    for a in -5..5 {
-        for b in -5..5 {
-            let choose_mat = RandUnit();
-            let x_rand = RandUnit();
-            let z_rand = RandUnit();
-            let center = Vector3::new(a as f32 + 0.9 * x_rand, 0.2, b as f32 + 0.9 * z_rand);
-            if (center - Vector3::new(4.0, 0.2, 0.0)).length() > 0.9 {
-                if choose_mat < 0.8 {
-                    hitable_list.add(Sphere::new(
-                        center,
-                        0.2,
-                        Material::Lambertian {
-                            albedo: Vector3::new(
-                                RandUnit() * RandUnit(),
-                                RandUnit() * RandUnit(),
-                                RandUnit() * RandUnit(),
-                            ),
-                        },
-                    ));
-                } else if choose_mat < 0.95 {
-                    hitable_list.add(Sphere::new(
-                        center,
-                        0.2,
-                        Material::Metal {
-                            albedo: Vector3::new(
-                                0.5 * (1.0 + RandUnit()),
-                                0.5 * (1.0 + RandUnit()),
-                                0.5 * (1.0 + RandUnit()),
-                            ),
-                            fuzz: 0.5 * RandUnit(),
-                        },
-                    ));
-                } else {
-                    hitable_list.add(Sphere::new(center, 0.2, Material::Dielectric { ri: 1.5 }));
-                }
-            }
-        }
+// Parses "r,g,b"
+fn parse_vector(value: &str) -> Option<Vector3> {
+    let parts: Vec<f32> = value
+        .split(',')
+        .map(|part| part.trim().parse())
+        .collect::<Result<_, _>>()
+        .ok()?;
+    if parts.len() == 3 {
+        Some(Vector3::new(parts[0], parts[1], parts[2]))
+    } else {
+        None
     }
-    hitable_list.add(Sphere::new(
-        Vector3::new(0.0, 1.0, 0.0),
-        1.0,
-        Material::Dielectric { ri: 1.5 },
-    ));
-    hitable_list.add(Sphere::new(
-        Vector3::new(-4.0, 1.0, 0.0),
-        1.0,
-        Material::Lambertian {
-            albedo: Vector3::new(0.4, 0.2, 0.1),
-        },
-    ));
-    hitable_list.add(Sphere::new(
-        Vector3::new(4.0, 1.0, 0.0),
-        1.0,
-        Material::Metal {
-            albedo: Vector3::new(0.7, 0.6, 0.5),
-            fuzz: 0.0,
-        },
-    ));
-
-
-    // Add a light to illuminate the scene
-    hitable_list.add(Sphere::new(
-        Vector3::new(1.0, 13.0, -10.0),
-        13.0,
-        Material::DiffuseLight {
-            color: Vector3::new(1.0, 0.54, 0.20),
-        },
-    ));
-
-    hitable_list
 }
 
 fn main() {
@@ -174,6 +226,280 @@ fn main() {
                 .help("Height of trace in pixels")
                 .default_value("100"),
         )
+        .arg(
+            Arg::with_name("filter")
+                .long("filter")
+                .takes_value(true)
+                .possible_values(&["box", "tent", "gaussian", "mitchell", "lanczos"])
+                .help("Pixel reconstruction filter. Box with its usual radius averages each pixel's own samples.")
+                .default_value("box"),
+        )
+        .arg(
+            Arg::with_name("filter_radius")
+                .long("filter_radius")
+                .takes_value(true)
+                .help("Radius of the filter in pixels. Defaults to 0.5 for box, 1 for tent, 1.5 for gaussian, 2 for mitchell and 3 for lanczos."),
+        )
+        .arg(
+            Arg::with_name("projection")
+                .long("projection")
+                .takes_value(true)
+                .possible_values(&["perspective", "orthographic", "fisheye", "equirectangular", "ods"])
+                .help("Camera projection. ods is a stereo equirectangular panorama, with the left eye on top. Lens options only apply to perspective.")
+                .default_value("perspective"),
+        )
+        .arg(
+            Arg::with_name("fov")
+                .long("fov")
+                .takes_value(true)
+                .help("Field of view in degrees: vertical for perspective and orthographic (at the focus distance), across the image circle for fisheye. Defaults to the scene's, or 180 for fisheye."),
+        )
+        .arg(
+            Arg::with_name("eye_separation")
+                .long("eye_separation")
+                .takes_value(true)
+                .help("Distance between the eyes for ods")
+                .default_value("0.064"),
+        )
+        .arg(
+            Arg::with_name("aperture")
+                .long("aperture")
+                .takes_value(true)
+                .help("Diameter of the lens, 0 for a pinhole. Defaults to the scene's."),
+        )
+        .arg(
+            Arg::with_name("blades")
+                .long("blades")
+                .takes_value(true)
+                .help("Number of aperture blades, for polygonal bokeh. Round if not given."),
+        )
+        .arg(
+            Arg::with_name("blade_rotation")
+                .long("blade_rotation")
+                .takes_value(true)
+                .help("Rotation of the polygonal aperture, in degrees")
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("aperture_image")
+                .long("aperture_image")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Greyscale image to use as the aperture's shape"),
+        )
+        .arg(
+            Arg::with_name("cat_eye")
+                .long("cat_eye")
+                .takes_value(true)
+                .help("Cat's eye vignetting: how far the lens barrel cuts into the aperture at the image corners, in lens radii")
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("focus_pixel")
+                .long("focus_pixel")
+                .takes_value(true)
+                .help("Focus on the surface seen through pixel x,y, counted from the top left"),
+        )
+        .arg(
+            Arg::with_name("frames")
+                .long("frames")
+                .takes_value(true)
+                .help("Render frames start..end of the scene's animation, not including end. The output file name needs a frame number, e.g. out_%04d.png."),
+        )
+        .arg(
+            Arg::with_name("turntable")
+                .long("turntable")
+                .takes_value(true)
+                .help("Orbit the camera around what it looks at, once every this many frames"),
+        )
+        .arg(
+            Arg::with_name("shutter_open")
+                .long("shutter_open")
+                .takes_value(true)
+                .help("Time the shutter opens in each frame, in frames, for motion blur")
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("shutter_close")
+                .long("shutter_close")
+                .takes_value(true)
+                .help("Time the shutter closes")
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("aovs")
+                .long("aovs")
+                .takes_value(true)
+                .help("Extra passes to render, separated by commas: depth, normal, albedo, position, id, material_id, direct and indirect. Written as layers of the output if it's an EXR, otherwise as images named after it, e.g. out_depth.png."),
+        )
+        .arg(
+            Arg::with_name("scene")
+                .long("scene")
+                .takes_value(true)
+                .possible_values(&["random", "smoke", "clouds", "outdoor", "lights", "area_lights", "many_lights", "motion", "csg", "sdf", "implicit", "terrain"])
+                .help("Scene to render")
+                .default_value("random"),
+        )
+        .arg(
+            Arg::with_name("volume")
+                .long("volume")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Dense voxel grid to use as the cloud in the clouds scene"),
+        )
+        .arg(
+            Arg::with_name("heightmap")
+                .long("heightmap")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Greyscale image to use as the ground in the terrain scene"),
+        )
+        .arg(
+            Arg::with_name("light_texture")
+                .long("light_texture")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Image to texture the panel light in the area_lights scene with"),
+        )
+        .arg(
+            Arg::with_name("light_profile")
+                .long("light_profile")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("IES file giving the angular profile of the panel light in the area_lights scene"),
+        )
+        .arg(
+            Arg::with_name("fog")
+                .long("fog")
+                .takes_value(true)
+                .help("Density of atmospheric fog filling the scene"),
+        )
+        .arg(
+            Arg::with_name("background")
+                .long("background")
+                .takes_value(true)
+                .help("Background colour as r,g,b, 'sky' for a gradient, 'daylight' for a physical sky, or an environment map image. Defaults to the scene's own."),
+        )
+        .arg(
+            Arg::with_name("env_rotation")
+                .long("env_rotation")
+                .takes_value(true)
+                .help("Rotation of the environment map around the vertical axis, in degrees")
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("env_intensity")
+                .long("env_intensity")
+                .takes_value(true)
+                .help("Multiplier for the environment map's or daylight sky's radiance")
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("sun_elevation")
+                .long("sun_elevation")
+                .takes_value(true)
+                .help("Angle of the sun above the horizon for the daylight sky, in degrees")
+                .default_value("35"),
+        )
+        .arg(
+            Arg::with_name("sun_azimuth")
+                .long("sun_azimuth")
+                .takes_value(true)
+                .help("Direction of the sun around the vertical axis for the daylight sky, in degrees")
+                .default_value("40"),
+        )
+        .arg(
+            Arg::with_name("turbidity")
+                .long("turbidity")
+                .takes_value(true)
+                .help("Haziness of the daylight sky, from 2 (clear) to 10")
+                .default_value("3"),
+        )
+        .arg(
+            Arg::with_name("max_diffuse")
+                .long("max_diffuse")
+                .takes_value(true)
+                .help("Most diffuse bounces a path can make")
+                .default_value("16"),
+        )
+        .arg(
+            Arg::with_name("max_glossy")
+                .long("max_glossy")
+                .takes_value(true)
+                .help("Most glossy bounces, off metals, a path can make")
+                .default_value("16"),
+        )
+        .arg(
+            Arg::with_name("max_transmission")
+                .long("max_transmission")
+                .takes_value(true)
+                .help("Most bounces through or off glass a path can make")
+                .default_value("32"),
+        )
+        .arg(
+            Arg::with_name("max_volume")
+                .long("max_volume")
+                .takes_value(true)
+                .help("Most scattering events in volumes a path can have")
+                .default_value("64"),
+        )
+        .arg(
+            Arg::with_name("roulette_depth")
+                .long("roulette_depth")
+                .takes_value(true)
+                .help("Bounces before paths carrying little light may be ended by Russian roulette")
+                .default_value("3"),
+        )
+        .arg(
+            Arg::with_name("integrator")
+                .long("integrator")
+                .takes_value(true)
+                .possible_values(&["path", "bdpt", "sppm", "ao", "normals", "uv", "cost", "furnace"])
+                .help("Path tracing from the camera, bidirectional path tracing, which also traces paths from the lights, or stochastic progressive photon mapping, for sharp caustics. With sppm, each sample is an iteration of photon tracing. The rest are views for debugging: ambient occlusion, normals, texture coordinates, a heatmap of BVH traversal cost, and a white furnace, which shows where light is lost.")
+                .default_value("path"),
+        )
+        .arg(
+            Arg::with_name("ao_distance")
+                .long("ao_distance")
+                .takes_value(true)
+                .help("Distance within which geometry occludes, for ambient occlusion")
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("photons")
+                .long("photons")
+                .takes_value(true)
+                .help("Photons traced each sppm iteration. Defaults to one per pixel."),
+        )
+        .arg(
+            Arg::with_name("photon_radius")
+                .long("photon_radius")
+                .takes_value(true)
+                .help("Radius sppm starts gathering photons from, in the scene's units")
+                .default_value("0.1"),
+        )
+        .arg(
+            Arg::with_name("max_depth")
+                .long("max_depth")
+                .takes_value(true)
+                .help("Most bounces a path can make with bidirectional path tracing")
+                .default_value("8"),
+        )
+        .arg(
+            Arg::with_name("light_sampling")
+                .long("light_sampling")
+                .help("Sample light sources directly, combined with BSDF sampling by MIS"),
+        )
+        .arg(
+            Arg::with_name("denoise")
+                .long("denoise")
+                .help("Denoise the image, guided by the albedo and normals seen through each pixel"),
+        )
+        .arg(
+            Arg::with_name("spectral")
+                .long("spectral")
+                .help("Trace sampled wavelengths instead of RGB"),
+        )
         .get_matches();
 
     let x_res: u32 = matches.value_of("x_res").unwrap().parse().unwrap();
@@ -181,83 +507,357 @@ fn main() {
     let num_samples: u32 = matches.value_of("samples").unwrap().parse().unwrap();
     let num_threads: u32 = matches.value_of("threads").unwrap().parse().unwrap();
     let output = matches.value_of("output");
+    let spectral = matches.is_present("spectral");
+    let light_sampling = matches.is_present("light_sampling");
+    let integrator_name = matches.value_of("integrator").unwrap();
+    let sppm = integrator_name == "sppm";
+    let ao_distance: f32 = matches.value_of("ao_distance").unwrap().parse().unwrap();
+    let photons: usize = match matches.value_of("photons") {
+        Some(photons) => photons.parse().unwrap(),
+        None => (x_res * y_res) as usize,
+    };
+    let photon_radius: f32 = matches.value_of("photon_radius").unwrap().parse().unwrap();
+    let max_depth: u32 = matches.value_of("max_depth").unwrap().parse().unwrap();
+    let denoise = matches.is_present("denoise");
+    let limits = PathLimits {
+        diffuse: matches.value_of("max_diffuse").unwrap().parse().unwrap(),
+        glossy: matches.value_of("max_glossy").unwrap().parse().unwrap(),
+        transmission: matches.value_of("max_transmission").unwrap().parse().unwrap(),
+        volume: matches.value_of("max_volume").unwrap().parse().unwrap(),
+        roulette_depth: matches.value_of("roulette_depth").unwrap().parse().unwrap(),
+    };
+    let filter = Filter::parse(
+        matches.value_of("filter").unwrap(),
+        matches
+            .value_of("filter_radius")
+            .map(|radius| radius.parse().unwrap()),
+    )
+    .unwrap();
 
-    let lookfrom = Vector3::new(13.0, 2.0, 3.0);
-    let lookat = Vector3::new(0.0, 0.0, 0.0);
-    let vup = Vector3::new(0.0, 1.0, 0.0);
-    let dist_to_focus = (lookfrom - lookat).length();
-    let aperture = 0.1;
-    let cam = Camera::new(
-        lookfrom,
-        lookat,
-        vup,
-        30.0,
-        x_res as f32 / y_res as f32,
-        aperture,
-        dist_to_focus,
-    );
-
-    let hitable_list = random_scene();
-    let bvh = BVHNode::new(hitable_list.hitables.clone());
-
-    let mut thread_handles = Vec::new();
-    let result = Arc::new(Mutex::new(Vec::new()));
-    let samples_per_thread = num_samples / num_threads;
-
-    for _ in 0..num_threads {
-        let mut thread_output = Vec::new();
-        let bvh_clone = bvh.clone();
-        let result = Arc::clone(&result);
-        // TODO Handle exceptions + num_samples not divisible by num_threads
-        thread_handles.push(thread::spawn(move || {
-            for j in (0..y_res).rev() {
-                for i in 0..x_res {
-                    let mut col = Vector3::new(0.0, 0.0, 0.0);
-                    for _s in 0..samples_per_thread {
-                        let u_rand = random::random_in_unit_interval();
-                        let v_rand = random::random_in_unit_interval();
-                        let u = (i as f32 + u_rand) / x_res as f32;
-                        let v = (j as f32 + v_rand) / y_res as f32;
-                        let ray = cam.get_ray(u, v);
-                        col = col + color(&ray, &bvh_clone, 0);
-                    }
-                    col = col / num_samples as f32;
-                    thread_output.push(col);
-                    // Do averaging in th thread
-                    // Then the final average at the end
-                }
+    let mut scene = match matches.value_of("scene").unwrap() {
+        "smoke" => scenes::smoke_scene(),
+        "clouds" => scenes::clouds_scene(matches.value_of("volume").map(|path| {
+            VoxelGrid::load(Path::new(path))
+                .unwrap_or_else(|e| panic!("Failed to load voxel grid {}: {}", path, e))
+        })),
+        "outdoor" => scenes::outdoor_scene(),
+        "lights" => scenes::lights_scene(),
+        "many_lights" => scenes::many_lights_scene(),
+        "csg" => scenes::csg_scene(),
+        "sdf" => scenes::sdf_scene(),
+        "implicit" => scenes::implicit_scene(),
+        "terrain" => scenes::terrain_scene(matches.value_of("heightmap").map(|path| {
+            HeightMap::load(Path::new(path))
+                .unwrap_or_else(|e| panic!("Failed to load height map {}: {}", path, e))
+        })),
+        "motion" => scenes::motion_scene(),
+        "area_lights" => scenes::area_lights_scene(
+            matches.value_of("light_texture").map(|path| {
+                Texture::Image(Arc::new(
+                    ImageTexture::load(Path::new(path))
+                        .unwrap_or_else(|e| panic!("Failed to load texture {}: {}", path, e)),
+                ))
+            }),
+            matches.value_of("light_profile").map(|path| {
+                AngularProfile::load_ies(Path::new(path))
+                    .unwrap_or_else(|e| panic!("Failed to load IES profile {}: {}", path, e))
+            }),
+        ),
+        _ => scenes::random_scene(),
+    };
+    let intensity: f32 = matches.value_of("env_intensity").unwrap().parse().unwrap();
+    if let Some(background) = matches.value_of("background") {
+        scene.background = match background {
+            "sky" => Background::Gradient {
+                bottom: Vector3::new(1.0, 1.0, 1.0),
+                top: Vector3::new(0.5, 0.7, 1.0),
+            },
+            "daylight" => {
+                let elevation = matches.value_of("sun_elevation").unwrap().parse().unwrap();
+                let azimuth = matches.value_of("sun_azimuth").unwrap().parse().unwrap();
+                let turbidity = matches.value_of("turbidity").unwrap().parse().unwrap();
+                Background::Sky(Sky::new(
+                    scenes::sun_direction(elevation, azimuth),
+                    turbidity,
+                    intensity,
+                ))
             }
-            let mut l_result = result.lock().unwrap();
-            if l_result.len() == 0 {
-                *l_result = thread_output;
-            } else {
-                *l_result = l_result
-                    .iter()
-                    .zip(thread_output.iter())
-                    .map(|(a, b)| a + b)
-                    .collect();
+            _ => match parse_vector(background) {
+                Some(color) => Background::Constant(color),
+                None => {
+                    let rotation: f32 = matches.value_of("env_rotation").unwrap().parse().unwrap();
+                    let map = EnvironmentMap::load(
+                        Path::new(background),
+                        rotation.to_radians(),
+                        intensity,
+                    )
+                    .unwrap_or_else(|e| {
+                        panic!("Failed to load environment map {}: {}", background, e)
+                    });
+                    Background::Environment(Arc::new(map))
+                }
+            },
+        };
+    }
+    scene.fog = matches.value_of("fog").map(|density| {
+        Fog::new(
+            density.parse().unwrap(),
+            Vector3::new(1.0, 1.0, 1.0),
+            PhaseFunction::Isotropic,
+        )
+    });
+    scene.settings = RenderSettings {
+        light_sampling,
+        limits,
+    };
+    let aovs: Vec<Aov> = match matches.value_of("aovs") {
+        Some(names) => names
+            .split(',')
+            .map(|name| Aov::parse(name.trim()).unwrap_or_else(|| panic!("Unknown AOV {}", name)))
+            .collect(),
+        None => Vec::new(),
+    };
+    if !aovs.is_empty() && output.is_none() {
+        panic!("AOVs need an output file");
+    }
+    // Photon mapping only makes the finished beauty pass
+    let split = aovs.contains(&Aov::Direct) || aovs.contains(&Aov::Indirect);
+    if sppm && (denoise || spectral || split) {
+        panic!("sppm can't be denoised, traced spectrally or split into direct and indirect light");
+    }
+    // Debug views' colours aren't light, so can't be turned into a wavelength
+    // and back
+    let debug_view = !matches!(integrator_name, "path" | "bdpt" | "sppm");
+    if debug_view && spectral {
+        panic!("Debug views can't be traced spectrally");
+    }
+    // The AOVs to render: those asked for, then the denoiser's features
+    let mut passes = aovs.clone();
+    if denoise {
+        for feature in [Aov::Albedo, Aov::Normal] {
+            if !passes.contains(&feature) {
+                passes.push(feature);
             }
-        }));
+        }
     }
-
-    for thread in thread_handles {
-        thread.join().unwrap();
+    let pass_index = |aov: Aov| 1 + passes.iter().position(|pass| *pass == aov).unwrap();
+    // Numbered in the order the scene added them
+    if aovs.contains(&Aov::ObjectId) {
+        scene.objects.hitables = scene
+            .objects
+            .hitables
+            .into_iter()
+            .enumerate()
+            .map(|(i, object)| {
+                Box::new(Tagged::new(i as u32 + 1, object)) as Box<dyn Hitable + Send>
+            })
+            .collect();
     }
+    let scene = scene
+        .build()
+        .unwrap_or_else(|e| panic!("Can't render the scene: {}", e));
 
-    let mut output_buffer: Vec<u8> = Vec::with_capacity(x_res as usize * y_res as usize * 3);
+    let frames = matches.value_of("frames").map(|value| {
+        parse_frames(value)
+            .unwrap_or_else(|| panic!("Expected the frames as start..end, got {}", value))
+    });
+    if frames.is_some() && output.and_then(|o| image_out::frame_path(o, 0)).is_none() {
+        panic!("Rendering frames needs an output file name with a frame number, e.g. out_%04d.png");
+    }
+    let shutter_open: f32 = matches.value_of("shutter_open").unwrap().parse().unwrap();
+    let shutter_close: f32 = matches.value_of("shutter_close").unwrap().parse().unwrap();
+    let aperture_image = matches.value_of("aperture_image").map(|path| {
+        Arc::new(
+            ApertureImage::load(Path::new(path))
+                .unwrap_or_else(|e| panic!("Failed to load aperture image {}: {}", path, e)),
+        )
+    });
 
-    // Should buffer and only write to the file at the end
-    for col in result.lock().unwrap().iter() {
-        let out_colour = Vector3::new(
-            (255.99 * col.r().sqrt()).floor(),
-            (255.99 * col.g().sqrt()).floor(),
-            (255.99 * col.b().sqrt()).floor(),
+    // Everything but the camera is built once for all the frames. Moving
+    // objects' bounds cover all their motion, so the BVH stays valid.
+    for frame in frames.clone().unwrap_or(0..1) {
+        let time = frame as f32;
+        let cam = make_camera(
+            &matches,
+            &scene,
+            aperture_image.as_ref(),
+            time,
+            x_res,
+            y_res,
         );
+        let shutter = Shutter::new(time + shutter_open, time + shutter_close);
+        let integrator: Arc<dyn Integrator<BVHNode>> = match integrator_name {
+            "path" => Arc::new(PathTracer),
+            "bdpt" => Arc::new(Bdpt::new(Arc::clone(&cam), max_depth)),
+            "sppm" => Arc::new(Sppm::new(Arc::clone(&cam), photons, photon_radius)),
+            name => Arc::new(DebugView::parse(name, ao_distance).unwrap()),
+        };
 
-        output_buffer.push(out_colour.r() as u8);
-        output_buffer.push(out_colour.g() as u8);
-        output_buffer.push(out_colour.b() as u8);
-    }
+        let mut thread_handles = Vec::new();
+        let samples_per_thread = num_samples / num_threads;
+        // Splats are summed, not averaged like samples
+        let splat_scale = 1.0 / (samples_per_thread * num_threads) as f32;
+
+        for thread_index in 0..num_threads {
+            // The beauty pass, then each AOV, then the beauty's squared
+            // luminance in x for the denoiser's variance
+            let mut layers = vec![false];
+            layers.extend(passes.iter().map(Aov::keeps_nearest));
+            layers.push(false);
+            let mut film = Film::new(x_res, y_res, filter, layers);
+            let scene = scene.clone();
+            let cam = Arc::clone(&cam);
+            let integrator = Arc::clone(&integrator);
+            let aovs = passes.clone();
+            // Different for every thread and frame
+            let mut sampler =
+                Sampler::new(u64::from(frame) * u64::from(num_threads) + u64::from(thread_index));
+            // TODO Handle exceptions + num_samples not divisible by num_threads
+            thread_handles.push(thread::spawn(move || {
+                let whole_image = integrator.render(
+                    &scene,
+                    &shutter,
+                    x_res,
+                    y_res,
+                    samples_per_thread,
+                    &mut sampler,
+                );
+                for j in 0..y_res {
+                    for i in 0..x_res {
+                        for _s in 0..samples_per_thread {
+                            // From the top left
+                            let x = i as f32 + sampler.uniform();
+                            let y = j as f32 + sampler.uniform();
+                            let mut values = vec![Vector3::zero(); 2 + aovs.len()];
+                            // Blocked by the lens barrel, so it stays dark
+                            let (s, t) = (x / x_res as f32, 1.0 - y / y_res as f32);
+                            let ray = match cam.get_ray(s, t, &mut sampler) {
+                                Some(ray) => ray.with_time(shutter.sample(&mut sampler)),
+                                None => {
+                                    film.add_sample(x, y, &values);
+                                    continue;
+                                }
+                            };
+                            let lambda = if spectral {
+                                Some(spectrum::sample_wavelength(&mut sampler))
+                            } else {
+                                None
+                            };
+                            let traced = match lambda {
+                                Some(lambda) => ray.clone().with_wavelength(lambda),
+                                None => ray.clone(),
+                            };
+                            // Only the first hit, for integrators rendering
+                            // whole images
+                            let Sample {
+                                mut radiance,
+                                mut splats,
+                                first_hit,
+                            } = integrator.sample(&traced, &scene, &mut sampler);
+                            if let Some(lambda) = lambda {
+                                // All channels hold the radiance at lambda
+                                let to_rgb =
+                                    |c: Vector3| spectrum::wavelength_to_rgb(lambda, c.x());
+                                radiance = Radiance {
+                                    direct: to_rgb(radiance.direct),
+                                    indirect: to_rgb(radiance.indirect),
+                                };
+                                for splat in splats.iter_mut() {
+                                    splat.value = to_rgb(splat.value);
+                                }
+                            }
+                            for splat in splats {
+                                film.add_splat(
+                                    splat.s * x_res as f32,
+                                    (1.0 - splat.t) * y_res as f32,
+                                    splat.value * splat_scale,
+                                );
+                            }
+                            values[0] = radiance.total();
+                            let luminance = spectrum::luminance(&values[0]);
+                            values[1 + aovs.len()] = Vector3::new(luminance * luminance, 0.0, 0.0);
+
+                            for (aov, value) in aovs.iter().zip(values[1..].iter_mut()) {
+                                *value = match aov {
+                                    Aov::Direct => radiance.direct,
+                                    Aov::Indirect => radiance.indirect,
+                                    _ => aov.first_hit(cam.as_ref(), &ray, first_hit.as_ref()),
+                                };
+                            }
+                            film.add_sample(x, y, &values);
+                        }
+                    }
+                }
+                (film, whole_image)
+            }));
+        }
+
+        let mut film: Option<Film> = None;
+        // Each thread's image from an integrator rendering whole images,
+        // like photon mapping, is a whole estimate by itself
+        let mut whole_image: Option<Vec<Vector3>> = None;
+        for thread in thread_handles {
+            let (thread_film, thread_image) = thread.join().unwrap();
+            match &mut film {
+                Some(film) => film.merge(&thread_film),
+                None => film = Some(thread_film),
+            }
+            if let Some(thread_image) = thread_image {
+                let image =
+                    whole_image.get_or_insert_with(|| vec![Vector3::zero(); thread_image.len()]);
+                for (pixel, thread_pixel) in image.iter_mut().zip(thread_image) {
+                    *pixel = *pixel + thread_pixel / num_threads as f32;
+                }
+            }
+        }
 
-    image_out::write_image(output, &output_buffer, x_res, y_res);
+        let mut buffers = film.unwrap().layers();
+        if let Some(image) = whole_image {
+            buffers[0] = image;
+        }
+        if denoise {
+            let variance: Vec<f32> = buffers[0]
+                .iter()
+                .zip(buffers.last().unwrap())
+                .map(|(mean, mean_square)| {
+                    let luminance = spectrum::luminance(mean);
+                    (mean_square.x() - luminance * luminance).max(0.0) / num_samples as f32
+                })
+                .collect();
+            let denoised = denoise::denoise(
+                &buffers[0],
+                &variance,
+                &buffers[pass_index(Aov::Albedo)],
+                &buffers[pass_index(Aov::Normal)],
+                x_res,
+                y_res,
+            );
+            buffers[0] = denoised;
+        }
+        // Only the AOVs that were asked for
+        let passes = &buffers[..1 + aovs.len()];
+        let path = match &frames {
+            Some(_) => Some(image_out::frame_path(output.unwrap(), frame).unwrap()),
+            None => output.map(String::from),
+        };
+        match path {
+            Some(path) if path.ends_with(".exr") => {
+                let mut layers = vec![("beauty", &["R", "G", "B"][..], &passes[0][..])];
+                for (aov, pass) in aovs.iter().zip(passes[1..].iter()) {
+                    layers.push((aov.name(), aov.channels(), pass));
+                }
+                image_out::write_exr(&path, x_res, y_res, &layers)
+                    .unwrap_or_else(|e| panic!("Failed to write {}: {}", path, e));
+            }
+            _ => {
+                let beauty = image_out::to_rgb8(&passes[0], true);
+                image_out::write_image(path.as_deref(), &beauty, x_res, y_res);
+                for (aov, pass) in aovs.iter().zip(passes[1..].iter()) {
+                    let image = image_out::to_rgb8(&aov.for_display(pass), aov.is_color());
+                    let aov_path = image_out::pass_path(path.as_ref().unwrap(), aov.name());
+                    image_out::write_image(Some(&aov_path), &image, x_res, y_res);
+                }
+            }
+        }
+    }
 }
@@ -1,7 +1,11 @@
+use crate::emission::Emission;
 use crate::hitable::HitRecord;
-use crate::random;
+use crate::random::Sampler;
 use crate::ray::Ray;
+use crate::spectrum;
 use crate::vec3::Vector3;
+use std::f32;
+use std::sync::Arc;
 
 fn reflect(v: &Vector3, n: &Vector3) -> Vector3 {
     *v - (v.dot(n) * 2.0) * *n
@@ -18,27 +22,116 @@ fn refract(v: &Vector3, n: &Vector3, ni_over_nt: f32) -> Option<Vector3> {
     }
 }
 
+fn spectral_color(color: &Vector3, r_in: &Ray) -> Vector3 {
+    spectrum::upsample(color, r_in.wavelength())
+}
+
+// The normal on the side the ray arrived from
+fn facing_normal(r_in: &Ray, normal: &Vector3) -> Vector3 {
+    if r_in.direction().dot(normal) > 0.0 {
+        -*normal
+    } else {
+        *normal
+    }
+}
+
 fn schlick(cosine: f32, ref_idx: f32) -> f32 {
     let r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
     let r0 = r0 * r0;
     r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
 }
 
+#[derive(Copy, Clone)]
+pub enum PhaseFunction {
+    Isotropic,
+    // g in (-1, 1): positive scatters forward, negative backward
+    HenyeyGreenstein { g: f32 },
+}
+
+impl PhaseFunction {
+    // Density of scattering light travelling along `direction` into
+    // `scattered`, per steradian
+    pub fn value(&self, direction: &Vector3, scattered: &Vector3) -> f32 {
+        match self {
+            PhaseFunction::Isotropic => 1.0 / (4.0 * f32::consts::PI),
+            PhaseFunction::HenyeyGreenstein { g } => {
+                let cos_theta = direction.get_unit_vector().dot(&scattered.get_unit_vector());
+                let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+                (1.0 - g * g) / (4.0 * f32::consts::PI * denom * denom.sqrt())
+            }
+        }
+    }
+
+    // Samples a new direction for light travelling along `direction`
+    pub fn sample(&self, direction: &Vector3, sampler: &mut Sampler) -> Vector3 {
+        let cos_theta = match self {
+            PhaseFunction::Isotropic => 1.0 - 2.0 * sampler.uniform(),
+            PhaseFunction::HenyeyGreenstein { g } => {
+                let xi = sampler.uniform();
+                if g.abs() < 0.001 {
+                    1.0 - 2.0 * xi
+                } else {
+                    let sq = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+                    (1.0 + g * g - sq * sq) / (2.0 * g)
+                }
+            }
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * f32::consts::PI * sampler.uniform();
+
+        let w = direction.get_unit_vector();
+        let (u, v) = w.orthonormal_basis();
+        sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * w
+    }
+}
+
+// The kinds of scattering, each with its own limit on how many times a
+// path may do it
+#[derive(Clone, Copy, PartialEq)]
+pub enum Bounce {
+    Diffuse,
+    Glossy,
+    // Through or off glass
+    Transmission,
+    Volume,
+}
+
 #[derive(Clone)]
 pub enum Material {
     Dielectric { ri: f32 },
     Lambertian { albedo: Vector3 },
     Metal { albedo: Vector3, fuzz: f32 },
-    DiffuseLight { color: Vector3 },
+    // Light is which of the scene's lights the surface belongs to, if it's
+    // one of them
+    DiffuseLight {
+        emission: Arc<Emission>,
+        light: Option<usize>,
+    },
+    // Scattering inside participating media. Emission is the radiance
+    // added at each collision, e.g. for fire, before the albedo scales the
+    // scattered light.
+    Volume {
+        albedo: Vector3,
+        phase: PhaseFunction,
+        emission: Vector3,
+    },
 }
 
 pub struct ScatterRecord {
     pub color: Vector3,
     pub ray: Ray,
+    // Density the ray was sampled with. None for specular scattering, and
+    // anything else eval() and pdf() can't describe.
+    pub pdf: Option<f32>,
 }
 
 impl Material {
-    pub fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+    pub fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut Sampler,
+    ) -> Option<ScatterRecord> {
         match self {
             Material::Dielectric { ri } => {
                 let outward_normal;
@@ -60,56 +153,245 @@ impl Material {
                 match refract(&r_in.direction(), &outward_normal, ni_over_nt) {
                     Some(refracted) => {
                         reflect_prob = schlick(cosine, *ri);
-                        if random::random_in_unit_interval() < reflect_prob {
+                        if sampler.uniform() < reflect_prob {
                             Some(ScatterRecord {
                                 color: attenuation,
-                                ray: Ray::new(rec.p, reflected),
+                                ray: r_in.spawn(rec.p, reflected),
+                                pdf: None,
                             })
                         } else {
                             Some(ScatterRecord {
                                 color: attenuation,
-                                ray: Ray::new(rec.p, refracted),
+                                ray: r_in.spawn(rec.p, refracted),
+                                pdf: None,
                             })
                         }
                     }
                     None => Some(ScatterRecord {
                         color: attenuation,
-                        ray: Ray::new(rec.p, reflected),
+                        ray: r_in.spawn(rec.p, reflected),
+                        pdf: None,
                     }),
                 }
             }
             Material::Lambertian { albedo } => {
-                let target = rec.p + rec.normal + random::random_in_unit_sphere();
+                // Cosine weighted around the normal
+                let normal = facing_normal(r_in, &rec.normal);
+                let mut direction = normal + sampler.unit_vector();
+                if direction.length_squared() < 1e-8 {
+                    direction = normal;
+                }
+                let ray = r_in.spawn(rec.p, direction);
                 Some(ScatterRecord {
-                    color: *albedo,
-                    ray: Ray::new(rec.p, target - rec.p),
+                    color: spectral_color(albedo, r_in),
+                    pdf: Some(self.pdf(r_in, rec, &ray.direction())),
+                    ray,
                 })
             }
             Material::Metal { albedo, fuzz } => {
                 let reflected = reflect(&r_in.direction(), &rec.normal);
-                let scattered = Ray::new(
+                let scattered = r_in.spawn(
                     rec.p,
-                    reflected + random::random_in_unit_sphere() * f32::min(*fuzz, 1.0),
+                    reflected + sampler.in_unit_sphere() * f32::min(*fuzz, 1.0),
                 );
                 let should_scatter = scattered.direction().dot(&rec.normal) > 0.0;
                 if should_scatter
                 {
                     Some(ScatterRecord {
-                        color: *albedo,
+                        color: spectral_color(albedo, r_in),
                         ray: scattered,
+                        pdf: None,
                     })
                 }
                 else {
                     None
                 }
             }
-            Material::DiffuseLight { color: _ } => None,
+            Material::DiffuseLight { .. } => None,
+            Material::Volume { albedo, phase, .. } => {
+                let ray = r_in.spawn(rec.p, phase.sample(&r_in.direction(), sampler));
+                Some(ScatterRecord {
+                    color: spectral_color(albedo, r_in),
+                    pdf: Some(phase.value(&r_in.direction(), &ray.direction())),
+                    ray,
+                })
+            }
+        }
+    }
+    // Scattering towards `direction`, including the cosine term for
+    // surfaces. Only meaningful when scatter() returns a pdf.
+    pub fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: &Vector3) -> Vector3 {
+        let wo = -r_in.direction().get_unit_vector();
+        let wi = direction.get_unit_vector();
+        let f = self.f(rec, &wo, &wi, r_in.wavelength());
+        match self {
+            Material::Volume { .. } => f,
+            _ => f * rec.normal.dot(&wi).abs(),
         }
     }
-    pub fn emitted(&self) -> Vector3 {
+
+    // Density with which scatter() picks `direction`
+    pub fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vector3) -> f32 {
+        let wo = -r_in.direction().get_unit_vector();
+        self.pdf_between(rec, &wo, &direction.get_unit_vector())
+    }
+
+    // The BSDF, or the albedo times the phase function in volumes, between
+    // unit directions wo and wi pointing away from the hit. It's the same
+    // whichever way the light goes, so paths traced from lights can use it
+    // too. Zero for specular materials, whose directions are only ever
+    // sampled.
+    pub fn f(
+        &self,
+        rec: &HitRecord,
+        wo: &Vector3,
+        wi: &Vector3,
+        wavelength: Option<f32>,
+    ) -> Vector3 {
         match self {
-            Material::DiffuseLight {color} => *color,
-            _ => Vector3::zero()
+            // Two-sided, so it only matters that they're on the same side
+            Material::Lambertian { albedo } if rec.normal.dot(wo) * rec.normal.dot(wi) > 0.0 => {
+                spectrum::upsample(albedo, wavelength) / f32::consts::PI
+            }
+            Material::Volume { albedo, phase, .. } => {
+                spectrum::upsample(albedo, wavelength) * phase.value(&-*wo, wi)
+            }
+            _ => Vector3::zero(),
+        }
+    }
+
+    // Density with which scatter() picks wi when the light leaves along wo.
+    // Swapped, it's the density for light going the other way.
+    pub fn pdf_between(&self, rec: &HitRecord, wo: &Vector3, wi: &Vector3) -> f32 {
+        match self {
+            Material::Lambertian { .. } if rec.normal.dot(wo) * rec.normal.dot(wi) > 0.0 => {
+                rec.normal.dot(wi).abs() / f32::consts::PI
+            }
+            Material::Volume { phase, .. } => phase.value(&-*wo, wi),
+            _ => 0.0,
+        }
+    }
+
+    // Whether it scatters into only a few directions, or ones it can't give
+    // a density for, so paths can't be joined there
+    pub fn is_specular(&self) -> bool {
+        matches!(self, Material::Dielectric { .. } | Material::Metal { .. })
+    }
+
+    pub fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Vector3 {
+        match self {
+            Material::DiffuseLight { emission, .. } => {
+                let cos_theta = -rec.normal.dot(&r_in.direction().get_unit_vector());
+                spectral_color(&emission.radiance(rec.u, rec.v, cos_theta), r_in)
+            }
+            Material::Volume { emission, .. } => spectral_color(emission, r_in),
+            _ => Vector3::zero(),
+        }
+    }
+
+    pub fn bounce(&self) -> Bounce {
+        match self {
+            Material::Lambertian { .. } | Material::DiffuseLight { .. } => Bounce::Diffuse,
+            Material::Metal { .. } => Bounce::Glossy,
+            Material::Dielectric { .. } => Bounce::Transmission,
+            Material::Volume { .. } => Bounce::Volume,
+        }
+    }
+
+    // Base colour, for the albedo pass. Glass and lights count as white.
+    pub fn albedo(&self) -> Vector3 {
+        match self {
+            Material::Lambertian { albedo }
+            | Material::Metal { albedo, .. }
+            | Material::Volume { albedo, .. } => *albedo,
+            Material::Dielectric { .. } | Material::DiffuseLight { .. } => {
+                Vector3::new(1.0, 1.0, 1.0)
+            }
+        }
+    }
+
+    // Identifies the material for the material ID pass. Materials are
+    // values, so ones with the same parameters share an ID, and IDs are the
+    // same from run to run. Never 0, which means no hit, and under 2^24 to
+    // be exact as an f32.
+    pub fn id(&self) -> u32 {
+        let bits = |v: &Vector3| vec![v.x().to_bits(), v.y().to_bits(), v.z().to_bits()];
+        let (kind, params) = match self {
+            Material::Dielectric { ri } => (1, vec![ri.to_bits()]),
+            Material::Lambertian { albedo } => (2, bits(albedo)),
+            Material::Metal { albedo, fuzz } => (3, [bits(albedo), vec![fuzz.to_bits()]].concat()),
+            Material::DiffuseLight { emission, .. } => (4, emission.id_words()),
+            Material::Volume {
+                albedo, emission, ..
+            } => (5, [bits(albedo), bits(emission)].concat()),
+        };
+        // FNV-1a over the kind and parameters
+        let mut h: u32 = 0x811c_9dc5;
+        for word in std::iter::once(kind).chain(params) {
+            h = (h ^ word).wrapping_mul(0x0100_0193);
+        }
+        (h & 0x00ff_ffff).max(1)
+    }
+
+    // Diffuse, two-sided emission
+    pub fn diffuse_light(color: Vector3) -> Material {
+        Material::DiffuseLight {
+            emission: Arc::new(Emission::new(color)),
+            light: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sampled directions fall between cones around the incoming direction
+    // as often as the phase function says, and lean forward by g on
+    // average
+    #[test]
+    fn henyey_greenstein_pdf_matches_sample() {
+        let direction = Vector3::new(1.0, 2.0, -0.5);
+        let bins = 20;
+        let trials = 100_000;
+        let mut sampler = Sampler::new(0);
+        for g in [-0.5, 0.0, 0.3, 0.8] {
+            let phase = PhaseFunction::HenyeyGreenstein { g };
+            let mut counts = vec![0; bins];
+            let mut sum = 0.0;
+            for _ in 0..trials {
+                let cos_theta = phase
+                    .sample(&direction, &mut sampler)
+                    .dot(&direction.get_unit_vector());
+                let bin = ((cos_theta + 1.0) / 2.0 * bins as f32) as usize;
+                counts[bin.min(bins - 1)] += 1;
+                sum += cos_theta;
+            }
+            assert!((sum / trials as f32 - g).abs() < 0.01, "{}", g);
+
+            // Integrated over each band of cos θ, by the midpoint rule
+            let (u, _) = direction.get_unit_vector().orthonormal_basis();
+            let steps = 50;
+            for (bin, &count) in counts.iter().enumerate() {
+                let width = 2.0 / (bins * steps) as f32;
+                let probability: f32 = (0..steps)
+                    .map(|step| {
+                        let cos_theta = -1.0 + ((bin * steps + step) as f32 + 0.5) * width;
+                        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                        let scattered = direction.get_unit_vector() * cos_theta + u * sin_theta;
+                        2.0 * f32::consts::PI * phase.value(&direction, &scattered) * width
+                    })
+                    .sum();
+                let fraction = count as f32 / trials as f32;
+                assert!(
+                    (fraction - probability).abs() < 0.005,
+                    "{} {} {} {}",
+                    g,
+                    bin,
+                    fraction,
+                    probability
+                );
+            }
         }
     }
 }
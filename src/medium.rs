@@ -0,0 +1,480 @@
+use crate::aabb::AABB;
+use crate::hitable::HitRecord;
+use crate::hitable::Hitable;
+use crate::material::Material;
+use crate::material::PhaseFunction;
+use crate::random::{self, Sampler};
+use crate::ray::Ray;
+use crate::vec3::Vector3;
+use crate::voxel::{MajorantGrid, VoxelGrid};
+use std::f32;
+use std::sync::Arc;
+
+// Distance travelled before the next scattering event in a medium of the
+// given density, from u uniform in [0, 1)
+fn sample_free_flight(density: f32, u: f32) -> f32 {
+    -(1.0 - u).ln() / density
+}
+
+// A homogeneous volume filling a closed boundary, e.g. smoke in a sphere.
+// A ray entering the boundary either scatters somewhere inside it, or
+// passes through unaffected.
+#[derive(Clone)]
+pub struct ConstantMedium {
+    boundary: Box<dyn Hitable + Send>,
+    density: f32,
+    phase_function: Material,
+}
+
+impl ConstantMedium {
+    pub fn new<T: Hitable + 'static + Send>(
+        boundary: T,
+        density: f32,
+        albedo: Vector3,
+        phase: PhaseFunction,
+    ) -> ConstantMedium {
+        ConstantMedium {
+            boundary: Box::new(boundary),
+            density,
+            phase_function: Material::Volume {
+                albedo,
+                phase,
+                emission: Vector3::zero(),
+            },
+        }
+    }
+}
+
+impl ConstantMedium {
+    // The part of [t_min, t_max] inside the boundary
+    fn interval(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        // Find where the ray enters and leaves the boundary, even if it
+        // starts inside it
+        let entry = self.boundary.hit(r, -f32::MAX, f32::MAX)?;
+        let exit = self.boundary.hit(r, entry.t + 0.0001, f32::MAX)?;
+
+        let t_enter = entry.t.max(t_min);
+        let t_exit = exit.t.min(t_max);
+        if t_enter >= t_exit {
+            None
+        } else {
+            Some((t_enter, t_exit))
+        }
+    }
+}
+
+impl Hitable for ConstantMedium {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let (t_enter, t_exit) = self.interval(r, t_min, t_max)?;
+
+        let ray_length = r.direction().length();
+        let distance_inside = (t_exit - t_enter) * ray_length;
+        let hit_distance = sample_free_flight(self.density, random::random_in_unit_interval());
+        if hit_distance > distance_inside {
+            return None;
+        }
+
+        let t = t_enter + hit_distance / ray_length;
+        Some(HitRecord {
+            t,
+            p: r.point_at_parameter(t),
+            // Arbitrary, the phase function doesn't use it
+            normal: Vector3::new(1.0, 0.0, 0.0),
+            u: 0.0,
+            v: 0.0,
+            material: self.phase_function.clone(),
+            object_id: 0,
+        })
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        match self.interval(r, t_min, t_max) {
+            Some((t_enter, t_exit)) => {
+                (-self.density * (t_exit - t_enter) * r.direction().length()).exp()
+            }
+            None => 1.0,
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        self.boundary.validate()
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        self.boundary.bounding_box()
+    }
+}
+
+// Atmospheric fog filling the whole scene. It fills a sphere around
+// everything in it, which the scene sets when it's built, so rays escaping
+// to the background can scatter on their way out, and light from the
+// background and directional lights is dimmed coming in.
+#[derive(Clone)]
+pub struct Fog {
+    density: f32,
+    phase_function: Material,
+    // Centre and radius, or None to fill all of space
+    bounds: Option<(Vector3, f32)>,
+}
+
+impl Fog {
+    pub fn new(density: f32, albedo: Vector3, phase: PhaseFunction) -> Fog {
+        Fog {
+            density,
+            phase_function: Material::Volume {
+                albedo,
+                phase,
+                emission: Vector3::zero(),
+            },
+            bounds: None,
+        }
+    }
+
+    // The same fog, only filling the sphere with this centre and radius
+    pub fn within(self, center: Vector3, radius: f32) -> Fog {
+        Fog {
+            bounds: Some((center, radius)),
+            ..self
+        }
+    }
+
+    // The part of t_min to t_max that's in the fog, if any
+    fn span(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        let (center, radius) = match self.bounds {
+            Some(bounds) => bounds,
+            None => return Some((t_min, t_max)),
+        };
+        let oc = r.origin() - center;
+        let a = r.direction().length_squared();
+        let b = oc.dot(&r.direction());
+        let c = oc.length_squared() - radius * radius;
+        let discriminant = b * b - a * c;
+        if discriminant <= 0.0 {
+            return None;
+        }
+        let root = discriminant.sqrt();
+        let start = t_min.max((-b - root) / a);
+        let end = t_max.min((-b + root) / a);
+        if start < end {
+            Some((start, end))
+        } else {
+            None
+        }
+    }
+
+    // A scattering event between t_min and t_max, if one happens
+    pub fn hit(&self, r: &Ray, t_min: f32, t_max: f32, sampler: &mut Sampler) -> Option<HitRecord> {
+        let (start, end) = self.span(r, t_min, t_max)?;
+        let distance = sample_free_flight(self.density, sampler.uniform());
+        let t = start + distance / r.direction().length();
+        if t >= end {
+            return None;
+        }
+
+        Some(HitRecord {
+            t,
+            p: r.point_at_parameter(t),
+            normal: Vector3::new(1.0, 0.0, 0.0),
+            u: 0.0,
+            v: 0.0,
+            material: self.phase_function.clone(),
+            object_id: 0,
+        })
+    }
+
+    // Fraction of light getting through between t_min and t_max
+    pub fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        match self.span(r, t_min, t_max) {
+            Some((start, end)) => (-self.density * (end - start) * r.direction().length()).exp(),
+            None => 1.0,
+        }
+    }
+}
+
+// A heterogeneous volume, e.g. a cloud or fire, with densities from a voxel
+// grid stretched over a bounding box. Collisions are found by delta
+// tracking against the majorants of a coarse grid, walked with a 3D DDA.
+#[derive(Clone)]
+pub struct GridMedium {
+    bbox: AABB,
+    grid: Arc<VoxelGrid>,
+    majorants: Arc<MajorantGrid>,
+    density_scale: f32,
+    albedo: Vector3,
+    phase: PhaseFunction,
+    // Scales the grid's emission channel
+    emission_color: Vector3,
+}
+
+impl GridMedium {
+    pub fn new(
+        bbox: AABB,
+        grid: Arc<VoxelGrid>,
+        density_scale: f32,
+        albedo: Vector3,
+        phase: PhaseFunction,
+        emission_color: Vector3,
+    ) -> GridMedium {
+        let majorants = Arc::new(MajorantGrid::new(&grid, 16));
+        GridMedium {
+            bbox,
+            grid,
+            majorants,
+            density_scale,
+            albedo,
+            phase,
+            emission_color,
+        }
+    }
+
+    // Position within the box, in [0, 1]^3
+    fn local(&self, p: &Vector3) -> Vector3 {
+        let extent = self.bbox.max() - self.bbox.min();
+        let offset = *p - self.bbox.min();
+        Vector3::new(
+            offset.x() / extent.x(),
+            offset.y() / extent.y(),
+            offset.z() / extent.z(),
+        )
+    }
+
+    // Walks the majorant cells the ray passes through between t_min and
+    // t_max with a 3D DDA, calling visit(t_start, t_end, majorant) for each
+    // until it returns true
+    fn march<F>(&self, r: &Ray, t_min: f32, t_max: f32, mut visit: F)
+    where
+        F: FnMut(f32, f32, f32) -> bool,
+    {
+        let (t_enter, t_exit) = match self.bbox.intersect(r, t_min, t_max) {
+            Some(interval) => interval,
+            None => return,
+        };
+
+        // Work in the cells' coordinate system
+        let n = self.majorants.resolution;
+        let cell_size = (self.bbox.max() - self.bbox.min()) / n as f32;
+        let start = self.local(&r.point_at_parameter(t_enter)) * n as f32;
+        let mut cell = [0usize; 3];
+        let mut step = [0i32; 3];
+        let mut t_next = [f32::MAX; 3];
+        let mut t_delta = [f32::MAX; 3];
+        for axis in 0..3 {
+            cell[axis] = (start.component(axis).max(0.0) as usize).min(n - 1);
+            let d = r.direction().component(axis) / cell_size.component(axis);
+            if d > 0.0 {
+                step[axis] = 1;
+                t_next[axis] = t_enter + (cell[axis] as f32 + 1.0 - start.component(axis)) / d;
+                t_delta[axis] = 1.0 / d;
+            } else if d < 0.0 {
+                step[axis] = -1;
+                t_next[axis] = t_enter + (cell[axis] as f32 - start.component(axis)) / d;
+                t_delta[axis] = -1.0 / d;
+            }
+        }
+
+        let mut t = t_enter;
+        loop {
+            let axis = if t_next[0] < t_next[1] && t_next[0] < t_next[2] {
+                0
+            } else if t_next[1] < t_next[2] {
+                1
+            } else {
+                2
+            };
+            let t_cell_exit = t_next[axis].min(t_exit);
+            let majorant = self.density_scale * self.majorants.majorant(cell[0], cell[1], cell[2]);
+            if visit(t, t_cell_exit, majorant) || t_cell_exit >= t_exit {
+                return;
+            }
+
+            let next_cell = cell[axis] as i32 + step[axis];
+            if next_cell < 0 || next_cell >= n as i32 {
+                return;
+            }
+            cell[axis] = next_cell as usize;
+            t = t_cell_exit;
+            t_next[axis] += t_delta[axis];
+        }
+    }
+
+    fn density(&self, p: &Vector3) -> f32 {
+        self.density_scale * self.grid.density(&self.local(p))
+    }
+}
+
+impl Hitable for GridMedium {
+    // Delta tracking: tentative collisions are sampled against the
+    // majorant, and accepted with probability density / majorant
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let ray_length = r.direction().length();
+        let mut collision = None;
+        self.march(r, t_min, t_max, |t_start, t_end, majorant| {
+            if majorant <= 0.0 {
+                return false;
+            }
+            let mut t = t_start;
+            loop {
+                t += sample_free_flight(majorant, random::random_in_unit_interval()) / ray_length;
+                if t >= t_end {
+                    return false;
+                }
+                // Otherwise it was a null collision, and the ray carries on
+                if random::random_in_unit_interval()
+                    < self.density(&r.point_at_parameter(t)) / majorant
+                {
+                    collision = Some(t);
+                    return true;
+                }
+            }
+        });
+
+        let t = collision?;
+        let p = r.point_at_parameter(t);
+        Some(HitRecord {
+            t,
+            p,
+            normal: Vector3::new(1.0, 0.0, 0.0),
+            u: 0.0,
+            v: 0.0,
+            material: Material::Volume {
+                albedo: self.albedo,
+                phase: self.phase,
+                emission: self.emission_color * self.grid.emission(&self.local(&p)),
+            },
+            object_id: 0,
+        })
+    }
+
+    // Ratio tracking: rather than stopping at the first collision, every
+    // tentative collision scales the estimate by the null collision
+    // probability, which is much less noisy for shadow rays
+    fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        let ray_length = r.direction().length();
+        let mut transmittance = 1.0;
+        self.march(r, t_min, t_max, |t_start, t_end, majorant| {
+            if majorant <= 0.0 {
+                return false;
+            }
+            let mut t = t_start;
+            loop {
+                t += sample_free_flight(majorant, random::random_in_unit_interval()) / ray_length;
+                if t >= t_end {
+                    return false;
+                }
+                transmittance *= 1.0 - self.density(&r.point_at_parameter(t)) / majorant;
+                if transmittance <= 0.0 {
+                    return true;
+                }
+            }
+        });
+        transmittance.max(0.0)
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.bbox.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cuboid::Cuboid;
+
+    const TRIALS: usize = 20000;
+
+    fn cube(half_size: f32) -> Cuboid {
+        let corner = Vector3::new(half_size, half_size, half_size);
+        Cuboid::new(
+            -corner,
+            corner,
+            Material::Lambertian {
+                albedo: Vector3::zero(),
+            },
+        )
+    }
+
+    // The mean distance along r to the collisions found by hit
+    fn mean_free_path(mut hit: impl FnMut() -> Option<HitRecord>, r: &Ray) -> f32 {
+        let distances: Vec<f32> = (0..TRIALS)
+            .filter_map(|_| hit())
+            .map(|hr| (hr.p - r.origin()).length())
+            .collect();
+        distances.iter().sum::<f32>() / distances.len() as f32
+    }
+
+    // How many of r's trips through hit get all the way
+    fn fraction_through(mut hit: impl FnMut() -> Option<HitRecord>) -> f32 {
+        (0..TRIALS).filter(|_| hit().is_none()).count() as f32 / TRIALS as f32
+    }
+
+    #[test]
+    fn constant_medium_attenuates_exponentially() {
+        let white = Vector3::new(1.0, 1.0, 1.0);
+        let smoke = ConstantMedium::new(cube(1.0), 0.5, white, PhaseFunction::Isotropic);
+        // Across the cube, with a direction that isn't a unit vector
+        let r = Ray::new(Vector3::new(-5.0, 0.0, 0.0), Vector3::new(2.0, 0.0, 0.0));
+        let expected = (-0.5f32 * 2.0).exp();
+        assert!((smoke.transmittance(&r, 0.0, f32::MAX) - expected).abs() < 0.0001);
+        let through = fraction_through(|| smoke.hit(&r, 0.0, f32::MAX));
+        assert!((through - expected).abs() < 0.015, "{}", through);
+
+        // From the middle of something much bigger
+        let smoke = ConstantMedium::new(cube(100.0), 0.5, white, PhaseFunction::Isotropic);
+        let r = Ray::new(Vector3::zero(), Vector3::new(0.0, 3.0, 0.0));
+        let path = mean_free_path(|| smoke.hit(&r, 0.0, f32::MAX), &r);
+        assert!((path - 2.0).abs() < 0.06, "{}", path);
+    }
+
+    #[test]
+    fn fog_attenuates_exponentially() {
+        let fog = Fog::new(0.25, Vector3::new(1.0, 1.0, 1.0), PhaseFunction::Isotropic);
+        let r = Ray::new(Vector3::zero(), Vector3::new(0.0, 0.0, -2.0));
+        let expected = (-0.25f32 * 2.0 * 3.0).exp();
+        assert!((fog.transmittance(&r, 1.0, 4.0) - expected).abs() < 0.0001);
+        let mut sampler = Sampler::new(0);
+        let through = fraction_through(|| fog.hit(&r, 1.0, 4.0, &mut sampler));
+        assert!((through - expected).abs() < 0.015, "{}", through);
+        // Measured from where it starts
+        let path = mean_free_path(|| fog.hit(&r, 0.0, f32::MAX, &mut sampler), &r);
+        assert!((path - 4.0).abs() < 0.12, "{}", path);
+    }
+
+    #[test]
+    fn grid_medium_tracking() {
+        let medium = |nx, density, size| {
+            let grid = VoxelGrid::new(nx, 1, 1, density, Vec::new());
+            let corner = Vector3::new(size, 1.0, 1.0);
+            GridMedium::new(
+                AABB::new(-corner, corner),
+                Arc::new(grid),
+                0.5,
+                Vector3::new(1.0, 1.0, 1.0),
+                PhaseFunction::Isotropic,
+                Vector3::zero(),
+            )
+        };
+
+        // Scaled to 1 over the first quarter, then interpolated down to 0
+        // over the last, so the density integrates to 1 across the box
+        let cloud = medium(2, vec![2.0, 0.0], 1.0);
+        let r = Ray::new(Vector3::new(-5.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        let expected = (-1.0f32).exp();
+        let ratio = (0..TRIALS)
+            .map(|_| cloud.transmittance(&r, 0.0, f32::MAX))
+            .sum::<f32>()
+            / TRIALS as f32;
+        assert!((ratio - expected).abs() < 0.01, "{}", ratio);
+        let through = fraction_through(|| cloud.hit(&r, 0.0, f32::MAX));
+        assert!((through - expected).abs() < 0.015, "{}", through);
+        // And from the thin side
+        let back = Ray::new(Vector3::new(5.0, 0.0, 0.0), Vector3::new(-1.0, 0.0, 0.0));
+        let through = fraction_through(|| cloud.hit(&back, 0.0, f32::MAX));
+        assert!((through - expected).abs() < 0.015, "{}", through);
+
+        // Even, and long enough that everything collides
+        let even = medium(4, vec![1.0; 4], 100.0);
+        let r = Ray::new(Vector3::new(-100.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        let path = mean_free_path(|| even.hit(&r, 0.0, f32::MAX), &r);
+        assert!((path - 2.0).abs() < 0.06, "{}", path);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::aabb::AABB;
+use crate::hitable::{HitRecord, Hitable};
+use crate::material::Material;
+use crate::polynomial;
+use crate::ray::Ray;
+use crate::sphere::sphere_uv;
+use crate::vec3::Vector3;
+
+#[derive(Clone)]
+pub struct Metaball {
+    pub center: Vector3,
+    // How far its field reaches
+    pub radius: f32,
+    // Its field at the centre. Negative strengths carve into the others.
+    pub strength: f32,
+}
+
+// Blobs that flow into each other, where the metaballs' fields add up to
+// the threshold. Each field is a quartic along a ray, so the surface is
+// found exactly by solving the sum between the points where balls' reaches
+// start and stop.
+#[derive(Clone)]
+pub struct Metaballs {
+    balls: Vec<Metaball>,
+    threshold: f32,
+    material: Material,
+}
+
+impl Metaballs {
+    pub fn new(balls: Vec<Metaball>, threshold: f32, material: Material) -> Metaballs {
+        Metaballs {
+            balls,
+            threshold,
+            material,
+        }
+    }
+
+    // Where the line of r crosses the surface, nearest first
+    fn roots(&self, r: &Ray) -> Vec<f32> {
+        let length = f64::from(r.direction().length());
+        let d = r.direction() / r.direction().length();
+        let along = |v: Vector3| f64::from(v.dot(&d));
+
+        // Where along the line each ball's reach starts and stops
+        let reaches: Vec<(f64, f64)> = self
+            .balls
+            .iter()
+            .map(|ball| {
+                let oc = r.origin() - ball.center;
+                let b = along(oc);
+                let c = f64::from(oc.length_squared()) - f64::from(ball.radius * ball.radius);
+                let discriminant = b * b - c;
+                if discriminant > 0.0 {
+                    (-b - discriminant.sqrt(), -b + discriminant.sqrt())
+                } else {
+                    (f64::MAX, f64::MAX)
+                }
+            })
+            .collect();
+        let mut ends: Vec<f64> = reaches
+            .iter()
+            .filter(|reach| reach.0 < f64::MAX)
+            .flat_map(|&(start, stop)| [start, stop])
+            .collect();
+        ends.sort_by(|x, y| x.partial_cmp(y).unwrap());
+
+        let mut roots = Vec::new();
+        for span in ends.windows(2) {
+            let (start, stop) = (span[0], span[1]);
+            if stop - start <= 0.0 {
+                continue;
+            }
+            // The field less the threshold, as a quartic in the distance
+            // from the start of the span
+            let middle = 0.5 * (start + stop);
+            let mut coefficients = [0.0, 0.0, 0.0, 0.0, -f64::from(self.threshold)];
+            for (ball, reach) in self.balls.iter().zip(&reaches) {
+                if middle < reach.0 || middle > reach.1 {
+                    continue;
+                }
+                // 1 - |o + s d - c|^2 / radius^2 = 1 - (s^2 + 2 b s + c) / radius^2
+                let oc = r.origin() - ball.center;
+                let (b, c) = (along(oc), f64::from(oc.length_squared()));
+                let (b, c) = (b + start, c + 2.0 * b * start + start * start);
+                let radius2 = f64::from(ball.radius * ball.radius);
+                let q = [-1.0 / radius2, -2.0 * b / radius2, 1.0 - c / radius2];
+                let w = f64::from(ball.strength);
+                coefficients[0] += w * q[0] * q[0];
+                coefficients[1] += w * 2.0 * q[0] * q[1];
+                coefficients[2] += w * (q[1] * q[1] + 2.0 * q[0] * q[2]);
+                coefficients[3] += w * 2.0 * q[1] * q[2];
+                coefficients[4] += w * q[2] * q[2];
+            }
+            let [a, b, c, d, e] = coefficients;
+            roots.extend(
+                polynomial::solve_quartic(a, b, c, d, e)
+                    .into_iter()
+                    .filter(|&s| s >= 0.0 && s < stop - start)
+                    .map(|s| ((s + start) / length) as f32),
+            );
+        }
+        roots
+    }
+
+    fn hit_record(&self, r: &Ray, t: f32) -> HitRecord {
+        let p = r.point_at_parameter(t);
+        // Down the field, which is highest inside
+        let normal = self
+            .balls
+            .iter()
+            .fold(Vector3::zero(), |sum, ball| {
+                let offset = p - ball.center;
+                let falloff = 1.0 - offset.length_squared() / (ball.radius * ball.radius);
+                if falloff > 0.0 {
+                    sum + offset * (ball.strength * falloff / (ball.radius * ball.radius))
+                } else {
+                    sum
+                }
+            })
+            .get_unit_vector();
+        let (u, v) = sphere_uv(&normal);
+        HitRecord {
+            t,
+            p,
+            normal,
+            u,
+            v,
+            material: self.material.clone(),
+            object_id: 0,
+        }
+    }
+}
+
+impl Hitable for Metaballs {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        if !self.bounding_box()?.hit(r, t_min, t_max) {
+            return None;
+        }
+        self.roots(r)
+            .into_iter()
+            .find(|&t| t > t_min && t < t_max)
+            .map(|t| self.hit_record(r, t))
+    }
+
+    fn all_hits(&self, r: &Ray) -> Option<Vec<HitRecord>> {
+        Some(
+            self.roots(r)
+                .into_iter()
+                .map(|t| self.hit_record(r, t))
+                .collect(),
+        )
+    }
+
+    fn is_solid(&self) -> bool {
+        true
+    }
+
+    // The balls' strengths can be anything, but the surface is only where
+    // they add up to a positive threshold
+    fn validate(&self) -> Result<(), String> {
+        if self.balls.is_empty() {
+            return Err("Metaballs have no balls".to_string());
+        }
+        if let Some(ball) = self.balls.iter().find(|ball| ball.radius <= 0.0) {
+            return Err(format!("Metaball radius {} isn't positive", ball.radius));
+        }
+        if self.threshold <= 0.0 {
+            return Err(format!(
+                "Metaball threshold {} isn't positive",
+                self.threshold
+            ));
+        }
+        Ok(())
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        self.balls
+            .iter()
+            .map(|ball| {
+                let radius = Vector3::new(ball.radius, ball.radius, ball.radius);
+                AABB::new(ball.center - radius, ball.center + radius)
+            })
+            .reduce(|a, b| a.surrounding_box(&b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The sum of the balls' fields, each falling smoothly to nothing at
+    // its radius
+    fn field(blob: &Metaballs, p: Vector3) -> f32 {
+        blob.balls
+            .iter()
+            .map(|ball| {
+                let falloff =
+                    1.0 - (p - ball.center).length_squared() / (ball.radius * ball.radius);
+                ball.strength * falloff.max(0.0) * falloff.max(0.0)
+            })
+            .sum()
+    }
+
+    fn blob(centers: &[Vector3]) -> Metaballs {
+        let balls = centers
+            .iter()
+            .map(|&center| Metaball {
+                center,
+                radius: 1.0,
+                strength: 1.0,
+            })
+            .collect();
+        let material = Material::Lambertian {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        };
+        Metaballs::new(balls, 0.25, material)
+    }
+
+    #[test]
+    fn finds_the_threshold() {
+        // Alone, the field's a quarter where 1 - s^2 = 1/2
+        let one = blob(&[Vector3::zero()]);
+        let edge = 0.5f32.sqrt();
+        let r = Ray::new(Vector3::new(-10.0, 0.0, 0.0), Vector3::new(2.0, 0.0, 0.0));
+        let hits = one.all_hits(&r).unwrap();
+        assert_eq!(hits.len(), 2);
+        assert!((hits[0].p.x() + edge).abs() < 0.0001);
+        assert!((hits[1].p.x() - edge).abs() < 0.0001);
+        assert!((hits[0].normal - Vector3::new(-1.0, 0.0, 0.0)).length() < 0.0001);
+        assert!(hits[1].normal.x() > 0.999);
+
+        // Two apart merge into one blob, with the surface where the field
+        // is the threshold and wider than either ball alone between them
+        let two = blob(&[Vector3::new(-0.6, 0.0, 0.0), Vector3::new(0.6, 0.0, 0.0)]);
+        let r = Ray::new(Vector3::new(-10.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        let hits = two.all_hits(&r).unwrap();
+        assert_eq!(hits.len(), 2);
+        for hr in &hits {
+            assert!((field(&two, hr.p) - 0.25).abs() < 0.0001);
+        }
+        let r = Ray::new(Vector3::new(0.0, -10.0, 0.0), Vector3::new(0.0, 1.0, 0.0));
+        let hr = two.hit(&r, 0.001, f32::MAX).unwrap();
+        // Alone, either would reach 0.37 above and below the middle
+        assert!(hr.p.y() < -0.5 && hr.p.y() > -edge);
+        assert!((field(&two, hr.p) - 0.25).abs() < 0.0001);
+        assert!(hr.normal.y() < -0.999);
+
+        // Far enough apart, they're separate
+        let apart = blob(&[Vector3::new(-1.5, 0.0, 0.0), Vector3::new(1.5, 0.0, 0.0)]);
+        let r = Ray::new(Vector3::new(-10.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(apart.all_hits(&r).unwrap().len(), 4);
+    }
+}
@@ -0,0 +1,189 @@
+use crate::vec3::Vector3;
+
+// Values that can be blended between keyframes
+pub trait Lerp {
+    // self at t = 0, other at t = 1
+    fn lerp(&self, other: &Self, t: f32) -> Self;
+}
+
+impl Lerp for Vector3 {
+    fn lerp(&self, other: &Vector3, t: f32) -> Vector3 {
+        (1.0 - t) * *self + t * *other
+    }
+}
+
+impl Lerp for f32 {
+    fn lerp(&self, other: &f32, t: f32) -> f32 {
+        (1.0 - t) * self + t * other
+    }
+}
+
+// How to get from one key to the next
+#[derive(Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    // In a straight line
+    Linear,
+    // A Catmull-Rom spline through the keys, for smooth camera moves
+    Smooth,
+}
+
+// Something changing over time, e.g. an object's position. Interpolated
+// between keys, and held at the first and last keys outside them. Two keys
+// give a straight line.
+#[derive(Clone)]
+pub struct Keyframes<T> {
+    // Sorted by time, never empty
+    keys: Vec<(f32, T)>,
+    interpolation: Interpolation,
+}
+
+impl<T: Lerp + Clone> Keyframes<T> {
+    pub fn new(mut keys: Vec<(f32, T)>) -> Keyframes<T> {
+        assert!(!keys.is_empty(), "Keyframes need at least one key");
+        keys.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        Keyframes {
+            keys,
+            interpolation: Interpolation::Linear,
+        }
+    }
+
+    pub fn with_interpolation(mut self, interpolation: Interpolation) -> Keyframes<T> {
+        self.interpolation = interpolation;
+        self
+    }
+
+    // The same at all times
+    pub fn constant(value: T) -> Keyframes<T> {
+        Keyframes {
+            keys: vec![(0.0, value)],
+            interpolation: Interpolation::Linear,
+        }
+    }
+
+    pub fn at(&self, time: f32) -> T {
+        let i = self.keys.partition_point(|(t, _)| *t <= time);
+        if i == 0 {
+            return self.keys[0].1.clone();
+        }
+        if i == self.keys.len() {
+            return self.keys[i - 1].1.clone();
+        }
+        let (t1, p1) = &self.keys[i - 1];
+        let (t2, p2) = &self.keys[i];
+        let lerp = |a: &T, b: &T, ta: f32, tb: f32| a.lerp(b, (time - ta) / (tb - ta));
+        if self.interpolation == Interpolation::Linear {
+            return lerp(p1, p2, *t1, *t2);
+        }
+
+        // Barry and Goldman's pyramid of lerps, which only needs Lerp. Past
+        // the ends, the neighbouring key is mirrored.
+        let (t0, p0) = match i {
+            1 => (t1 - (t2 - t1), p1.lerp(p2, -1.0)),
+            _ => self.keys[i - 2].clone(),
+        };
+        let (t3, p3) = match self.keys.get(i + 1) {
+            Some(key) => key.clone(),
+            None => (t2 + (t2 - t1), p1.lerp(p2, 2.0)),
+        };
+        let a1 = lerp(&p0, p1, t0, *t1);
+        let a2 = lerp(p1, p2, *t1, *t2);
+        let a3 = lerp(p2, &p3, *t2, t3);
+        let b1 = lerp(&a1, &a2, t0, *t2);
+        let b2 = lerp(&a2, &a3, *t1, t3);
+        lerp(&b1, &b2, *t1, *t2)
+    }
+
+    // Straight stretches, as the values at either end, whose ends bound the
+    // motion: anything that depends linearly on the value, such as a corner
+    // of a moving box, stays within the box around their ends. Smooth curves
+    // are given as their Bézier control polygons, as each piece between two
+    // keys is a cubic lying within the hull of its control points. A single
+    // key is a stretch from it to itself.
+    pub fn segments(&self) -> Vec<(T, T)> {
+        if self.keys.len() == 1 {
+            return vec![(self.keys[0].1.clone(), self.keys[0].1.clone())];
+        }
+        let mut segments = Vec::new();
+        for pair in self.keys.windows(2) {
+            let ((t0, p0), (t1, p1)) = (&pair[0], &pair[1]);
+            if self.interpolation == Interpolation::Linear {
+                segments.push((p0.clone(), p1.clone()));
+                continue;
+            }
+            // The control points, from the curve a third and two thirds of
+            // the way along
+            let q1 = self.at(t0 + (t1 - t0) / 3.0);
+            let q2 = self.at(t0 + (t1 - t0) * 2.0 / 3.0);
+            let c1 = affine(&[(p0, -5.0), (&q1, 18.0), (&q2, -9.0), (p1, 2.0)]);
+            let c2 = affine(&[(p0, 2.0), (&q1, -9.0), (&q2, 18.0), (p1, -5.0)]);
+            segments.push((p0.clone(), c1.clone()));
+            segments.push((c1, c2.clone()));
+            segments.push((c2, p1.clone()));
+        }
+        segments
+    }
+}
+
+// The sum of values by weights, divided by the weights' total, from lerps
+// alone. The running total of the weights must never be 0.
+fn affine<T: Lerp + Clone>(terms: &[(&T, f32)]) -> T {
+    let (mut sum, mut total) = (terms[0].0.clone(), terms[0].1);
+    for (value, weight) in &terms[1..] {
+        total += weight;
+        sum = sum.lerp(value, weight / total);
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolates_between_keys() {
+        let keys = Keyframes::new(vec![(1.0, 4.0), (0.0, 2.0), (2.0, 0.0)]);
+        assert_eq!(keys.at(-1.0), 2.0);
+        assert_eq!(keys.at(0.5), 3.0);
+        assert_eq!(keys.at(1.5), 2.0);
+        assert_eq!(keys.at(3.0), 0.0);
+        assert_eq!(keys.segments().len(), 2);
+        assert_eq!(Keyframes::constant(1.0).segments().len(), 1);
+    }
+
+    #[test]
+    fn smooth_through_keys() {
+        let keys = Keyframes::new(vec![(0.0, 0.0), (1.0, 1.0), (2.0, 0.0), (4.0, 0.0)])
+            .with_interpolation(Interpolation::Smooth);
+        assert_eq!(keys.at(1.0), 1.0);
+        assert_eq!(keys.at(2.0), 0.0);
+        // Rounding over the peak, rather than a sharp corner
+        assert!(keys.at(0.9) > 0.9);
+        assert!(keys.at(1.1) > 0.9);
+        // Evenly spaced keys in a line stay on it
+        let line = Keyframes::new(vec![(0.0, 0.0), (1.0, 1.0), (2.0, 2.0)])
+            .with_interpolation(Interpolation::Smooth);
+        assert!((line.at(0.5) - 0.5).abs() < 0.0001);
+        assert!((line.at(1.5) - 1.5).abs() < 0.0001);
+    }
+    // The box around the segments' ends holds the whole curve, even where
+    // it overshoots the keys between samples
+    #[test]
+    fn segments_bound_the_curve() {
+        let keys = Keyframes::new(vec![(0.0, 0.0), (1.0, 0.0), (1.2, 1.0), (3.0, 1.0)])
+            .with_interpolation(Interpolation::Smooth);
+        let ends: Vec<f32> = keys
+            .segments()
+            .into_iter()
+            .flat_map(|(start, end)| [start, end])
+            .collect();
+        let min = ends.iter().cloned().fold(f32::MAX, f32::min);
+        let max = ends.iter().cloned().fold(-f32::MAX, f32::max);
+        let curve: Vec<f32> = (0..=3000).map(|i| keys.at(i as f32 / 1000.0)).collect();
+        // It does overshoot
+        assert!(curve.iter().any(|v| *v > 1.01));
+        assert!(curve.iter().any(|v| *v < -0.01));
+        for v in curve {
+            assert!(v >= min && v <= max, "{} {} {}", v, min, max);
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use crate::random;
+use crate::vec3::Vector3;
+
+const POINT_COUNT: usize = 256;
+
+// Perlin noise with random gradient vectors, from Ray Tracing: The Next Week
+pub struct Perlin {
+    ranvec: Vec<Vector3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+fn generate_perm() -> Vec<usize> {
+    let mut perm: Vec<usize> = (0..POINT_COUNT).collect();
+    for i in (1..POINT_COUNT).rev() {
+        let target = ((random::random_in_unit_interval() * (i + 1) as f32) as usize).min(i);
+        perm.swap(i, target);
+    }
+    perm
+}
+
+fn perlin_interp(c: &[[[Vector3; 2]; 2]; 2], u: f32, v: f32, w: f32) -> f32 {
+    // Hermite smoothing avoids grid artifacts
+    let uu = u * u * (3.0 - 2.0 * u);
+    let vv = v * v * (3.0 - 2.0 * v);
+    let ww = w * w * (3.0 - 2.0 * w);
+    let mut accum = 0.0;
+    for (i, ci) in c.iter().enumerate() {
+        for (j, cij) in ci.iter().enumerate() {
+            for (k, cijk) in cij.iter().enumerate() {
+                let (fi, fj, fk) = (i as f32, j as f32, k as f32);
+                let weight = Vector3::new(u - fi, v - fj, w - fk);
+                accum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                    * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                    * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                    * cijk.dot(&weight);
+            }
+        }
+    }
+    accum
+}
+
+impl Perlin {
+    pub fn new() -> Perlin {
+        let ranvec = (0..POINT_COUNT)
+            .map(|_| random::random_in_unit_sphere().get_unit_vector())
+            .collect();
+        Perlin {
+            ranvec,
+            perm_x: generate_perm(),
+            perm_y: generate_perm(),
+            perm_z: generate_perm(),
+        }
+    }
+
+    // Smooth noise in roughly [-1, 1]
+    pub fn noise(&self, p: &Vector3) -> f32 {
+        let u = p.x() - p.x().floor();
+        let v = p.y() - p.y().floor();
+        let w = p.z() - p.z().floor();
+        let i = p.x().floor() as i32;
+        let j = p.y().floor() as i32;
+        let k = p.z().floor() as i32;
+
+        let mut c = [[[Vector3::zero(); 2]; 2]; 2];
+        for (di, ci) in c.iter_mut().enumerate() {
+            for (dj, cij) in ci.iter_mut().enumerate() {
+                for (dk, cijk) in cij.iter_mut().enumerate() {
+                    *cijk = self.ranvec[self.perm_x[((i + di as i32) & 255) as usize]
+                        ^ self.perm_y[((j + dj as i32) & 255) as usize]
+                        ^ self.perm_z[((k + dk as i32) & 255) as usize]];
+                }
+            }
+        }
+        perlin_interp(&c, u, v, w)
+    }
+
+    // Sum of noise at several frequencies, in [0, ~1]
+    pub fn turb(&self, p: &Vector3, depth: u32) -> f32 {
+        let mut accum = 0.0;
+        let mut temp_p = *p;
+        let mut weight = 1.0;
+        for _ in 0..depth {
+            accum += weight * self.noise(&temp_p);
+            weight *= 0.5;
+            temp_p = temp_p * 2.0;
+        }
+        accum.abs()
+    }
+}
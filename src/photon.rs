@@ -0,0 +1,532 @@
+use crate::camera::{CameraModel, Shutter};
+use crate::hitable::{HitRecord, Hitable};
+use crate::integrator::{
+    mis_weight, sample_background, sample_lights, Integrator, Radiance, Sample,
+};
+use crate::material::Material;
+use crate::random::Sampler;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::vec3::Vector3;
+use std::f32;
+use std::sync::Arc;
+
+// Stochastic progressive photon mapping (Hachisuka and Jensen), as in PBRT.
+// Each iteration follows a path from every pixel through glass and mirrors
+// to the first diffuse surface, its visible point, then traces photons from
+// the lights into a photon map. Photons near a visible point are added to
+// its pixel, and the radius they're gathered from shrinks as more arrive,
+// so the image converges. Caustics, which the other integrators can only
+// find by chance, come out sharp.
+//
+// Light reaching visible points straight from the lights and background is
+// sampled as by the path tracer, and photons only count once they've
+// bounced. Photons aren't stored in fog, which paths from the camera
+// scatter through instead, and they don't come from the background, so
+// light from it only reaches surfaces directly.
+
+// Fraction of the new photons each iteration keeps, setting how fast the
+// radius shrinks
+const ALPHA: f32 = 2.0 / 3.0;
+
+#[derive(Clone, Copy)]
+struct Photon {
+    p: Vector3,
+    // Unit, back the way it came
+    wi: Vector3,
+    power: Vector3,
+}
+
+// Photons in a balanced kd-tree, stored in place: each range of the array
+// is split at its middle photon, on the axis its photons spread most along
+struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<usize>,
+}
+
+impl PhotonMap {
+    fn new(mut photons: Vec<Photon>) -> PhotonMap {
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        PhotonMap { photons, axes }
+    }
+
+    // Calls f with every photon within radius of p
+    fn for_each_within(&self, p: &Vector3, radius: f32, mut f: impl FnMut(&Photon)) {
+        self.gather(0, self.photons.len(), p, radius * radius, &mut f);
+    }
+
+    fn gather(
+        &self,
+        start: usize,
+        end: usize,
+        p: &Vector3,
+        radius_squared: f32,
+        f: &mut impl FnMut(&Photon),
+    ) {
+        if start >= end {
+            return;
+        }
+        let middle = start + (end - start) / 2;
+        let photon = &self.photons[middle];
+        if (photon.p - *p).length_squared() <= radius_squared {
+            f(photon);
+        }
+        let axis = self.axes[middle];
+        let offset = p.component(axis) - photon.p.component(axis);
+        // The near side first, then the far one if the sphere reaches it
+        let (near, far) = if offset <= 0.0 {
+            ((start, middle), (middle + 1, end))
+        } else {
+            ((middle + 1, end), (start, middle))
+        };
+        self.gather(near.0, near.1, p, radius_squared, f);
+        if offset * offset <= radius_squared {
+            self.gather(far.0, far.1, p, radius_squared, f);
+        }
+    }
+}
+
+fn build(photons: &mut [Photon], axes: &mut [usize]) {
+    if photons.is_empty() {
+        return;
+    }
+    let mut min = photons[0].p;
+    let mut max = photons[0].p;
+    for photon in photons.iter() {
+        min = Vector3::new(
+            min.x().min(photon.p.x()),
+            min.y().min(photon.p.y()),
+            min.z().min(photon.p.z()),
+        );
+        max = Vector3::new(
+            max.x().max(photon.p.x()),
+            max.y().max(photon.p.y()),
+            max.z().max(photon.p.z()),
+        );
+    }
+    let extent = max - min;
+    let axis = (0..3)
+        .max_by(|a, b| extent.component(*a).total_cmp(&extent.component(*b)))
+        .unwrap();
+
+    let middle = photons.len() / 2;
+    photons.select_nth_unstable_by(middle, |a, b| {
+        a.p.component(axis).total_cmp(&b.p.component(axis))
+    });
+    axes[middle] = axis;
+    let (photons_below, photons_above) = photons.split_at_mut(middle);
+    let (axes_below, axes_above) = axes.split_at_mut(middle);
+    build(photons_below, axes_below);
+    build(&mut photons_above[1..], &mut axes_above[1..]);
+}
+
+// The first diffuse surface on a path from a pixel, and the path's
+// throughput to it
+struct VisiblePoint {
+    ray: Ray,
+    hr: HitRecord,
+    beta: Vector3,
+}
+
+// A pixel's estimate so far
+struct Pixel {
+    // Summed over iterations
+    direct: Vector3,
+    radius: f32,
+    // Photons gathered, as reduced by ALPHA
+    count: f32,
+    // Flux from the photons gathered, scaled with the radius
+    flux: Vector3,
+}
+
+// Follows r to its visible point, if it has one, giving the light along the
+// way and arriving there directly
+fn trace_camera_path<T: Hitable>(
+    r: &Ray,
+    scene: &Scene<T>,
+    sampler: &mut Sampler,
+) -> (Vector3, Option<VisiblePoint>) {
+    let mut ray = r.clone();
+    let mut direct = Vector3::zero();
+    let mut beta = Vector3::new(1.0, 1.0, 1.0);
+    // As for the path tracer, the density the ray was scattered with if
+    // lights were also sampled where it started
+    let mut bsdf_pdf = None;
+    let mut bounces = [0; 4];
+    let mut visible = None;
+
+    loop {
+        let hr = match scene.hit(&ray, sampler) {
+            Some(hr) => hr,
+            None => {
+                let mut background = scene.background.color(&ray);
+                if let Some(pdf) = bsdf_pdf {
+                    background =
+                        background * mis_weight(pdf, scene.background.pdf(&ray.direction()));
+                }
+                direct = direct + beta * background;
+                break;
+            }
+        };
+
+        let mut emitted = hr.material.emitted(&ray, &hr);
+        if let Some(pdf) = bsdf_pdf {
+            emitted = emitted * mis_weight(pdf, scene.light_tree.pdf(&scene.lights, &ray, &hr));
+        }
+        direct = direct + beta * emitted;
+        // Only followed this far for light hit by sampling the visible
+        // point's BSDF
+        if visible.is_some() {
+            break;
+        }
+
+        let s = match hr.material.scatter(&ray, &hr, sampler) {
+            Some(s) => s,
+            None => break,
+        };
+        let bounce = hr.material.bounce();
+        if bounces[bounce as usize] >= scene.settings.limits.max(bounce) {
+            break;
+        }
+        bounces[bounce as usize] += 1;
+
+        if s.pdf.is_some() {
+            let lights = sample_lights(&ray, &hr, scene, true, sampler)
+                + sample_background(&ray, &hr, scene, sampler);
+            direct = direct + beta * lights;
+            if !matches!(hr.material, Material::Volume { .. }) {
+                visible = Some(VisiblePoint {
+                    ray: ray.clone(),
+                    hr,
+                    beta,
+                });
+            }
+        }
+        beta = beta * s.color;
+        bsdf_pdf = s.pdf;
+        ray = s.ray;
+    }
+    (direct, visible)
+}
+
+// Traces count photons from lights picked uniformly, storing them where
+// they land on diffuse surfaces after bouncing at least once. Their power
+// is per photon path, not yet divided by how many were traced.
+fn trace_photons<T: Hitable>(
+    scene: &Scene<T>,
+    shutter: &Shutter,
+    count: usize,
+    sampler: &mut Sampler,
+) -> Vec<Photon> {
+    let mut photons = Vec::new();
+    if scene.lights.is_empty() {
+        return photons;
+    }
+    let (center, radius) = scene.bounds();
+    let pmf = 1.0 / scene.lights.len() as f32;
+    for _ in 0..count {
+        let index = sampler.index(scene.lights.len());
+        let e = match scene.lights[index].sample_emission(&center, radius, sampler) {
+            Some(e) => e,
+            None => continue,
+        };
+        let cosine = if e.normal == Vector3::zero() {
+            1.0
+        } else {
+            e.normal.dot(&e.direction).abs()
+        };
+        let mut power = e.radiance * (cosine / (pmf * e.pdf_pos * e.pdf_dir));
+        let mut ray = Ray::new(e.origin, e.direction).with_time(shutter.sample(sampler));
+        let mut bounces = [0; 4];
+        let mut depth = 0;
+
+        while let Some(hr) = scene.hit(&ray, sampler) {
+            let volume = matches!(hr.material, Material::Volume { .. });
+            if depth > 0 && !volume && !hr.material.is_specular() {
+                photons.push(Photon {
+                    p: hr.p,
+                    wi: -ray.direction().get_unit_vector(),
+                    power,
+                });
+            }
+
+            let s = match hr.material.scatter(&ray, &hr, sampler) {
+                Some(s) => s,
+                None => break,
+            };
+            let bounce = hr.material.bounce();
+            if bounces[bounce as usize] >= scene.settings.limits.max(bounce) {
+                break;
+            }
+            bounces[bounce as usize] += 1;
+            depth += 1;
+            power = power * s.color;
+            ray = s.ray;
+
+            if depth >= scene.settings.limits.roulette_depth {
+                let survival = power.x().max(power.y()).max(power.z()).min(0.95);
+                if sampler.uniform() >= survival {
+                    break;
+                }
+                power = power / survival;
+            }
+        }
+    }
+    photons
+}
+
+// Photon mapping, with photons_per_iteration photons traced each iteration
+// and gathered from radius at first, in the scene's units. Each sample per
+// pixel is an iteration, so it renders whole images.
+pub struct Sppm {
+    camera: Arc<dyn CameraModel>,
+    photons_per_iteration: usize,
+    radius: f32,
+}
+
+impl Sppm {
+    pub fn new(camera: Arc<dyn CameraModel>, photons_per_iteration: usize, radius: f32) -> Sppm {
+        Sppm {
+            camera,
+            photons_per_iteration,
+            radius,
+        }
+    }
+}
+
+impl<T: Hitable> Integrator<T> for Sppm {
+    // Only the first hit, as light is gathered over whole images
+    fn sample(&self, r: &Ray, scene: &Scene<T>, sampler: &mut Sampler) -> Sample {
+        Sample::new(Radiance::zero(), scene.hit(r, sampler))
+    }
+
+    fn render(
+        &self,
+        scene: &Scene<T>,
+        shutter: &Shutter,
+        width: u32,
+        height: u32,
+        samples: u32,
+        sampler: &mut Sampler,
+    ) -> Option<Vec<Vector3>> {
+        Some(render(
+            self.camera.as_ref(),
+            scene,
+            shutter,
+            width,
+            height,
+            samples,
+            self.photons_per_iteration,
+            self.radius,
+            sampler,
+        ))
+    }
+}
+
+// Renders an image with rows from the top over the given iterations,
+// tracing photons_per_iteration photons each time. Gathering starts from
+// radius, in the scene's units.
+#[allow(clippy::too_many_arguments)]
+fn render<T: Hitable>(
+    cam: &dyn CameraModel,
+    scene: &Scene<T>,
+    shutter: &Shutter,
+    width: u32,
+    height: u32,
+    iterations: u32,
+    photons_per_iteration: usize,
+    radius: f32,
+    sampler: &mut Sampler,
+) -> Vec<Vector3> {
+    let mut pixels: Vec<Pixel> = (0..width * height)
+        .map(|_| Pixel {
+            direct: Vector3::zero(),
+            radius,
+            count: 0.0,
+            flux: Vector3::zero(),
+        })
+        .collect();
+
+    for _ in 0..iterations {
+        let mut visible_points = Vec::with_capacity(pixels.len());
+        for j in 0..height {
+            for i in 0..width {
+                let x = i as f32 + sampler.uniform();
+                let y = j as f32 + sampler.uniform();
+                let ray = match cam.get_ray(x / width as f32, 1.0 - y / height as f32, sampler) {
+                    Some(ray) => ray.with_time(shutter.sample(sampler)),
+                    None => {
+                        visible_points.push(None);
+                        continue;
+                    }
+                };
+                let (direct, visible) = trace_camera_path(&ray, scene, sampler);
+                let pixel = &mut pixels[(i + j * width) as usize];
+                pixel.direct = pixel.direct + direct;
+                visible_points.push(visible);
+            }
+        }
+
+        let photons = trace_photons(scene, shutter, photons_per_iteration, sampler);
+        let map = PhotonMap::new(photons);
+        for (pixel, visible) in pixels.iter_mut().zip(visible_points) {
+            let visible = match visible {
+                Some(visible) => visible,
+                None => continue,
+            };
+            let wo = -visible.ray.direction().get_unit_vector();
+            let mut found = 0.0;
+            let mut flux = Vector3::zero();
+            map.for_each_within(&visible.hr.p, pixel.radius, |photon| {
+                let f = visible.hr.material.f(&visible.hr, &wo, &photon.wi, None);
+                flux = flux + photon.power * f;
+                found += 1.0;
+            });
+            if found == 0.0 {
+                continue;
+            }
+            let count = pixel.count + ALPHA * found;
+            let radius = pixel.radius * (count / (pixel.count + found)).sqrt();
+            pixel.flux = (pixel.flux + visible.beta * flux) * (radius * radius)
+                / (pixel.radius * pixel.radius);
+            pixel.count = count;
+            pixel.radius = radius;
+        }
+    }
+
+    let photons = (iterations as usize * photons_per_iteration) as f32;
+    pixels
+        .iter()
+        .map(|pixel| {
+            let area = f32::consts::PI * pixel.radius * pixel.radius;
+            let indirect = if photons > 0.0 {
+                pixel.flux / (photons * area)
+            } else {
+                Vector3::zero()
+            };
+            pixel.direct / iterations.max(1) as f32 + indirect
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::background::Background;
+    use crate::camera::Camera;
+    use crate::emission::Emission;
+    use crate::hitable::HitableList;
+    use crate::integrator::PathTracer;
+    use crate::light::MeshLight;
+    use crate::random;
+    use crate::scene::{PathLimits, RenderSettings};
+    use crate::triangle;
+
+    #[test]
+    fn finds_the_photons_in_range() {
+        let photons: Vec<Photon> = (0..500)
+            .map(|_| Photon {
+                p: 2.0 * random::random_in_unit_sphere(),
+                wi: Vector3::new(0.0, 1.0, 0.0),
+                power: Vector3::zero(),
+            })
+            .collect();
+        let map = PhotonMap::new(photons.clone());
+        for _ in 0..20 {
+            let p = random::random_in_unit_sphere();
+            let radius = 0.5 * random::random_in_unit_interval();
+            let mut found = Vec::new();
+            map.for_each_within(&p, radius, |photon| found.push(photon.p));
+            let expected = photons
+                .iter()
+                .filter(|photon| (photon.p - p).length() <= radius)
+                .count();
+            assert_eq!(found.len(), expected);
+            assert!(found.iter().all(|q| (*q - p).length() <= radius * 1.0001));
+        }
+    }
+
+    // Between a floor and a ceiling, with a panel light shining up, so the
+    // floor is only lit by light off the ceiling, which photons bring.
+    // Gathering converges on what the path tracer finds.
+    #[test]
+    fn lit_floor_has_its_radiance() {
+        let grey = Material::Lambertian {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        };
+        let (x, z) = (Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 1.0));
+        let mut room = HitableList::new();
+        // Facing up, then down
+        for (corner, u, v) in [
+            (Vector3::new(-1.0, 0.0, -1.0), 2.0 * z, 2.0 * x),
+            (Vector3::new(-1.0, 1.0, -1.0), 2.0 * x, 2.0 * z),
+        ] {
+            for t in triangle::quad(corner, u, v, grey.clone()) {
+                room.add(t);
+            }
+        }
+        let mut scene = Scene::new(room);
+        scene.add_area_light(MeshLight::new(
+            triangle::quad(Vector3::new(0.2, 0.5, -0.3), 0.6 * z, 0.6 * x, grey),
+            Emission {
+                two_sided: false,
+                ..Emission::new(Vector3::new(4.0, 4.0, 4.0))
+            },
+            None,
+        ));
+        scene.background = Background::Constant(Vector3::zero());
+        scene.settings = RenderSettings {
+            light_sampling: true,
+            limits: PathLimits {
+                diffuse: 4,
+                glossy: 4,
+                transmission: 4,
+                volume: 4,
+                roulette_depth: 4,
+            },
+        };
+        let scene = scene.build().unwrap();
+        // Below the panel, looking down
+        let cam = Arc::new(Camera::new(
+            Vector3::new(-0.5, 0.9, 0.0),
+            Vector3::new(-0.5, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 1.0),
+            40.0,
+            1.0,
+            0.0,
+            2.0,
+        ));
+        let size = 8;
+        let samples = 1024;
+
+        let mut sampler = Sampler::new(0);
+        let mut sum = 0.0;
+        for j in 0..size {
+            for i in 0..size {
+                for _ in 0..samples {
+                    let x = (i as f32 + sampler.uniform()) / size as f32;
+                    let y = (j as f32 + sampler.uniform()) / size as f32;
+                    let ray = cam.get_ray(x, 1.0 - y, &mut sampler).unwrap();
+                    let sample = PathTracer.sample(&ray, &scene, &mut sampler);
+                    sum += sample.radiance.total().x();
+                }
+            }
+        }
+        let expected = sum / (size * size * samples) as f32;
+
+        let shutter = Shutter::new(0.0, 0.0);
+        let mut render = |photons| {
+            let sppm = Sppm::new(cam.clone(), photons, 0.1);
+            let pixels = sppm
+                .render(&scene, &shutter, size, size, 32, &mut sampler)
+                .unwrap();
+            pixels.iter().map(|p| p.x()).sum::<f32>() / pixels.len() as f32
+        };
+        // Without photons, it's dark
+        assert!(render(0) == 0.0);
+        let total = render(10000);
+        let error = (total - expected).abs() / expected;
+        assert!(error < 0.06, "{} {}", total, expected);
+    }
+}
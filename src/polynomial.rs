@@ -0,0 +1,152 @@
+// Real roots of polynomials up to degree four, for ray intersections with
+// implicit surfaces. Coefficients run from the highest power down. Worked
+// in f64, as the quartic's roots lose precision quickly in f32.
+
+// In no particular order, with any double root twice
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a == 0.0 {
+        return if b == 0.0 { Vec::new() } else { vec![-c / b] };
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return Vec::new();
+    }
+    // Avoids cancellation between -b and the root of the discriminant
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    if q == 0.0 {
+        return vec![0.0, 0.0];
+    }
+    vec![q / a, c / q]
+}
+
+// x^3 + a x^2 + b x + c
+fn solve_monic_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    // Depressed, in y = x + a / 3: y^3 + 3 p y + 2 q
+    let p = (3.0 * b - a * a) / 9.0;
+    let q = (2.0 * a * a * a - 9.0 * a * b + 27.0 * c) / 54.0;
+    let shift = a / 3.0;
+    let discriminant = q * q + p * p * p;
+    if discriminant > 0.0 {
+        // One real root, by Cardano's formula
+        let s = discriminant.sqrt();
+        vec![(-q + s).cbrt() + (-q - s).cbrt() - shift]
+    } else if p == 0.0 {
+        vec![-shift]
+    } else {
+        // Three, by the trigonometric method
+        let phi = (-q / (-p * p * p).sqrt()).clamp(-1.0, 1.0).acos() / 3.0;
+        let m = 2.0 * (-p).sqrt();
+        let third = 2.0 * std::f64::consts::PI / 3.0;
+        vec![
+            m * phi.cos() - shift,
+            m * (phi + third).cos() - shift,
+            m * (phi - third).cos() - shift,
+        ]
+    }
+}
+
+// By Ferrari's method, as in Graphics Gems' Roots3And4.c, then polished
+// with Newton's method on the original polynomial, sorted
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64, e: f64) -> Vec<f64> {
+    if a == 0.0 {
+        let mut roots = if b == 0.0 {
+            solve_quadratic(c, d, e)
+        } else {
+            solve_monic_cubic(c / b, d / b, e / b)
+        };
+        roots.sort_by(|x, y| x.partial_cmp(y).unwrap());
+        return roots;
+    }
+    let (a3, a2, a1, a0) = (b / a, c / a, d / a, e / a);
+
+    // Depressed, in y = x + a3 / 4: y^4 + p y^2 + q y + r
+    let sq = a3 * a3;
+    let p = -3.0 / 8.0 * sq + a2;
+    let q = sq * a3 / 8.0 - a3 * a2 / 2.0 + a1;
+    let r = -3.0 / 256.0 * sq * sq + sq * a2 / 16.0 - a3 * a1 / 4.0 + a0;
+
+    let mut roots = if r.abs() < 1e-12 {
+        // y (y^3 + p y + q) = 0
+        let mut roots = solve_monic_cubic(0.0, p, q);
+        roots.push(0.0);
+        roots
+    } else {
+        // Split into two quadratics by a root of the resolvent cubic
+        let z = solve_monic_cubic(-p / 2.0, -r, r * p / 2.0 - q * q / 8.0)[0];
+        let u = z * z - r;
+        let v = 2.0 * z - p;
+        let u = if u.abs() < 1e-12 {
+            0.0
+        } else if u > 0.0 {
+            u.sqrt()
+        } else {
+            return Vec::new();
+        };
+        let v = if v.abs() < 1e-12 {
+            0.0
+        } else if v > 0.0 {
+            v.sqrt()
+        } else {
+            return Vec::new();
+        };
+        let v = if q < 0.0 { -v } else { v };
+        let mut roots = solve_quadratic(1.0, v, z - u);
+        roots.extend(solve_quadratic(1.0, -v, z + u));
+        roots
+    };
+
+    for root in roots.iter_mut() {
+        *root -= a3 / 4.0;
+        for _ in 0..2 {
+            let value = (((a * *root + b) * *root + c) * *root + d) * *root + e;
+            let slope = ((4.0 * a * *root + 3.0 * b) * *root + 2.0 * c) * *root + d;
+            if slope != 0.0 {
+                *root -= value / slope;
+            }
+        }
+    }
+    roots.sort_by(|x, y| x.partial_cmp(y).unwrap());
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(roots: &[f64], expected: &[f64]) -> bool {
+        roots.len() == expected.len()
+            && roots
+                .iter()
+                .zip(expected)
+                .all(|(a, b)| (a - b).abs() < 1e-6)
+    }
+
+    #[test]
+    fn finds_real_roots() {
+        let mut roots = solve_quadratic(2.0, -2.0, -4.0);
+        roots.sort_by(|x, y| x.partial_cmp(y).unwrap());
+        assert!(close(&roots, &[-1.0, 2.0]));
+        assert!(solve_quadratic(1.0, 0.0, 1.0).is_empty());
+
+        // (x + 2)(x - 1)(x - 3)(x - 4)
+        let roots = solve_quartic(1.0, -6.0, 3.0, 26.0, -24.0);
+        assert!(close(&roots, &[-2.0, 1.0, 3.0, 4.0]), "{:?}", roots);
+        // (x^2 + 1)(x - 1)(x - 2), two complex
+        let roots = solve_quartic(1.0, -3.0, 3.0, -3.0, 2.0);
+        assert!(close(&roots, &[1.0, 2.0]), "{:?}", roots);
+        // (x^2 + 1)(x^2 + 4), none
+        assert!(solve_quartic(1.0, 0.0, 5.0, 0.0, 4.0).is_empty());
+        // x (x - 1)(x - 2)(x - 3), with no constant term
+        let roots = solve_quartic(1.0, -6.0, 11.0, -6.0, 0.0);
+        assert!(close(&roots, &[0.0, 1.0, 2.0, 3.0]), "{:?}", roots);
+        // 3 (x - 1)(x - 2)^2 (x - 3) nudged up, splitting the double root
+        // by 0.01 either way, as for a ray grazing a torus
+        let roots = solve_quartic(3.0, -24.0, 69.0, -84.0, 36.0 + 3e-4);
+        let expected = [1.0, 1.99, 2.01, 3.0];
+        assert_eq!(roots.len(), 4, "{:?}", roots);
+        assert!(roots
+            .iter()
+            .zip(&expected)
+            .all(|(a, b)| (a - b).abs() < 1e-4));
+    }
+}
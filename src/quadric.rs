@@ -0,0 +1,211 @@
+use crate::aabb::AABB;
+use crate::hitable::{HitRecord, Hitable};
+use crate::material::Material;
+use crate::polynomial;
+use crate::ray::Ray;
+use crate::vec3::Vector3;
+use std::f32;
+
+// How far outside the box hits still count, so its edges don't crack
+const EPSILON: f32 = 0.0001;
+
+// A surface of the second degree,
+// a x^2 + b y^2 + c z^2 + d xy + e yz + f xz + g x + h y + i z + j = 0,
+// e.g. a cylinder, cone, paraboloid or hyperboloid. Most go on forever, so
+// only the part in the bounding box is kept, which is usually open.
+#[derive(Clone)]
+pub struct Quadric {
+    coefficients: [f64; 10],
+    bbox: AABB,
+    material: Material,
+}
+
+impl Quadric {
+    pub fn new(coefficients: [f32; 10], bbox: AABB, material: Material) -> Quadric {
+        Quadric {
+            coefficients: coefficients.map(f64::from),
+            bbox,
+            material,
+        }
+    }
+
+    // Standing on base_center, with no ends
+    pub fn cylinder(base_center: Vector3, radius: f32, height: f32, material: Material) -> Quadric {
+        let (x, z) = (base_center.x(), base_center.z());
+        let half_size = Vector3::new(radius, 0.0, radius);
+        let top = Vector3::new(0.0, height, 0.0);
+        Quadric::new(
+            [
+                1.0,
+                0.0,
+                1.0,
+                0.0,
+                0.0,
+                0.0,
+                -2.0 * x,
+                0.0,
+                -2.0 * z,
+                x * x + z * z - radius * radius,
+            ],
+            AABB::new(base_center - half_size, base_center + half_size + top),
+            material,
+        )
+    }
+
+    // Standing on base_center, narrowing to a point at the top, with no
+    // base
+    pub fn cone(base_center: Vector3, radius: f32, height: f32, material: Material) -> Quadric {
+        let (x, z) = (base_center.x(), base_center.z());
+        let apex = base_center.y() + height;
+        let slope = (radius / height) * (radius / height);
+        let half_size = Vector3::new(radius, 0.0, radius);
+        let top = Vector3::new(0.0, height, 0.0);
+        Quadric::new(
+            [
+                1.0,
+                -slope,
+                1.0,
+                0.0,
+                0.0,
+                0.0,
+                -2.0 * x,
+                2.0 * slope * apex,
+                -2.0 * z,
+                x * x + z * z - slope * apex * apex,
+            ],
+            AABB::new(base_center - half_size, base_center + half_size + top),
+            material,
+        )
+    }
+
+    fn contains(&self, p: Vector3) -> bool {
+        (0..3).all(|axis| {
+            p.component(axis) > self.bbox.min().component(axis) - EPSILON
+                && p.component(axis) < self.bbox.max().component(axis) + EPSILON
+        })
+    }
+
+    // Where the line of r crosses the surface inside the box, nearest
+    // first
+    fn roots(&self, r: &Ray) -> Vec<f32> {
+        let [a, b, c, d, e, f, g, h, i, j] = self.coefficients;
+        let (o, dir) = (r.origin(), r.direction());
+        let (ox, oy, oz) = (f64::from(o.x()), f64::from(o.y()), f64::from(o.z()));
+        let (dx, dy, dz) = (f64::from(dir.x()), f64::from(dir.y()), f64::from(dir.z()));
+        let a_t = a * dx * dx + b * dy * dy + c * dz * dz + d * dx * dy + e * dy * dz + f * dx * dz;
+        let b_t = 2.0 * (a * ox * dx + b * oy * dy + c * oz * dz)
+            + d * (ox * dy + oy * dx)
+            + e * (oy * dz + oz * dy)
+            + f * (ox * dz + oz * dx)
+            + g * dx
+            + h * dy
+            + i * dz;
+        let c_t = a * ox * ox
+            + b * oy * oy
+            + c * oz * oz
+            + d * ox * oy
+            + e * oy * oz
+            + f * ox * oz
+            + g * ox
+            + h * oy
+            + i * oz
+            + j;
+        let mut roots: Vec<f32> = polynomial::solve_quadratic(a_t, b_t, c_t)
+            .into_iter()
+            .map(|t| t as f32)
+            .filter(|&t| self.contains(r.point_at_parameter(t)))
+            .collect();
+        roots.sort_by(|x, y| x.partial_cmp(y).unwrap());
+        roots
+    }
+
+    fn hit_record(&self, r: &Ray, t: f32) -> HitRecord {
+        let [a, b, c, d, e, f, g, h, i, _] = self.coefficients.map(|k| k as f32);
+        let p = r.point_at_parameter(t);
+        let (x, y, z) = (p.x(), p.y(), p.z());
+        // The gradient, pointing to where the equation's positive
+        let normal = Vector3::new(
+            2.0 * a * x + d * y + f * z + g,
+            2.0 * b * y + d * x + e * z + h,
+            2.0 * c * z + e * y + f * x + i,
+        )
+        .get_unit_vector();
+        // Around the box's vertical axis, and up it
+        let (min, max) = (self.bbox.min(), self.bbox.max());
+        let middle = (min + max) / 2.0;
+        let phi = (z - middle.z()).atan2(x - middle.x());
+        HitRecord {
+            t,
+            p,
+            normal,
+            u: 0.5 + phi / (2.0 * f32::consts::PI),
+            v: (y - min.y()) / (max.y() - min.y()),
+            material: self.material.clone(),
+            object_id: 0,
+        }
+    }
+}
+
+impl Hitable for Quadric {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        if !self.bbox.hit(r, t_min, t_max) {
+            return None;
+        }
+        self.roots(r)
+            .into_iter()
+            .find(|&t| t > t_min && t < t_max)
+            .map(|t| self.hit_record(r, t))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.coefficients.iter().all(|&k| k == 0.0) {
+            Err("Quadric has no terms".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.bbox.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grey() -> Material {
+        Material::Lambertian {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        }
+    }
+
+    #[test]
+    fn clips_to_the_box() {
+        let base = Vector3::new(1.0, 0.0, 0.0);
+        let cylinder = Quadric::cylinder(base, 0.5, 2.0, grey());
+        let r = Ray::new(Vector3::new(-10.0, 1.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        let hr = cylinder.hit(&r, 0.001, f32::MAX).unwrap();
+        assert!((hr.p.x() - 0.5).abs() < 0.0001);
+        assert!((hr.normal - Vector3::new(-1.0, 0.0, 0.0)).length() < 0.0001);
+        assert!((hr.v - 0.5).abs() < 0.0001);
+        // Open at the top, so a ray down the middle misses
+        let down = Ray::new(Vector3::new(1.0, 10.0, 0.0), Vector3::new(0.0, -1.0, 0.0));
+        assert!(cylinder.hit(&down, 0.001, f32::MAX).is_none());
+        // And nothing above it
+        let above = Ray::new(Vector3::new(-10.0, 3.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        assert!(cylinder.hit(&above, 0.001, f32::MAX).is_none());
+
+        // Half as wide half way up, without the cone's upside down copy
+        // above the apex
+        let cone = Quadric::cone(base, 0.5, 2.0, grey());
+        let hr = cone.hit(&r, 0.001, f32::MAX).unwrap();
+        assert!((hr.p.x() - 0.75).abs() < 0.0001);
+        assert!(hr.normal.x() < 0.0 && hr.normal.y() > 0.0);
+        assert!(cone.hit(&above, 0.001, f32::MAX).is_none());
+
+        assert!(Quadric::new([0.0; 10], cylinder.bbox.clone(), grey())
+            .validate()
+            .is_err());
+    }
+}
@@ -1,18 +1,6 @@
 use crate::vec3::Vector3;
-use rand::Rng;
-
-pub fn random_in_unit_disk() -> Vector3 {
-    let mut p;
-    loop {
-        let x = rand::thread_rng().gen_range(0.0, 1.0);
-        let y = rand::thread_rng().gen_range(0.0, 1.0);
-        p = Vector3::new(x, y, 0.0) * 2.0 - Vector3::new(1.0, 1.0, 0.0);
-        if p.dot(&p) < 1.0 {
-            break;
-        }
-    }
-    p
-}
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 pub fn random_in_unit_sphere() -> Vector3 {
     let mut p;
@@ -31,3 +19,63 @@ pub fn random_in_unit_sphere() -> Vector3 {
 pub fn random_in_unit_interval() -> f32 {
     rand::thread_rng().gen_range(0.0, 1.0)
 }
+
+// Random numbers for rendering: the camera's lens and shutter, the
+// integrator's choices, and the materials, lights and backgrounds it
+// samples. Each render thread has its own, seeded so they don't repeat
+// each other's. Only building scenes, and media objects sampling free
+// paths inside their hit(), use the thread's generator above.
+pub struct Sampler {
+    rng: StdRng,
+}
+
+impl Sampler {
+    pub fn new(seed: u64) -> Sampler {
+        Sampler {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    // In [0, 1)
+    pub fn uniform(&mut self) -> f32 {
+        self.rng.gen_range(0.0, 1.0)
+    }
+
+    // One of count things, picked uniformly
+    pub fn index(&mut self, count: usize) -> usize {
+        ((self.uniform() * count as f32) as usize).min(count - 1)
+    }
+
+    // In the unit disk, in the xy plane
+    pub fn in_unit_disk(&mut self) -> Vector3 {
+        loop {
+            let p = Vector3::new(self.uniform(), self.uniform(), 0.0) * 2.0
+                - Vector3::new(1.0, 1.0, 0.0);
+            if p.dot(&p) < 1.0 {
+                return p;
+            }
+        }
+    }
+
+    pub fn in_unit_sphere(&mut self) -> Vector3 {
+        loop {
+            let p = Vector3::new(self.uniform(), self.uniform(), self.uniform()) * 2.0
+                - Vector3::new(1.0, 1.0, 1.0);
+            if p.dot(&p) < 1.0 {
+                return p;
+            }
+        }
+    }
+
+    // Uniformly distributed on the surface of the unit sphere
+    pub fn unit_vector(&mut self) -> Vector3 {
+        loop {
+            let p = Vector3::new(self.uniform(), self.uniform(), self.uniform()) * 2.0
+                - Vector3::new(1.0, 1.0, 1.0);
+            let length_squared = p.length_squared();
+            if length_squared < 1.0 && length_squared > 0.0 {
+                return p / length_squared.sqrt();
+            }
+        }
+    }
+}
@@ -1,15 +1,46 @@
 use crate::vec3::Vector3;
 use std::fmt;
 
+#[derive(Clone)]
 pub struct Ray {
     origin: Vector3,
     direction: Vector3,
+    // Only set when rendering spectrally
+    wavelength: Option<f32>,
+    // When the ray was sent, for motion blur
+    time: f32,
 }
 
 impl Ray {
     pub fn new(origin: Vector3, direction: Vector3) -> Ray {
-        Ray { origin, direction }
+        Ray {
+            origin,
+            direction,
+            wavelength: None,
+            time: 0.0,
+        }
     }
+
+    pub fn with_wavelength(mut self, wavelength: f32) -> Ray {
+        self.wavelength = Some(wavelength);
+        self
+    }
+
+    pub fn with_time(mut self, time: f32) -> Ray {
+        self.time = time;
+        self
+    }
+
+    // A ray continuing this one's path, e.g. after scattering
+    pub fn spawn(&self, origin: Vector3, direction: Vector3) -> Ray {
+        Ray {
+            origin,
+            direction,
+            wavelength: self.wavelength,
+            time: self.time,
+        }
+    }
+
     pub fn origin(&self) -> Vector3 {
         self.origin
     }
@@ -18,6 +49,14 @@ impl Ray {
         self.direction
     }
 
+    pub fn wavelength(&self) -> Option<f32> {
+        self.wavelength
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
     pub fn point_at_parameter(&self, t: f32) -> Vector3 {
         self.origin + self.direction * t
     }
@@ -0,0 +1,228 @@
+use crate::background::Background;
+use crate::bvh::BVHNode;
+use crate::camera::CameraTrack;
+use crate::hitable::{HitRecord, Hitable, HitableList};
+use crate::light::{Light, MeshLight, SphereLight};
+use crate::light_tree::LightTree;
+use crate::material::Bounce;
+use crate::medium::Fog;
+use crate::random::Sampler;
+use crate::ray::Ray;
+use crate::vec3::Vector3;
+use std::f32;
+use std::sync::Arc;
+
+// How long paths get. Each kind of bounce has its own limit. After
+// roulette_depth bounces, paths are ended at random, more likely the less
+// light they still carry, and the survivors weighted up to make up for it.
+#[derive(Clone, Copy)]
+pub struct PathLimits {
+    pub diffuse: u32,
+    pub glossy: u32,
+    pub transmission: u32,
+    pub volume: u32,
+    pub roulette_depth: u32,
+}
+
+impl PathLimits {
+    pub fn max(&self, bounce: Bounce) -> u32 {
+        match bounce {
+            Bounce::Diffuse => self.diffuse,
+            Bounce::Glossy => self.glossy,
+            Bounce::Transmission => self.transmission,
+            Bounce::Volume => self.volume,
+        }
+    }
+}
+
+// How paths through the scene are traced
+#[derive(Clone, Copy)]
+pub struct RenderSettings {
+    pub light_sampling: bool,
+    pub limits: PathLimits,
+}
+
+impl Default for RenderSettings {
+    // As on the command line
+    fn default() -> RenderSettings {
+        RenderSettings {
+            light_sampling: false,
+            limits: PathLimits {
+                diffuse: 16,
+                glossy: 16,
+                transmission: 32,
+                volume: 64,
+                roulette_depth: 3,
+            },
+        }
+    }
+}
+
+// Everything needed to render a scene. The scenes are put together with
+// their objects in a list, and built into a BVH to render.
+#[derive(Clone)]
+pub struct Scene<T: Hitable> {
+    pub objects: T,
+    pub lights: Vec<Light>,
+    // Made from the lights when the scene is built
+    pub light_tree: Arc<LightTree>,
+    // Times are in frames
+    pub camera: CameraTrack,
+    // What rays escaping the scene see
+    pub background: Background,
+    pub fog: Option<Fog>,
+    pub settings: RenderSettings,
+}
+
+impl Scene<HitableList> {
+    pub fn new(objects: HitableList) -> Scene<HitableList> {
+        Scene {
+            objects,
+            lights: Vec::new(),
+            light_tree: Arc::new(LightTree::new(&[])),
+            camera: CameraTrack::new(Vector3::new(13.0, 2.0, 3.0), Vector3::zero(), 30.0, 0.1),
+            background: Background::Constant(Vector3::new(0.2, 0.2, 0.2)),
+            fog: None,
+            settings: RenderSettings::default(),
+        }
+    }
+
+    pub fn add_area_light(&mut self, light: MeshLight) {
+        let light = light.numbered(Some(self.lights.len()));
+        let mut mesh = HitableList::new();
+        for triangle in light.triangles() {
+            mesh.add(triangle.clone());
+        }
+        self.objects.add(mesh);
+        self.lights.push(Light::Area(Arc::new(light)));
+    }
+
+    pub fn add_sphere_light(&mut self, light: SphereLight) {
+        let light = light.numbered(Some(self.lights.len()));
+        self.objects.add(light.sphere().clone());
+        self.lights.push(Light::Sphere(Arc::new(light)));
+    }
+
+    // Checks every object can be rendered, then puts them in a BVH and the
+    // lights in a light tree
+    pub fn build(self) -> Result<Scene<BVHNode>, String> {
+        if self.objects.hitables.is_empty() {
+            return Err("The scene is empty".to_string());
+        }
+        for (i, object) in self.objects.hitables.iter().enumerate() {
+            object
+                .validate()
+                .map_err(|e| format!("Object {}: {}", i + 1, e))?;
+            let bbox = object
+                .bounding_box()
+                .ok_or_else(|| format!("Object {} has no bounds", i + 1))?;
+            let finite = |v: Vector3| v.x().is_finite() && v.y().is_finite() && v.z().is_finite();
+            if !finite(bbox.min()) || !finite(bbox.max()) {
+                return Err(format!("Object {} has infinite bounds", i + 1));
+            }
+        }
+        let fog = self.fog;
+        let mut scene = Scene {
+            objects: BVHNode::new(self.objects.hitables),
+            light_tree: Arc::new(LightTree::new(&self.lights)),
+            lights: self.lights,
+            camera: self.camera,
+            background: self.background,
+            fog: None,
+            settings: self.settings,
+        };
+        let (center, radius) = scene.bounds();
+        scene.fog = fog.map(|fog| fog.within(center, radius));
+        Ok(scene)
+    }
+}
+
+impl<T: Hitable> Scene<T> {
+    // Where r first hits a surface or scatters in the fog
+    pub fn hit(&self, r: &Ray, sampler: &mut Sampler) -> Option<HitRecord> {
+        let hit = self.objects.hit(r, 0.001, f32::MAX);
+        if let Some(fog) = &self.fog {
+            let t_max = hit.as_ref().map_or(f32::MAX, |hr| hr.t);
+            if let Some(fog_hr) = fog.hit(r, 0.001, t_max, sampler) {
+                return Some(fog_hr);
+            }
+        }
+        hit
+    }
+
+    // A sphere around the scene, for directional lights to shine through
+    // and the fog to fill
+    pub fn bounds(&self) -> (Vector3, f32) {
+        match self.objects.bounding_box() {
+            Some(bbox) => {
+                let center = 0.5 * (bbox.min() + bbox.max());
+                (center, (bbox.max() - center).length().max(0.001))
+            }
+            None => (Vector3::zero(), 1.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Material, PhaseFunction};
+    use crate::sphere::Sphere;
+
+    #[test]
+    fn only_builds_renderable_scenes() {
+        let material = Material::Lambertian {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        };
+        let sphere = |radius: f32| Sphere::new(Vector3::zero(), radius, material.clone());
+
+        let mut objects = HitableList::new();
+        objects.add(sphere(1.0));
+        assert!(Scene::new(objects.clone()).build().is_ok());
+
+        assert!(Scene::new(HitableList::new()).build().is_err());
+        let mut nested = HitableList::new();
+        nested.add(sphere(0.0));
+        objects.add(nested);
+        assert!(Scene::new(objects).build().is_err());
+        let mut objects = HitableList::new();
+        objects.add(sphere(f32::INFINITY));
+        assert!(Scene::new(objects).build().is_err());
+    }
+    // Rays escaping the scene cross the fog around it on their way out
+    #[test]
+    fn escaping_rays_scatter_in_fog() {
+        let mut objects = HitableList::new();
+        objects.add(Sphere::new(
+            Vector3::zero(),
+            1.0,
+            Material::Lambertian {
+                albedo: Vector3::new(0.5, 0.5, 0.5),
+            },
+        ));
+        let mut scene = Scene::new(objects);
+        let white = Vector3::new(1.0, 1.0, 1.0);
+        scene.fog = Some(Fog::new(1.0, white, PhaseFunction::Isotropic));
+        let scene = scene.build().unwrap();
+        // Straight out from just above the object
+        let r = Ray::new(Vector3::new(0.0, 1.5, 0.0), Vector3::new(0.0, 1.0, 0.0));
+        let (center, radius) = scene.bounds();
+        assert!(center == Vector3::zero());
+        let mut sampler = Sampler::new(0);
+        let trials = 10000;
+        let mut scattered = 0;
+        for _ in 0..trials {
+            if let Some(hr) = scene.hit(&r, &mut sampler) {
+                assert!(hr.p.length() < radius);
+                scattered += 1;
+            }
+        }
+        let expected = 1.0 - (-(radius - 1.5)).exp();
+        let fraction = scattered as f32 / trials as f32;
+        assert!((fraction - expected).abs() < 0.02, "{}", fraction);
+        // And are dimmed by the rest of it
+        let fog = scene.fog.as_ref().unwrap();
+        let through = fog.transmittance(&r, 0.0, f32::MAX);
+        assert!((through - (1.0 - expected)).abs() < 0.0001);
+    }
+}
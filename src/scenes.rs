@@ -0,0 +1,924 @@
+use crate::aabb::AABB;
+use crate::background::Background;
+use crate::camera::CameraTrack;
+use crate::csg::Csg;
+use crate::cuboid::Cuboid;
+use crate::emission::{AngularProfile, Emission, Power};
+use crate::heightfield::{HeightMap, Heightfield};
+use crate::hitable::{Hitable, HitableList};
+use crate::instance::{Instance, Transform};
+use crate::light::{Light, MeshLight, SphereLight};
+use crate::material::Material;
+use crate::material::PhaseFunction;
+use crate::medium::{ConstantMedium, GridMedium};
+use crate::metaballs::{Metaball, Metaballs};
+use crate::motion::{Interpolation, Keyframes};
+use crate::quadric::Quadric;
+use crate::random;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::sdf::{DistanceField, Sdf};
+use crate::sky::Sky;
+use crate::sphere::Sphere;
+use crate::texture::{CheckerTexture, Texture};
+use crate::torus::Torus;
+use crate::triangle::{self, Triangle};
+use crate::vec3::Vector3;
+use crate::voxel::VoxelGrid;
+use std::sync::Arc;
+
+pub fn random_scene() -> Scene<HitableList> {
+    use random::random_in_unit_interval as RandUnit;
+    let mut hitable_list = HitableList::new();
+
+    hitable_list.add(Sphere::new(
+        Vector3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Material::Lambertian {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        },
+    ));
+
+    for a in -5..5 {
+        for b in -5..5 {
+            let choose_mat = RandUnit();
+            let x_rand = RandUnit();
+            let z_rand = RandUnit();
+            let center = Vector3::new(a as f32 + 0.9 * x_rand, 0.2, b as f32 + 0.9 * z_rand);
+            if (center - Vector3::new(4.0, 0.2, 0.0)).length() > 0.9 {
+                if choose_mat < 0.8 {
+                    hitable_list.add(Sphere::new(
+                        center,
+                        0.2,
+                        Material::Lambertian {
+                            albedo: Vector3::new(
+                                RandUnit() * RandUnit(),
+                                RandUnit() * RandUnit(),
+                                RandUnit() * RandUnit(),
+                            ),
+                        },
+                    ));
+                } else if choose_mat < 0.95 {
+                    hitable_list.add(Sphere::new(
+                        center,
+                        0.2,
+                        Material::Metal {
+                            albedo: Vector3::new(
+                                0.5 * (1.0 + RandUnit()),
+                                0.5 * (1.0 + RandUnit()),
+                                0.5 * (1.0 + RandUnit()),
+                            ),
+                            fuzz: 0.5 * RandUnit(),
+                        },
+                    ));
+                } else {
+                    hitable_list.add(Sphere::new(center, 0.2, Material::Dielectric { ri: 1.5 }));
+                }
+            }
+        }
+    }
+    hitable_list.add(Sphere::new(
+        Vector3::new(0.0, 1.0, 0.0),
+        1.0,
+        Material::Dielectric { ri: 1.5 },
+    ));
+    hitable_list.add(Sphere::new(
+        Vector3::new(-4.0, 1.0, 0.0),
+        1.0,
+        Material::Lambertian {
+            albedo: Vector3::new(0.4, 0.2, 0.1),
+        },
+    ));
+    hitable_list.add(Sphere::new(
+        Vector3::new(4.0, 1.0, 0.0),
+        1.0,
+        Material::Metal {
+            albedo: Vector3::new(0.7, 0.6, 0.5),
+            fuzz: 0.0,
+        },
+    ));
+
+    // Add a light to illuminate the scene
+    hitable_list.add(Sphere::new(
+        Vector3::new(1.0, 13.0, -10.0),
+        13.0,
+        Material::diffuse_light(Vector3::new(1.0, 0.54, 0.20)),
+    ));
+
+    Scene::new(hitable_list)
+}
+
+// Smoke and haze, with the same layout as random_scene
+pub fn smoke_scene() -> Scene<HitableList> {
+    let mut hitable_list = HitableList::new();
+
+    hitable_list.add(Sphere::new(
+        Vector3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Material::Lambertian {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        },
+    ));
+
+    // Glass ball filled with a blue haze, for a subsurface-like look
+    hitable_list.add(Sphere::new(
+        Vector3::new(0.0, 1.0, 0.0),
+        1.0,
+        Material::Dielectric { ri: 1.5 },
+    ));
+    hitable_list.add(ConstantMedium::new(
+        Sphere::new(
+            Vector3::new(0.0, 1.0, 0.0),
+            0.99,
+            Material::Dielectric { ri: 1.5 },
+        ),
+        2.0,
+        Vector3::new(0.2, 0.4, 0.9),
+        PhaseFunction::Isotropic,
+    ));
+
+    // White smoke
+    hitable_list.add(ConstantMedium::new(
+        Sphere::new(
+            Vector3::new(-4.0, 1.0, 0.0),
+            1.0,
+            Material::Dielectric { ri: 1.5 },
+        ),
+        1.5,
+        Vector3::new(0.9, 0.9, 0.9),
+        PhaseFunction::Isotropic,
+    ));
+
+    // Dark, forward scattering smoke
+    hitable_list.add(ConstantMedium::new(
+        Sphere::new(
+            Vector3::new(4.0, 1.0, 0.0),
+            1.0,
+            Material::Dielectric { ri: 1.5 },
+        ),
+        3.0,
+        Vector3::new(0.3, 0.3, 0.3),
+        PhaseFunction::HenyeyGreenstein { g: 0.7 },
+    ));
+
+    hitable_list.add(Sphere::new(
+        Vector3::new(1.0, 13.0, -10.0),
+        13.0,
+        Material::diffuse_light(Vector3::new(1.0, 0.54, 0.20)),
+    ));
+
+    Scene::new(hitable_list)
+}
+
+// A cloud and a fireball. A loaded voxel grid replaces the cloud.
+pub fn clouds_scene(cloud: Option<VoxelGrid>) -> Scene<HitableList> {
+    let mut hitable_list = HitableList::new();
+
+    hitable_list.add(Sphere::new(
+        Vector3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Material::Lambertian {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        },
+    ));
+
+    let cloud = cloud.unwrap_or_else(|| VoxelGrid::procedural_cloud(64));
+    hitable_list.add(GridMedium::new(
+        AABB::new(Vector3::new(-3.0, 1.0, -2.5), Vector3::new(2.0, 4.0, 2.5)),
+        Arc::new(cloud),
+        6.0,
+        Vector3::new(0.95, 0.95, 0.95),
+        PhaseFunction::HenyeyGreenstein { g: 0.3 },
+        Vector3::zero(),
+    ));
+
+    hitable_list.add(GridMedium::new(
+        AABB::new(Vector3::new(2.5, 0.0, -1.0), Vector3::new(4.5, 2.5, 1.0)),
+        Arc::new(VoxelGrid::procedural_fire(48)),
+        8.0,
+        Vector3::new(0.3, 0.3, 0.3),
+        PhaseFunction::Isotropic,
+        Vector3::new(4.0, 1.2, 0.3),
+    ));
+
+    hitable_list.add(Sphere::new(
+        Vector3::new(1.0, 13.0, -10.0),
+        13.0,
+        Material::diffuse_light(Vector3::new(1.0, 0.54, 0.20)),
+    ));
+
+    Scene::new(hitable_list)
+}
+
+// Direction towards the sun. Azimuth is measured from +x towards -z.
+pub fn sun_direction(elevation: f32, azimuth: f32) -> Vector3 {
+    let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+    Vector3::new(
+        elevation.cos() * azimuth.cos(),
+        elevation.sin(),
+        -elevation.cos() * azimuth.sin(),
+    )
+}
+
+// Axis aligned box made of triangles facing outwards
+fn box_triangles(min: Vector3, max: Vector3, material: Material) -> Vec<Triangle> {
+    // Corner i takes max on the x axis if bit 0 of i is set, y for bit 1
+    // and z for bit 2
+    let corner = |i: usize| {
+        Vector3::new(
+            if i & 1 != 0 { max.x() } else { min.x() },
+            if i & 2 != 0 { max.y() } else { min.y() },
+            if i & 4 != 0 { max.z() } else { min.z() },
+        )
+    };
+    let faces = [
+        [0, 2, 3, 1],
+        [4, 5, 7, 6],
+        [0, 4, 6, 2],
+        [1, 3, 7, 5],
+        [0, 1, 5, 4],
+        [2, 6, 7, 3],
+    ];
+    let mut triangles = Vec::new();
+    for face in faces.iter() {
+        let [a, b, c, d] = face.map(corner);
+        triangles.push(Triangle::new(a, b, c, material.clone()));
+        triangles.push(Triangle::new(a, c, d, material.clone()));
+    }
+    triangles
+}
+
+// As one object, e.g. for the object ID pass
+fn add_box(hitable_list: &mut HitableList, min: Vector3, max: Vector3, material: Material) {
+    let mut mesh = HitableList::new();
+    for triangle in box_triangles(min, max, material) {
+        mesh.add(triangle);
+    }
+    hitable_list.add(mesh);
+}
+
+// A few blocks and balls in daylight
+pub fn outdoor_scene() -> Scene<HitableList> {
+    let mut hitable_list = HitableList::new();
+
+    hitable_list.add(Sphere::new(
+        Vector3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Material::Lambertian {
+            albedo: Vector3::new(0.4, 0.4, 0.35),
+        },
+    ));
+
+    let concrete = Material::Lambertian {
+        albedo: Vector3::new(0.7, 0.68, 0.65),
+    };
+    add_box(
+        &mut hitable_list,
+        Vector3::new(-6.0, 0.0, -4.0),
+        Vector3::new(-3.0, 4.0, -1.0),
+        concrete.clone(),
+    );
+    add_box(
+        &mut hitable_list,
+        Vector3::new(-2.5, 0.0, -5.0),
+        Vector3::new(0.5, 2.5, -2.5),
+        Material::Lambertian {
+            albedo: Vector3::new(0.7, 0.35, 0.25),
+        },
+    );
+    add_box(
+        &mut hitable_list,
+        Vector3::new(-1.5, 0.0, 2.0),
+        Vector3::new(0.0, 1.5, 3.5),
+        concrete,
+    );
+
+    hitable_list.add(Sphere::new(
+        Vector3::new(0.0, 1.0, 0.0),
+        1.0,
+        Material::Dielectric { ri: 1.5 },
+    ));
+    hitable_list.add(Sphere::new(
+        Vector3::new(3.0, 1.0, -1.0),
+        1.0,
+        Material::Metal {
+            albedo: Vector3::new(0.8, 0.8, 0.8),
+            fuzz: 0.05,
+        },
+    ));
+    hitable_list.add(Sphere::new(
+        Vector3::new(2.5, 0.5, 1.8),
+        0.5,
+        Material::Lambertian {
+            albedo: Vector3::new(0.2, 0.4, 0.7),
+        },
+    ));
+
+    // A smooth flight around the blocks, from the usual view at frame 0
+    let mut camera = CameraTrack::new(Vector3::new(13.0, 2.0, 3.0), Vector3::zero(), 30.0, 0.1);
+    camera.lookfrom = Keyframes::new(vec![
+        (0.0, Vector3::new(13.0, 2.0, 3.0)),
+        (32.0, Vector3::new(7.0, 2.5, 9.0)),
+        (64.0, Vector3::new(-3.0, 3.0, 10.0)),
+        (96.0, Vector3::new(-10.0, 4.0, 5.0)),
+    ])
+    .with_interpolation(Interpolation::Smooth);
+    camera.lookat = Keyframes::new(vec![
+        (0.0, Vector3::zero()),
+        (96.0, Vector3::new(-2.0, 1.0, -1.0)),
+    ])
+    .with_interpolation(Interpolation::Smooth);
+    camera.vfov = Keyframes::new(vec![(0.0, 30.0), (96.0, 40.0)]);
+
+    Scene {
+        background: Background::Sky(Sky::new(sun_direction(35.0, 40.0), 3.0, 1.0)),
+        camera,
+        ..Scene::new(hitable_list)
+    }
+}
+
+// Hills in daylight, from a height map passed in or noise, with a few
+// balls on them for scale
+pub fn terrain_scene(map: Option<HeightMap>) -> Scene<HitableList> {
+    let mut hitable_list = HitableList::new();
+
+    let map = map.unwrap_or_else(|| HeightMap::procedural(512));
+    let terrain = Heightfield::new(
+        &map,
+        Vector3::new(-20.0, -2.0, -20.0),
+        Vector3::new(40.0, 6.0, 40.0),
+        Material::Lambertian {
+            albedo: Vector3::new(0.45, 0.5, 0.3),
+        },
+    );
+    // Resting on the ground, found by dropping rays onto it
+    for (x, z, radius, material) in [
+        (0.0, 0.0, 1.0, Material::Dielectric { ri: 1.5 }),
+        (
+            3.0,
+            -2.0,
+            1.0,
+            Material::Metal {
+                albedo: Vector3::new(0.8, 0.8, 0.8),
+                fuzz: 0.05,
+            },
+        ),
+        (
+            2.0,
+            2.5,
+            0.5,
+            Material::Lambertian {
+                albedo: Vector3::new(0.7, 0.35, 0.25),
+            },
+        ),
+    ] {
+        let drop = Ray::new(Vector3::new(x, 10.0, z), Vector3::new(0.0, -1.0, 0.0));
+        let ground = terrain.hit(&drop, 0.0, f32::MAX).map_or(0.0, |hr| hr.p.y());
+        hitable_list.add(Sphere::new(
+            Vector3::new(x, ground + radius, z),
+            radius,
+            material,
+        ));
+    }
+    hitable_list.add(terrain);
+
+    Scene {
+        background: Background::Sky(Sky::new(sun_direction(25.0, 40.0), 3.0, 1.0)),
+        camera: CameraTrack::new(
+            Vector3::new(16.0, 6.0, 5.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            40.0,
+            0.0,
+        ),
+        ..Scene::new(hitable_list)
+    }
+}
+
+// Blocked in with punctual lights only: a spot on the glass ball, a warm
+// point light and dim moonlight
+pub fn lights_scene() -> Scene<HitableList> {
+    let mut hitable_list = HitableList::new();
+
+    hitable_list.add(Sphere::new(
+        Vector3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Material::Lambertian {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        },
+    ));
+    hitable_list.add(Sphere::new(
+        Vector3::new(0.0, 1.0, 0.0),
+        1.0,
+        Material::Dielectric { ri: 1.5 },
+    ));
+    hitable_list.add(Sphere::new(
+        Vector3::new(-4.0, 1.0, 0.0),
+        1.0,
+        Material::Lambertian {
+            albedo: Vector3::new(0.4, 0.2, 0.1),
+        },
+    ));
+    hitable_list.add(Sphere::new(
+        Vector3::new(4.0, 1.0, 0.0),
+        1.0,
+        Material::Metal {
+            albedo: Vector3::new(0.7, 0.6, 0.5),
+            fuzz: 0.0,
+        },
+    ));
+
+    Scene {
+        background: Background::Constant(Vector3::new(0.02, 0.02, 0.03)),
+        lights: vec![
+            Light::Spot {
+                position: Vector3::new(2.0, 6.0, 2.0),
+                direction: Vector3::new(-2.0, -6.0, -2.0),
+                intensity: Vector3::new(80.0, 80.0, 80.0),
+                inner_angle: 12.0,
+                outer_angle: 18.0,
+            },
+            Light::Point {
+                position: Vector3::new(-2.0, 3.0, 3.0),
+                intensity: Vector3::new(24.0, 16.0, 8.0),
+            },
+            Light::Directional {
+                direction: Vector3::new(1.0, -2.0, -1.0),
+                irradiance: Vector3::new(0.2, 0.24, 0.4),
+            },
+        ],
+        ..Scene::new(hitable_list)
+    }
+}
+
+// Solids made by CSG under a sky: a die with its pips cut out, a stepped
+// block with a bowl cut out of its top and a glass lens where two spheres
+// meet
+pub fn csg_scene() -> Scene<HitableList> {
+    let mut hitable_list = HitableList::new();
+
+    hitable_list.add(Sphere::new(
+        Vector3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Material::Lambertian {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        },
+    ));
+
+    let ivory = Material::Lambertian {
+        albedo: Vector3::new(0.8, 0.75, 0.6),
+    };
+    let center = Vector3::new(0.0, 1.0, -2.5);
+    let corner = Vector3::new(1.0, 1.0, 1.0);
+    let mut die = Csg::intersection(
+        Cuboid::new(center - corner, center + corner, ivory.clone()),
+        Sphere::new(center, 1.4, ivory.clone()),
+    );
+    // Five on the side facing the camera
+    for (y, z) in [
+        (0.0, 0.0),
+        (0.5, 0.5),
+        (0.5, -0.5),
+        (-0.5, 0.5),
+        (-0.5, -0.5),
+    ] {
+        let pip = Sphere::new(
+            center + Vector3::new(1.05, y, z),
+            0.2,
+            Material::Lambertian {
+                albedo: Vector3::new(0.05, 0.05, 0.05),
+            },
+        );
+        die = Csg::difference(die, pip);
+    }
+    hitable_list.add(die);
+
+    let steel = Material::Metal {
+        albedo: Vector3::new(0.7, 0.7, 0.75),
+        fuzz: 0.2,
+    };
+    let base = Cuboid::new(
+        Vector3::new(-1.0, 0.0, -0.9),
+        Vector3::new(1.0, 0.4, 0.9),
+        steel.clone(),
+    );
+    let boss = Cuboid::new(
+        Vector3::new(-0.6, 0.4, -0.6),
+        Vector3::new(0.6, 1.4, 0.6),
+        steel.clone(),
+    );
+    hitable_list.add(Csg::difference(
+        Csg::union(base, boss),
+        Sphere::new(Vector3::new(0.0, 1.6, 0.0), 0.55, steel),
+    ));
+
+    let glass = Material::Dielectric { ri: 1.5 };
+    hitable_list.add(Csg::intersection(
+        Sphere::new(Vector3::new(-1.7, 1.1, 2.5), 2.0, glass.clone()),
+        Sphere::new(Vector3::new(1.7, 1.1, 2.5), 2.0, glass),
+    ));
+
+    Scene {
+        background: Background::Gradient {
+            bottom: Vector3::new(1.0, 1.0, 1.0),
+            top: Vector3::new(0.5, 0.7, 1.0),
+        },
+        ..Scene::new(hitable_list)
+    }
+}
+
+// Shapes made from distance functions under a sky: a twisted column, part
+// way between a box and a capsule, a wheel with spokes melted into its rim
+// and a row of glass beads melted into a bar
+pub fn sdf_scene() -> Scene<HitableList> {
+    let mut hitable_list = HitableList::new();
+
+    hitable_list.add(Sphere::new(
+        Vector3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Material::Lambertian {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        },
+    ));
+
+    let column = Sdf::cuboid(Vector3::new(0.4, 1.2, 0.4))
+        .blend(
+            Sdf::capsule(
+                Vector3::new(0.0, -0.8, 0.0),
+                Vector3::new(0.0, 0.8, 0.0),
+                0.4,
+            ),
+            0.3,
+        )
+        .twist(1.2)
+        .translate(Vector3::new(0.0, 1.2, -2.5));
+    hitable_list.add(DistanceField::new(
+        column,
+        AABB::new(Vector3::new(-0.6, 0.0, -3.1), Vector3::new(0.6, 2.4, -1.9)),
+        Material::Lambertian {
+            albedo: Vector3::new(0.7, 0.3, 0.2),
+        },
+    ));
+
+    let spokes = Sdf::capsule(
+        Vector3::new(-0.8, 0.0, 0.0),
+        Vector3::new(0.8, 0.0, 0.0),
+        0.1,
+    )
+    .union(Sdf::capsule(
+        Vector3::new(0.0, 0.0, -0.8),
+        Vector3::new(0.0, 0.0, 0.8),
+        0.1,
+    ));
+    let wheel = Sdf::torus(0.8, 0.2)
+        .smooth_union(spokes, 0.2)
+        .translate(Vector3::new(0.0, 0.2, 0.0));
+    hitable_list.add(DistanceField::new(
+        wheel,
+        AABB::new(Vector3::new(-1.1, 0.0, -1.1), Vector3::new(1.1, 0.5, 1.1)),
+        Material::Metal {
+            albedo: Vector3::new(0.8, 0.7, 0.4),
+            fuzz: 0.1,
+        },
+    ));
+
+    let beads = Sdf::sphere(0.3)
+        .repeat(Vector3::new(0.0, 0.0, 0.6))
+        .smooth_union(Sdf::cuboid(Vector3::new(0.1, 0.1, 0.9)), 0.2)
+        .translate(Vector3::new(0.0, 0.35, 2.5));
+    hitable_list.add(DistanceField::new(
+        beads,
+        AABB::new(Vector3::new(-0.4, 0.0, 1.6), Vector3::new(0.4, 0.7, 3.4)),
+        Material::Dielectric { ri: 1.5 },
+    ));
+
+    Scene {
+        background: Background::Gradient {
+            bottom: Vector3::new(1.0, 1.0, 1.0),
+            top: Vector3::new(0.5, 0.7, 1.0),
+        },
+        // Looking down on the wheel
+        camera: CameraTrack::new(
+            Vector3::new(10.0, 5.0, 3.0),
+            Vector3::new(0.0, 0.6, 0.0),
+            30.0,
+            0.0,
+        ),
+        ..Scene::new(hitable_list)
+    }
+}
+
+// A torus, a cylinder and cone, a hyperboloid and a metaball blob, all
+// found exactly rather than by marching
+pub fn implicit_scene() -> Scene<HitableList> {
+    let mut hitable_list = HitableList::new();
+
+    hitable_list.add(Sphere::new(
+        Vector3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Material::Lambertian {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        },
+    ));
+
+    hitable_list.add(Torus::new(
+        Vector3::new(0.0, 0.3, 0.0),
+        0.9,
+        0.3,
+        Material::Metal {
+            albedo: Vector3::new(0.8, 0.7, 0.4),
+            fuzz: 0.05,
+        },
+    ));
+
+    let red = Material::Lambertian {
+        albedo: Vector3::new(0.7, 0.3, 0.2),
+    };
+    hitable_list.add(Quadric::cylinder(
+        Vector3::new(0.0, 0.0, -2.5),
+        0.4,
+        1.2,
+        red.clone(),
+    ));
+    hitable_list.add(Quadric::cone(Vector3::new(0.0, 1.2, -2.5), 0.6, 1.0, red));
+
+    // A cooling tower, (x - 2)^2 + z^2 - (y - 1)^2 / 4 = 0.09, open at
+    // both ends
+    hitable_list.add(Quadric::new(
+        [1.0, -0.25, 1.0, 0.0, 0.0, 0.0, -4.0, 0.5, 0.0, 3.66],
+        AABB::new(Vector3::new(1.4, 0.0, -0.6), Vector3::new(2.6, 2.0, 0.6)),
+        Material::Lambertian {
+            albedo: Vector3::new(0.2, 0.4, 0.7),
+        },
+    ));
+
+    let ball = |x: f32, y: f32, z: f32, radius: f32| Metaball {
+        center: Vector3::new(x, y, z),
+        radius,
+        strength: 1.0,
+    };
+    hitable_list.add(Metaballs::new(
+        vec![
+            ball(0.0, 0.6, 2.2, 0.9),
+            ball(0.0, 0.6, 3.0, 0.8),
+            ball(0.0, 1.2, 2.6, 0.7),
+        ],
+        0.3,
+        Material::Dielectric { ri: 1.5 },
+    ));
+
+    Scene {
+        background: Background::Gradient {
+            bottom: Vector3::new(1.0, 1.0, 1.0),
+            top: Vector3::new(0.5, 0.7, 1.0),
+        },
+        camera: CameraTrack::new(
+            Vector3::new(10.0, 5.0, 3.0),
+            Vector3::new(0.0, 0.6, 0.0),
+            30.0,
+            0.0,
+        ),
+        ..Scene::new(hitable_list)
+    }
+}
+
+// A textured panel light overhead and a glowing block. A texture or
+// angular profile passed in replaces the panel's.
+pub fn area_lights_scene(
+    texture: Option<Texture>,
+    profile: Option<AngularProfile>,
+) -> Scene<HitableList> {
+    let mut hitable_list = HitableList::new();
+
+    hitable_list.add(Sphere::new(
+        Vector3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Material::Lambertian {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        },
+    ));
+    hitable_list.add(Sphere::new(
+        Vector3::new(0.0, 1.0, 0.0),
+        1.0,
+        Material::Dielectric { ri: 1.5 },
+    ));
+    hitable_list.add(Sphere::new(
+        Vector3::new(-4.0, 1.0, 0.0),
+        1.0,
+        Material::Lambertian {
+            albedo: Vector3::new(0.4, 0.2, 0.1),
+        },
+    ));
+    hitable_list.add(Sphere::new(
+        Vector3::new(4.0, 1.0, 0.0),
+        1.0,
+        Material::Metal {
+            albedo: Vector3::new(0.7, 0.6, 0.5),
+            fuzz: 0.0,
+        },
+    ));
+
+    // Replaced by the lights' emission
+    let unlit = Material::Lambertian {
+        albedo: Vector3::zero(),
+    };
+
+    let mut scene = Scene {
+        background: Background::Constant(Vector3::new(0.01, 0.01, 0.01)),
+        ..Scene::new(hitable_list)
+    };
+
+    // Facing down, and brightest straight below
+    let panel = Emission {
+        two_sided: false,
+        texture: Some(texture.unwrap_or(Texture::Checker(CheckerTexture::new(
+            Vector3::new(1.0, 0.8, 0.6),
+            Vector3::new(0.6, 0.8, 1.0),
+            4,
+        )))),
+        profile: Some(profile.unwrap_or_else(|| {
+            AngularProfile::new(vec![0.0, 30.0, 60.0, 90.0], vec![1.0, 0.9, 0.4, 0.0])
+        })),
+        ..Emission::new(Vector3::new(1.0, 1.0, 1.0))
+    };
+    scene.add_area_light(MeshLight::new(
+        triangle::quad(
+            Vector3::new(-3.0, 5.0, -2.0),
+            Vector3::new(6.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 4.0),
+            unlit.clone(),
+        ),
+        panel,
+        Some(Power::Watts(500.0)),
+    ));
+
+    let block = Emission {
+        two_sided: false,
+        ..Emission::new(Vector3::new(0.3, 1.0, 0.4))
+    };
+    scene.add_area_light(MeshLight::new(
+        box_triangles(
+            Vector3::new(2.0, 0.0, 1.8),
+            Vector3::new(2.6, 0.6, 2.4),
+            unlit,
+        ),
+        block,
+        Some(Power::Lumens(4000.0)),
+    ));
+
+    scene
+}
+
+// random_scene at night, with hundreds of small lights in place of the
+// glass and metal balls
+pub fn many_lights_scene() -> Scene<HitableList> {
+    use random::random_in_unit_interval as RandUnit;
+    let mut scene = Scene::new(HitableList::new());
+    scene.background = Background::Constant(Vector3::zero());
+
+    scene.objects.add(Sphere::new(
+        Vector3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Material::Lambertian {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        },
+    ));
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let choose_mat = RandUnit();
+            let x_rand = RandUnit();
+            let z_rand = RandUnit();
+            let center = Vector3::new(a as f32 + 0.9 * x_rand, 0.2, b as f32 + 0.9 * z_rand);
+            if (center - Vector3::new(4.0, 0.2, 0.0)).length() > 0.9 {
+                if choose_mat < 0.5 {
+                    scene.objects.add(Sphere::new(
+                        center,
+                        0.2,
+                        Material::Lambertian {
+                            albedo: Vector3::new(
+                                RandUnit() * RandUnit(),
+                                RandUnit() * RandUnit(),
+                                RandUnit() * RandUnit(),
+                            ),
+                        },
+                    ));
+                } else {
+                    // Mostly dim, with the odd bright one
+                    let brightness = if choose_mat < 0.97 { 1.0 } else { 20.0 };
+                    scene.add_sphere_light(SphereLight::new(
+                        center,
+                        0.2,
+                        Emission::new(
+                            brightness
+                                * Vector3::new(
+                                    0.5 + 0.5 * RandUnit(),
+                                    0.5 + 0.5 * RandUnit(),
+                                    0.5 + 0.5 * RandUnit(),
+                                ),
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+    scene.objects.add(Sphere::new(
+        Vector3::new(0.0, 1.0, 0.0),
+        1.0,
+        Material::Dielectric { ri: 1.5 },
+    ));
+    scene.objects.add(Sphere::new(
+        Vector3::new(-4.0, 1.0, 0.0),
+        1.0,
+        Material::Lambertian {
+            albedo: Vector3::new(0.4, 0.2, 0.1),
+        },
+    ));
+    scene.objects.add(Sphere::new(
+        Vector3::new(4.0, 1.0, 0.0),
+        1.0,
+        Material::Metal {
+            albedo: Vector3::new(0.7, 0.6, 0.5),
+            fuzz: 0.0,
+        },
+    ));
+
+    scene
+}
+
+// Things moving while the shutter is open, from time 0 to 1: a ball rolling
+// past, one falling, and a spinning block
+pub fn motion_scene() -> Scene<HitableList> {
+    let mut hitable_list = HitableList::new();
+
+    hitable_list.add(Sphere::new(
+        Vector3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Material::Lambertian {
+            albedo: Vector3::new(0.4, 0.4, 0.35),
+        },
+    ));
+    hitable_list.add(Sphere::new(
+        Vector3::new(0.0, 1.0, 0.0),
+        1.0,
+        Material::Metal {
+            albedo: Vector3::new(0.8, 0.8, 0.8),
+            fuzz: 0.05,
+        },
+    ));
+
+    hitable_list.add(
+        Sphere::new(
+            Vector3::zero(),
+            0.5,
+            Material::Lambertian {
+                albedo: Vector3::new(0.7, 0.2, 0.15),
+            },
+        )
+        .with_motion(Keyframes::new(vec![
+            (0.0, Vector3::new(3.0, 0.5, 2.5)),
+            (1.0, Vector3::new(3.0, 0.5, 0.8)),
+        ])),
+    );
+
+    // Speeding up as it falls
+    let falling = (0..5)
+        .map(|i| {
+            let t = i as f32 / 4.0;
+            (t, Vector3::new(2.0, 2.4 - 1.9 * t * t, -2.5))
+        })
+        .collect();
+    hitable_list.add(
+        Sphere::new(
+            Vector3::zero(),
+            0.5,
+            Material::Lambertian {
+                albedo: Vector3::new(0.2, 0.4, 0.7),
+            },
+        )
+        .with_motion(Keyframes::new(falling)),
+    );
+
+    let mut block = HitableList::new();
+    for triangle in box_triangles(
+        Vector3::new(-0.6, 0.0, -0.6),
+        Vector3::new(0.6, 1.2, 0.6),
+        Material::Lambertian {
+            albedo: Vector3::new(0.7, 0.68, 0.65),
+        },
+    ) {
+        block.add(triangle);
+    }
+    let position = Vector3::new(-1.0, 0.0, 2.5);
+    hitable_list.add(Instance::new(
+        Box::new(block),
+        Keyframes::new(vec![
+            (0.0, Transform::new(position, 0.0, 1.0)),
+            (1.0, Transform::new(position, 40.0, 1.0)),
+        ]),
+    ));
+
+    Scene {
+        background: Background::Sky(Sky::new(sun_direction(35.0, 40.0), 3.0, 1.0)),
+        ..Scene::new(hitable_list)
+    }
+}
@@ -0,0 +1,377 @@
+use crate::aabb::AABB;
+use crate::hitable::{HitRecord, Hitable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sphere::sphere_uv;
+use crate::vec3::Vector3;
+use std::f32;
+
+// Most steps along a ray before giving up on it, e.g. one skimming along
+// the surface
+const MAX_STEPS: u32 = 512;
+// How close to the surface counts as on it
+const EPSILON: f32 = 0.0001;
+
+// A signed distance function, negative inside the shape, built from
+// primitives centred on the origin and operators combining or bending
+// them. Primitives and most operators give the exact distance. The rest
+// may give more than it, which Lipschitz bounds make up for.
+#[derive(Clone)]
+pub enum Sdf {
+    Sphere {
+        radius: f32,
+    },
+    Box {
+        half_size: Vector3,
+    },
+    // Lying in the xz plane
+    Torus {
+        major_radius: f32,
+        minor_radius: f32,
+    },
+    // Around the segment from a to b
+    Capsule {
+        a: Vector3,
+        b: Vector3,
+        radius: f32,
+    },
+    Translate {
+        offset: Vector3,
+        sdf: Box<Sdf>,
+    },
+    Union(Box<Sdf>, Box<Sdf>),
+    // Rounded off where they meet, within about k of each other
+    SmoothUnion {
+        a: Box<Sdf>,
+        b: Box<Sdf>,
+        k: f32,
+    },
+    // Part way between one shape and the other, for t from 0 to 1
+    Blend {
+        a: Box<Sdf>,
+        b: Box<Sdf>,
+        t: f32,
+    },
+    // Copies every period along each axis, or none along axes with a
+    // period of 0. The shape should fit in one period.
+    Repeat {
+        period: Vector3,
+        sdf: Box<Sdf>,
+    },
+    // Turned about the y axis by rate radians per unit up it
+    Twist {
+        rate: f32,
+        sdf: Box<Sdf>,
+    },
+}
+
+impl Sdf {
+    pub fn sphere(radius: f32) -> Sdf {
+        Sdf::Sphere { radius }
+    }
+
+    pub fn cuboid(half_size: Vector3) -> Sdf {
+        Sdf::Box { half_size }
+    }
+
+    pub fn torus(major_radius: f32, minor_radius: f32) -> Sdf {
+        Sdf::Torus {
+            major_radius,
+            minor_radius,
+        }
+    }
+
+    pub fn capsule(a: Vector3, b: Vector3, radius: f32) -> Sdf {
+        Sdf::Capsule { a, b, radius }
+    }
+
+    pub fn translate(self, offset: Vector3) -> Sdf {
+        Sdf::Translate {
+            offset,
+            sdf: Box::new(self),
+        }
+    }
+
+    pub fn union(self, other: Sdf) -> Sdf {
+        Sdf::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn smooth_union(self, other: Sdf, k: f32) -> Sdf {
+        Sdf::SmoothUnion {
+            a: Box::new(self),
+            b: Box::new(other),
+            k,
+        }
+    }
+
+    pub fn blend(self, other: Sdf, t: f32) -> Sdf {
+        Sdf::Blend {
+            a: Box::new(self),
+            b: Box::new(other),
+            t,
+        }
+    }
+
+    pub fn repeat(self, period: Vector3) -> Sdf {
+        Sdf::Repeat {
+            period,
+            sdf: Box::new(self),
+        }
+    }
+
+    pub fn twist(self, rate: f32) -> Sdf {
+        Sdf::Twist {
+            rate,
+            sdf: Box::new(self),
+        }
+    }
+
+    // After Inigo Quilez's distance functions,
+    // https://iquilezles.org/articles/distfunctions/
+    pub fn distance(&self, p: Vector3) -> f32 {
+        match self {
+            Sdf::Sphere { radius } => p.length() - radius,
+            Sdf::Box { half_size } => {
+                let q = Vector3::new(
+                    p.x().abs() - half_size.x(),
+                    p.y().abs() - half_size.y(),
+                    p.z().abs() - half_size.z(),
+                );
+                let outside = Vector3::new(q.x().max(0.0), q.y().max(0.0), q.z().max(0.0));
+                outside.length() + q.x().max(q.y()).max(q.z()).min(0.0)
+            }
+            Sdf::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let ring = (p.x() * p.x() + p.z() * p.z()).sqrt() - major_radius;
+                (ring * ring + p.y() * p.y()).sqrt() - minor_radius
+            }
+            Sdf::Capsule { a, b, radius } => {
+                let (pa, ba) = (p - *a, *b - *a);
+                let h = (pa.dot(&ba) / ba.dot(&ba)).clamp(0.0, 1.0);
+                (pa - ba * h).length() - radius
+            }
+            Sdf::Translate { offset, sdf } => sdf.distance(p - *offset),
+            Sdf::Union(a, b) => a.distance(p).min(b.distance(p)),
+            Sdf::SmoothUnion { a, b, k } => {
+                let (da, db) = (a.distance(p), b.distance(p));
+                let h = (0.5 + 0.5 * (db - da) / k).clamp(0.0, 1.0);
+                db + (da - db) * h - k * h * (1.0 - h)
+            }
+            Sdf::Blend { a, b, t } => (1.0 - t) * a.distance(p) + t * b.distance(p),
+            Sdf::Repeat { period, sdf } => {
+                let wrap = |x: f32, period: f32| {
+                    if period > 0.0 {
+                        x - period * (x / period).round()
+                    } else {
+                        x
+                    }
+                };
+                sdf.distance(Vector3::new(
+                    wrap(p.x(), period.x()),
+                    wrap(p.y(), period.y()),
+                    wrap(p.z(), period.z()),
+                ))
+            }
+            Sdf::Twist { rate, sdf } => {
+                let (sin, cos) = (rate * p.y()).sin_cos();
+                sdf.distance(Vector3::new(
+                    cos * p.x() - sin * p.z(),
+                    p.y(),
+                    sin * p.x() + cos * p.z(),
+                ))
+            }
+        }
+    }
+
+    // How much faster than the distance to the surface the function can
+    // change, within radius of the origin. Stepping by the distance over
+    // this never oversteps the surface.
+    fn lipschitz(&self, radius: f32) -> f32 {
+        match self {
+            Sdf::Sphere { .. } | Sdf::Box { .. } | Sdf::Torus { .. } | Sdf::Capsule { .. } => 1.0,
+            Sdf::Translate { offset, sdf } => sdf.lipschitz(radius + offset.length()),
+            Sdf::Union(a, b) | Sdf::SmoothUnion { a, b, .. } | Sdf::Blend { a, b, .. } => {
+                a.lipschitz(radius).max(b.lipschitz(radius))
+            }
+            Sdf::Repeat { period, sdf } => sdf.lipschitz(radius.min(period.length())),
+            // Points turn faster the further they are from the axis
+            Sdf::Twist { rate, sdf } => {
+                (1.0 + rate * rate * radius * radius).sqrt() * sdf.lipschitz(radius)
+            }
+        }
+    }
+}
+
+// A shape given by a signed distance function, found by sphere tracing:
+// stepping along the ray by the distance to the nearest surface, which
+// can't pass through it. The bounding box must hold the whole shape, and
+// is where marching starts and stops.
+#[derive(Clone)]
+pub struct DistanceField {
+    sdf: Sdf,
+    bbox: AABB,
+    material: Material,
+    // Fraction of the distance to step, to make up for operators that
+    // overestimate it
+    step: f32,
+}
+
+impl DistanceField {
+    pub fn new(sdf: Sdf, bbox: AABB, material: Material) -> DistanceField {
+        let corners = [bbox.min(), bbox.max()];
+        let radius = corners.iter().map(|c| c.length()).fold(0.0, f32::max);
+        DistanceField {
+            step: 1.0 / sdf.lipschitz(radius),
+            sdf,
+            bbox,
+            material,
+        }
+    }
+
+    // From the gradient, by differences at the corners of a tetrahedron
+    fn normal(&self, p: Vector3) -> Vector3 {
+        let h = 0.5 * EPSILON;
+        let offsets = [
+            Vector3::new(1.0, -1.0, -1.0),
+            Vector3::new(-1.0, -1.0, 1.0),
+            Vector3::new(-1.0, 1.0, -1.0),
+            Vector3::new(1.0, 1.0, 1.0),
+        ];
+        offsets
+            .iter()
+            .fold(Vector3::zero(), |sum, k| {
+                sum + *k * self.sdf.distance(p + *k * h)
+            })
+            .get_unit_vector()
+    }
+
+    // Where along r between t_min and t_max it reaches the surface, if it
+    // does, and how many steps that took
+    fn march(&self, r: &Ray, t_min: f32, t_max: f32) -> (Option<f32>, u32) {
+        let (t_start, t_end) = match self.bbox.intersect(r, t_min, t_max) {
+            Some(interval) => interval,
+            None => return (None, 0),
+        };
+        let length = r.direction().length();
+        let mut t = t_start;
+        // Marching from the side of the surface the ray starts on. A ray
+        // leaving the surface, e.g. after a bounce, starts right by it, so
+        // is nudged along until it's clear of it.
+        let side = self.sdf.distance(r.point_at_parameter(t)).signum();
+        let mut clear = t_start > t_min;
+        for steps in 1..=MAX_STEPS {
+            let distance = side * self.sdf.distance(r.point_at_parameter(t));
+            if distance < EPSILON {
+                if clear {
+                    return (Some(t), steps);
+                }
+                t += EPSILON / length;
+            } else {
+                clear = true;
+                t += self.step * distance / length;
+            }
+            if t >= t_end {
+                return (None, steps);
+            }
+        }
+        (None, MAX_STEPS)
+    }
+}
+
+impl Hitable for DistanceField {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let t = self.march(r, t_min, t_max).0?;
+        let p = r.point_at_parameter(t);
+        let normal = self.normal(p);
+        let (u, v) = sphere_uv(&normal);
+        Some(HitRecord {
+            t,
+            p,
+            normal,
+            u,
+            v,
+            material: self.material.clone(),
+            object_id: 0,
+        })
+    }
+
+    // The box test and each step
+    fn traversal_cost(&self, r: &Ray, t_min: f32, t_max: f32) -> u32 {
+        1 + self.march(r, t_min, t_max).1
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.bbox.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grey() -> Material {
+        Material::Lambertian {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        }
+    }
+
+    #[test]
+    fn distances_to_primitives() {
+        let p = Vector3::new(3.0, 0.0, 0.0);
+        let close = |a: f32, b: f32| (a - b).abs() < 0.0001;
+        assert!(close(Sdf::sphere(1.0).distance(p), 2.0));
+        assert!(close(
+            Sdf::cuboid(Vector3::new(1.0, 1.0, 1.0)).distance(p),
+            2.0
+        ));
+        assert!(close(Sdf::torus(2.0, 0.5).distance(p), 0.5));
+        let capsule = Sdf::capsule(
+            Vector3::new(0.0, -1.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            1.0,
+        );
+        assert!(close(capsule.distance(p), 2.0));
+        // Copies along x every 4, the nearest at 4
+        let row = Sdf::sphere(1.0).repeat(Vector3::new(4.0, 0.0, 0.0));
+        assert!(close(row.distance(p), 0.0));
+        // Inside is negative
+        assert!(close(Sdf::sphere(1.0).translate(p).distance(p), -1.0));
+        // Smooth unions swell where the shapes meet
+        let a = Sdf::sphere(1.0).translate(Vector3::new(-1.0, 0.0, 0.0));
+        let b = Sdf::sphere(1.0).translate(Vector3::new(1.0, 0.0, 0.0));
+        let above = Vector3::new(0.0, 0.5, 0.0);
+        assert!(
+            a.clone().smooth_union(b.clone(), 0.5).distance(above) < a.union(b).distance(above)
+        );
+    }
+
+    #[test]
+    fn sphere_traces_like_a_sphere() {
+        let shapes = [
+            Sdf::sphere(1.0),
+            // The same sphere, whatever the twist
+            Sdf::sphere(1.0).twist(2.0),
+            Sdf::sphere(1.0).blend(Sdf::sphere(1.0), 0.5),
+        ];
+        let bbox = AABB::new(Vector3::new(-2.0, -2.0, -2.0), Vector3::new(2.0, 2.0, 2.0));
+        for sdf in shapes {
+            let field = DistanceField::new(sdf, bbox.clone(), grey());
+            let r = Ray::new(Vector3::new(-5.0, 0.5, 0.0), Vector3::new(2.0, 0.0, 0.0));
+            let hr = field.hit(&r, 0.001, f32::MAX).unwrap();
+            let x = -(0.75f32.sqrt());
+            assert!((hr.p - Vector3::new(x, 0.5, 0.0)).length() < 0.001);
+            assert!((hr.normal - Vector3::new(x, 0.5, 0.0)).length() < 0.01);
+
+            // Leaving the surface it's on, back the way it came, there's
+            // nothing to hit. Into the sphere, there's the far side.
+            let back = Ray::new(hr.p, Vector3::new(-1.0, 0.0, 0.0));
+            assert!(field.hit(&back, 0.001, f32::MAX).is_none());
+            let through = Ray::new(hr.p, Vector3::new(1.0, 0.0, 0.0));
+            let far = field.hit(&through, 0.001, f32::MAX).unwrap();
+            assert!((far.p.x() + x).abs() < 0.001);
+            assert!(far.normal.x() > 0.0);
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use crate::random::Sampler;
+use crate::spectrum;
+use crate::vec3::Vector3;
+use std::f32;
+
+// Preetham's luminances are in kcd/m^2, this brings a clear midday sky to
+// around 0.5
+const SKY_SCALE: f32 = 0.05;
+
+// Luminance of the sun above the atmosphere, in kcd/m^2
+const SUN_LUMINANCE: f32 = 1.6e6;
+
+// Angular radius of the sun's disk
+const SUN_RADIUS: f32 = 0.00465;
+
+// How often light sampling picks the sun rather than the rest of the sky
+const SUN_SAMPLE_PROBABILITY: f32 = 0.5;
+
+// Perez et al.'s sky luminance distribution, relative to the zenith
+fn perez(coefficients: &[f32; 5], cos_theta: f32, gamma: f32) -> f32 {
+    let [a, b, c, d, e] = *coefficients;
+    (1.0 + a * (b / cos_theta).exp())
+        * (1.0 + c * (d * gamma).exp() + e * gamma.cos() * gamma.cos())
+}
+
+fn cubic(coefficients: &[f32; 4], x: f32) -> f32 {
+    ((coefficients[0] * x + coefficients[1]) * x + coefficients[2]) * x + coefficients[3]
+}
+
+// Planck's law, up to a constant factor. lambda in nanometres.
+fn blackbody(lambda: f32, temperature: f32) -> f32 {
+    let l = lambda as f64 * 1e-9;
+    let c2 = 1.4388e-2;
+    (1.0 / (l.powi(5) * ((c2 / (l * temperature as f64)).exp() - 1.0))) as f32
+}
+
+// The analytic daylight model from Preetham, Shirley and Smits, "A
+// Practical Analytic Model for Daylight" (1999), plus the sun's disk.
+// Turbidity ranges from about 2 for a very clear sky to 10 for haze.
+#[derive(Clone)]
+pub struct Sky {
+    sun_direction: Vector3,
+    // Perez coefficients for luminance Y and chromaticities x and y
+    perez_luminance: [f32; 5],
+    perez_x: [f32; 5],
+    perez_y: [f32; 5],
+    // Zenith values divided by the Perez function at the zenith, so that
+    // scaling by the Perez function gives absolute values
+    zenith: Vector3,
+    sun_radiance: Vector3,
+    intensity: f32,
+}
+
+impl Sky {
+    pub fn new(sun_direction: Vector3, turbidity: f32, intensity: f32) -> Sky {
+        let t = turbidity;
+        let sun_direction = sun_direction.get_unit_vector();
+        let theta_s = sun_direction.y().clamp(0.0, 1.0).acos();
+
+        let perez_luminance = [
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ];
+        let perez_x = [
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ];
+        let perez_y = [
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (f32::consts::PI - 2.0 * theta_s);
+        let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+        let zenith_x = t * t * cubic(&[0.00166, -0.00375, 0.00209, 0.0], theta_s)
+            + t * cubic(&[-0.02903, 0.06377, -0.03202, 0.00394], theta_s)
+            + cubic(&[0.11693, -0.21196, 0.06052, 0.25886], theta_s);
+        let zenith_y = t * t * cubic(&[0.00275, -0.00610, 0.00317, 0.0], theta_s)
+            + t * cubic(&[-0.04214, 0.08970, -0.04153, 0.00516], theta_s)
+            + cubic(&[0.15346, -0.26756, 0.06670, 0.26688], theta_s);
+
+        let zenith = Vector3::new(
+            zenith_luminance / perez(&perez_luminance, 1.0, theta_s),
+            zenith_x / perez(&perez_x, 1.0, theta_s),
+            zenith_y / perez(&perez_y, 1.0, theta_s),
+        );
+
+        Sky {
+            sun_direction,
+            perez_luminance,
+            perez_x,
+            perez_y,
+            zenith,
+            sun_radiance: Sky::sun_radiance(theta_s, turbidity),
+            intensity,
+        }
+    }
+
+    // Sunlight attenuated by Rayleigh and aerosol scattering on its way
+    // through the atmosphere, from the appendix of the paper
+    fn sun_radiance(theta_s: f32, turbidity: f32) -> Vector3 {
+        if theta_s >= f32::consts::FRAC_PI_2 {
+            return Vector3::zero();
+        }
+        // Relative optical mass of the air the sunlight passes through
+        let m = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+        let beta = 0.04608 * turbidity - 0.04586;
+
+        let reference = blackbody(560.0, 5778.0);
+        let color = spectrum::spectrum_to_rgb(|lambda| {
+            let micrometres = lambda / 1000.0;
+            let rayleigh = (-0.008735 * micrometres.powf(-4.08) * m).exp();
+            let aerosol = (-beta * micrometres.powf(-1.3) * m).exp();
+            blackbody(lambda, 5778.0) / reference * rayleigh * aerosol
+        });
+        color * (SUN_LUMINANCE * SKY_SCALE)
+    }
+
+    fn sun_visible(&self) -> bool {
+        self.sun_direction.y() > 0.0
+    }
+
+    pub fn radiance(&self, direction: &Vector3) -> Vector3 {
+        let d = direction.get_unit_vector();
+        // Below the horizon, repeat the horizon
+        let cos_theta = d.y().max(0.001);
+        let cos_gamma = d.dot(&self.sun_direction);
+        let gamma = cos_gamma.clamp(-1.0, 1.0).acos();
+
+        let luminance = self.zenith.x() * perez(&self.perez_luminance, cos_theta, gamma);
+        let x = self.zenith.y() * perez(&self.perez_x, cos_theta, gamma);
+        let y = self.zenith.z() * perez(&self.perez_y, cos_theta, gamma);
+        let sky = if y > 0.0 {
+            spectrum::xyz_to_rgb(Vector3::new(
+                x / y * luminance,
+                luminance,
+                (1.0 - x - y) / y * luminance,
+            ))
+        } else {
+            Vector3::zero()
+        };
+
+        let mut radiance = sky * SKY_SCALE;
+        if cos_gamma >= SUN_RADIUS.cos() {
+            radiance = radiance + self.sun_radiance;
+        }
+        self.intensity * radiance
+    }
+
+    // Picks either a direction towards the sun, or one from a cosine
+    // distribution over the upper hemisphere for the rest of the sky
+    pub fn sample(&self, sampler: &mut Sampler) -> Option<(Vector3, Vector3, f32)> {
+        let direction = if self.sun_visible() && sampler.uniform() < SUN_SAMPLE_PROBABILITY {
+            let cos_theta = 1.0 - sampler.uniform() * (1.0 - SUN_RADIUS.cos());
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = 2.0 * f32::consts::PI * sampler.uniform();
+            let (u, v) = self.sun_direction.orthonormal_basis();
+            sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * self.sun_direction
+        } else {
+            (Vector3::new(0.0, 1.0, 0.0) + sampler.unit_vector()).get_unit_vector()
+        };
+
+        let pdf = self.pdf(&direction);
+        if pdf > 0.0 {
+            Some((direction, self.radiance(&direction), pdf))
+        } else {
+            None
+        }
+    }
+
+    pub fn pdf(&self, direction: &Vector3) -> f32 {
+        let d = direction.get_unit_vector();
+        let sun_probability = if self.sun_visible() {
+            SUN_SAMPLE_PROBABILITY
+        } else {
+            0.0
+        };
+        let mut pdf = (1.0 - sun_probability) * d.y().max(0.0) / f32::consts::PI;
+        if d.dot(&self.sun_direction) >= SUN_RADIUS.cos() {
+            pdf += sun_probability / (2.0 * f32::consts::PI * (1.0 - SUN_RADIUS.cos()));
+        }
+        pdf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Samples come with the density pdf() gives them, and weighted by it
+    // integrate cos θ over the hemisphere, and the sun's disk, correctly
+    #[test]
+    fn pdf_matches_sample() {
+        let pi = f32::consts::PI;
+        let cone_solid_angle = 2.0 * pi * (1.0 - SUN_RADIUS.cos());
+        for (sun, sun_probability) in [
+            (Vector3::new(1.0, 1.0, 0.3), SUN_SAMPLE_PROBABILITY),
+            // Set, so never sampled
+            (Vector3::new(1.0, -0.2, 0.0), 0.0),
+        ] {
+            let sun = sun.get_unit_vector();
+            let sky = Sky::new(sun, 3.0, 1.0);
+            let trials = 100_000;
+            let mut sampler = Sampler::new(0);
+            let (mut cosine, mut cone, mut in_cone) = (0.0, 0.0, 0);
+            for _ in 0..trials {
+                let (direction, radiance, pdf) = sky.sample(&mut sampler).unwrap();
+                assert!((sky.pdf(&direction) - pdf).abs() <= 0.0001 * pdf);
+                assert!(radiance == sky.radiance(&direction));
+                cosine += direction.y() / pdf;
+                if direction.dot(&sun) >= SUN_RADIUS.cos() {
+                    cone += 1.0 / pdf;
+                    in_cone += 1;
+                }
+            }
+            let cosine = cosine / trials as f32;
+            assert!((cosine - pi).abs() < 0.015 * pi, "{}", cosine);
+            let fraction = in_cone as f32 / trials as f32;
+            assert!((fraction - sun_probability).abs() < 0.01, "{}", fraction);
+            if sun_probability > 0.0 {
+                let cone = cone / trials as f32;
+                let error = (cone - cone_solid_angle).abs() / cone_solid_angle;
+                assert!(error < 0.015, "{}", cone);
+            }
+        }
+    }
+}
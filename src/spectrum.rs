@@ -0,0 +1,152 @@
+use crate::random::Sampler;
+use crate::vec3::Vector3;
+use std::sync::OnceLock;
+
+// Range of wavelengths traced in spectral mode, in nanometres
+pub const LAMBDA_MIN: f32 = 380.0;
+pub const LAMBDA_MAX: f32 = 780.0;
+
+// Piecewise gaussian used by the CIE fit below
+fn piecewise_gaussian(x: f32, mu: f32, sigma_low: f32, sigma_high: f32) -> f32 {
+    let sigma = if x < mu { sigma_low } else { sigma_high };
+    let t = (x - mu) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+// CIE 1931 colour matching functions, using the multi-lobe fit from
+// Wyman, Sloan and Shirley, "Simple Analytic Approximations to the CIE XYZ
+// Color Matching Functions" (2013)
+pub fn cie_xyz(lambda: f32) -> Vector3 {
+    let x = 1.056 * piecewise_gaussian(lambda, 599.8, 37.9, 31.0)
+        + 0.362 * piecewise_gaussian(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * piecewise_gaussian(lambda, 501.1, 20.4, 26.2);
+    let y = 0.821 * piecewise_gaussian(lambda, 568.8, 46.9, 40.5)
+        + 0.286 * piecewise_gaussian(lambda, 530.9, 16.3, 31.1);
+    let z = 1.217 * piecewise_gaussian(lambda, 437.0, 11.8, 36.0)
+        + 0.681 * piecewise_gaussian(lambda, 459.0, 26.0, 13.8);
+    Vector3::new(x, y, z)
+}
+
+// Linear sRGB primaries (D65)
+pub fn xyz_to_rgb(xyz: Vector3) -> Vector3 {
+    Vector3::new(
+        3.2406 * xyz.x() - 1.5372 * xyz.y() - 0.4986 * xyz.z(),
+        -0.9689 * xyz.x() + 1.8758 * xyz.y() + 0.0415 * xyz.z(),
+        0.0557 * xyz.x() - 0.2040 * xyz.y() + 1.0570 * xyz.z(),
+    )
+}
+
+// Y of a linear sRGB colour
+pub fn luminance(rgb: &Vector3) -> f32 {
+    0.2126 * rgb.r() + 0.7152 * rgb.g() + 0.0722 * rgb.b()
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Upsamples an RGB triple to a smooth spectrum and evaluates it at lambda.
+// The blue, green and red basis spectra sum to one everywhere, so white
+// stays flat, and reflectances in [0, 1] stay physically valid. Being
+// linear it works equally well for emission values above one.
+pub fn rgb_to_spectrum(rgb: &Vector3, lambda: f32) -> f32 {
+    let blue = 1.0 - smoothstep(475.0, 505.0, lambda);
+    let red = smoothstep(570.0, 600.0, lambda);
+    let green = 1.0 - blue - red;
+    rgb.r() * red + rgb.g() * green + rgb.b() * blue
+}
+
+// Spectral rays carry a single wavelength, so colours are upsampled and
+// evaluated there. Every channel then holds the same value.
+pub fn upsample(color: &Vector3, wavelength: Option<f32>) -> Vector3 {
+    match wavelength {
+        Some(lambda) => {
+            let value = rgb_to_spectrum(color, lambda);
+            Vector3::new(value, value, value)
+        }
+        None => *color,
+    }
+}
+
+pub fn sample_wavelength(sampler: &mut Sampler) -> f32 {
+    LAMBDA_MIN + (LAMBDA_MAX - LAMBDA_MIN) * sampler.uniform()
+}
+
+// The RGB of a flat unit spectrum, used to white balance so that a flat
+// spectrum maps back to (1, 1, 1)
+fn white_point() -> Vector3 {
+    static WHITE_POINT: OnceLock<Vector3> = OnceLock::new();
+    *WHITE_POINT.get_or_init(|| {
+        let steps = 1000;
+        let mut sum = Vector3::zero();
+        for i in 0..steps {
+            let lambda = LAMBDA_MIN + (LAMBDA_MAX - LAMBDA_MIN) * (i as f32 + 0.5) / steps as f32;
+            sum = sum + xyz_to_rgb(cie_xyz(lambda));
+        }
+        sum / steps as f32
+    })
+}
+
+// Converts the radiance carried at one uniformly sampled wavelength into an
+// RGB estimate. Averaging these over many wavelengths integrates the spectrum
+// against the colour matching functions.
+pub fn wavelength_to_rgb(lambda: f32, radiance: f32) -> Vector3 {
+    let rgb = xyz_to_rgb(cie_xyz(lambda));
+    let white = white_point();
+    Vector3::new(
+        radiance * rgb.r() / white.r(),
+        radiance * rgb.g() / white.g(),
+        radiance * rgb.b() / white.b(),
+    )
+}
+
+// RGB of a spectral function, integrated the same way as the samples from
+// wavelength_to_rgb
+pub fn spectrum_to_rgb<F: Fn(f32) -> f32>(spectrum: F) -> Vector3 {
+    let steps = 200;
+    let mut sum = Vector3::zero();
+    for i in 0..steps {
+        let lambda = LAMBDA_MIN + (LAMBDA_MAX - LAMBDA_MIN) * (i as f32 + 0.5) / steps as f32;
+        sum = sum + wavelength_to_rgb(lambda, spectrum(lambda));
+    }
+    sum / steps as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn integrate(rgb: &Vector3) -> Vector3 {
+        spectrum_to_rgb(|lambda| rgb_to_spectrum(rgb, lambda))
+    }
+
+    #[test]
+    fn white_is_flat() {
+        let white = Vector3::new(1.0, 1.0, 1.0);
+        for lambda in [380.0, 450.0, 550.0, 650.0, 780.0].iter() {
+            assert!((rgb_to_spectrum(&white, *lambda) - 1.0).abs() < 0.0001);
+        }
+
+        let result = integrate(&white);
+        assert!((result.r() - 1.0).abs() < 0.01, "Got {}", result);
+        assert!((result.g() - 1.0).abs() < 0.01, "Got {}", result);
+        assert!((result.b() - 1.0).abs() < 0.01, "Got {}", result);
+    }
+
+    #[test]
+    fn primaries_keep_their_hue() {
+        let red = integrate(&Vector3::new(1.0, 0.0, 0.0));
+        assert!(red.r() > red.g() && red.r() > red.b(), "Got {}", red);
+
+        let green = integrate(&Vector3::new(0.0, 1.0, 0.0));
+        assert!(
+            green.g() > green.r() && green.g() > green.b(),
+            "Got {}",
+            green
+        );
+
+        let blue = integrate(&Vector3::new(0.0, 0.0, 1.0));
+        assert!(blue.b() > blue.r() && blue.b() > blue.g(), "Got {}", blue);
+    }
+}
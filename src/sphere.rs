@@ -2,12 +2,25 @@ use crate::aabb::AABB;
 use crate::hitable::HitRecord;
 use crate::hitable::Hitable;
 use crate::material::Material;
+use crate::motion::Keyframes;
 use crate::ray::Ray;
 use crate::vec3::Vector3;
+use std::f32;
+
+// Longitude and latitude of a point on the unit sphere, as in Ray Tracing:
+// The Next Week
+pub fn sphere_uv(normal: &Vector3) -> (f32, f32) {
+    let phi = normal.z().atan2(normal.x());
+    let theta = normal.y().clamp(-1.0, 1.0).asin();
+    (
+        1.0 - (phi + f32::consts::PI) / (2.0 * f32::consts::PI),
+        (theta + f32::consts::FRAC_PI_2) / f32::consts::PI,
+    )
+}
 
 #[derive(Clone)]
 pub struct Sphere {
-    center: Vector3,
+    center: Keyframes<Vector3>,
     radius: f32,
     material: Material,
 }
@@ -15,51 +28,95 @@ pub struct Sphere {
 impl Sphere {
     pub fn new(center: Vector3, radius: f32, material: Material) -> Sphere {
         Sphere {
-            center,
+            center: Keyframes::constant(center),
             radius,
             material,
         }
     }
-}
 
-impl Hitable for Sphere {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let oc: Vector3 = r.origin() - self.center;
+    // Moves the centre over time, e.g. for motion blur
+    pub fn with_motion(mut self, center: Keyframes<Vector3>) -> Sphere {
+        self.center = center;
+        self
+    }
+
+    // Where the line of r crosses the sphere centred at center, nearest
+    // first
+    fn roots(&self, r: &Ray, center: Vector3) -> Option<(f32, f32)> {
+        let oc: Vector3 = r.origin() - center;
         let a = r.direction().length_squared();
         let b = oc.dot(&r.direction());
         let c = oc.dot(&oc) - self.radius * self.radius;
         let discriminant = b * b - a * c;
         if discriminant > 0.0 {
-            let mut temp = (-b - discriminant.sqrt()) / a;
-            if temp < t_max && temp > t_min {
-                let t = temp;
-                let p = r.point_at_parameter(t);
-                return Some(HitRecord {
-                    t,
-                    p,
-                    normal: (p - self.center) / self.radius,
-                    material: self.material.clone(),
-                });
-            }
-            temp = (-b + discriminant.sqrt()) / a;
-            if temp < t_max && temp > t_min {
-                let t = temp;
+            let root = discriminant.sqrt();
+            Some(((-b - root) / a, (-b + root) / a))
+        } else {
+            None
+        }
+    }
+
+    fn hit_record(&self, t: f32, p: Vector3, center: Vector3) -> HitRecord {
+        let normal = (p - center) / self.radius;
+        let (u, v) = sphere_uv(&normal);
+        HitRecord {
+            t,
+            p,
+            normal,
+            u,
+            v,
+            material: self.material.clone(),
+            object_id: 0,
+        }
+    }
+}
+
+impl Hitable for Sphere {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let center = self.center.at(r.time());
+        let (near, far) = self.roots(r, center)?;
+        for t in [near, far] {
+            if t < t_max && t > t_min {
                 let p = r.point_at_parameter(t);
-                return Some(HitRecord {
-                    t,
-                    p,
-                    normal: (p - self.center) / self.radius,
-                    material: self.material.clone(),
-                });
+                return Some(self.hit_record(t, p, center));
             }
         }
-        return None;
+        None
+    }
+
+    fn all_hits(&self, r: &Ray) -> Option<Vec<HitRecord>> {
+        let center = self.center.at(r.time());
+        Some(match self.roots(r, center) {
+            Some((near, far)) => [near, far]
+                .iter()
+                .map(|&t| self.hit_record(t, r.point_at_parameter(t), center))
+                .collect(),
+            None => Vec::new(),
+        })
+    }
+
+    fn is_solid(&self) -> bool {
+        true
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.radius > 0.0 {
+            Ok(())
+        } else {
+            Err(format!("Sphere radius {} isn't positive", self.radius))
+        }
     }
 
+    // Covers the whole of the sphere's motion
     fn bounding_box(&self) -> Option<AABB> {
-        Some(AABB::new(
-            self.center - Vector3::new(self.radius, self.radius, self.radius),
-            self.center + Vector3::new(self.radius, self.radius, self.radius),
-        ))
+        let radius = Vector3::new(self.radius, self.radius, self.radius);
+        self.center
+            .segments()
+            .into_iter()
+            .map(|(start, end)| {
+                AABB::new(start - radius, start + radius)
+                    .surrounding_box(&AABB::new(end - radius, end + radius))
+            })
+            .reduce(|a, b| a.surrounding_box(&b))
     }
 }
@@ -0,0 +1,130 @@
+use crate::vec3::Vector3;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+extern crate image;
+
+// Reads any format the image crate understands into linear RGB. HDR and
+// EXR files are used as is, others are assumed to be gamma encoded.
+pub fn load_linear_rgb(path: &Path) -> io::Result<(usize, usize, Vec<Vector3>)> {
+    let image =
+        image::open(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    let linear = matches!(
+        image.color(),
+        image::ColorType::Rgb32F | image::ColorType::Rgba32F
+    );
+    let rgb = image.into_rgb32f();
+    let (width, height) = (rgb.width() as usize, rgb.height() as usize);
+    let pixels = rgb
+        .pixels()
+        .map(|p| {
+            let decode = |c: f32| if linear { c } else { c.powf(2.2) };
+            Vector3::new(decode(p[0]), decode(p[1]), decode(p[2]))
+        })
+        .collect();
+    Ok((width, height, pixels))
+}
+
+// Colours varying over a surface, looked up by its (u, v) coordinates
+#[derive(Clone)]
+pub enum Texture {
+    Checker(CheckerTexture),
+    Image(Arc<ImageTexture>),
+}
+
+impl Texture {
+    pub fn value(&self, u: f32, v: f32) -> Vector3 {
+        match self {
+            Texture::Checker(checker) => checker.value(u, v),
+            Texture::Image(image) => image.value(u, v),
+        }
+    }
+
+    // Mean colour over the whole texture
+    pub fn average(&self) -> Vector3 {
+        match self {
+            Texture::Checker(checker) => checker.average(),
+            Texture::Image(image) => image.average,
+        }
+    }
+
+    // Describes the texture for hashing into material IDs. Images are told
+    // apart by their size and average.
+    pub fn id_words(&self) -> Vec<u32> {
+        let bits = |v: &Vector3| [v.x().to_bits(), v.y().to_bits(), v.z().to_bits()];
+        match self {
+            Texture::Checker(checker) => [
+                &[1][..],
+                &bits(&checker.odd),
+                &bits(&checker.even),
+                &[checker.squares],
+            ]
+            .concat(),
+            Texture::Image(image) => [
+                &[2, image.width as u32, image.height as u32][..],
+                &bits(&image.average),
+            ]
+            .concat(),
+        }
+    }
+}
+
+// Alternating squares, `squares` to a side
+#[derive(Clone)]
+pub struct CheckerTexture {
+    odd: Vector3,
+    even: Vector3,
+    squares: u32,
+}
+
+impl CheckerTexture {
+    pub fn new(odd: Vector3, even: Vector3, squares: u32) -> CheckerTexture {
+        assert!(squares > 0, "A checker texture needs at least one square");
+        CheckerTexture { odd, even, squares }
+    }
+
+    fn value(&self, u: f32, v: f32) -> Vector3 {
+        let x = (u * self.squares as f32).floor() as i32;
+        let y = (v * self.squares as f32).floor() as i32;
+        if (x + y) % 2 == 0 {
+            self.even
+        } else {
+            self.odd
+        }
+    }
+
+    fn average(&self) -> Vector3 {
+        // An odd number of squares has one more even square
+        let n = self.squares * self.squares;
+        let n_even = n.div_ceil(2);
+        (n_even as f32 * self.even + (n - n_even) as f32 * self.odd) / n as f32
+    }
+}
+
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vector3>,
+    average: Vector3,
+}
+
+impl ImageTexture {
+    pub fn load(path: &Path) -> io::Result<ImageTexture> {
+        let (width, height, pixels) = load_linear_rgb(path)?;
+        let sum = pixels.iter().fold(Vector3::zero(), |sum, p| sum + *p);
+        Ok(ImageTexture {
+            width,
+            height,
+            average: sum / pixels.len().max(1) as f32,
+            pixels,
+        })
+    }
+
+    // v = 0 is the bottom of the image
+    fn value(&self, u: f32, v: f32) -> Vector3 {
+        let x = ((u.clamp(0.0, 1.0) * self.width as f32) as usize).min(self.width - 1);
+        let y = (((1.0 - v.clamp(0.0, 1.0)) * self.height as f32) as usize).min(self.height - 1);
+        self.pixels[x + y * self.width]
+    }
+}
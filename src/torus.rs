@@ -0,0 +1,165 @@
+use crate::aabb::AABB;
+use crate::hitable::{HitRecord, Hitable};
+use crate::material::Material;
+use crate::polynomial;
+use crate::ray::Ray;
+use crate::vec3::Vector3;
+use std::f32;
+
+// A ring doughnut around a vertical axis, found exactly by solving a
+// quartic rather than by marching a distance field
+#[derive(Clone)]
+pub struct Torus {
+    center: Vector3,
+    // From the axis to the middle of the tube
+    major_radius: f32,
+    // Of the tube
+    minor_radius: f32,
+    material: Material,
+}
+
+impl Torus {
+    pub fn new(center: Vector3, major_radius: f32, minor_radius: f32, material: Material) -> Torus {
+        Torus {
+            center,
+            major_radius,
+            minor_radius,
+            material,
+        }
+    }
+
+    // Where the line of r crosses the surface, nearest first
+    fn roots(&self, r: &Ray) -> Vec<f32> {
+        let length = f64::from(r.direction().length());
+        let d = r.direction() / r.direction().length();
+        let (dx, dy, dz) = (f64::from(d.x()), f64::from(d.y()), f64::from(d.z()));
+        let o = r.origin() - self.center;
+        // Measured from the point on the line nearest the centre, as the
+        // quartic's roots are only accurate for small coefficients
+        let shift = -(f64::from(o.x()) * dx + f64::from(o.y()) * dy + f64::from(o.z()) * dz);
+        let (ox, oy, oz) = (
+            f64::from(o.x()) + shift * dx,
+            f64::from(o.y()) + shift * dy,
+            f64::from(o.z()) + shift * dz,
+        );
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + z^2), with p = o + s d
+        let (major, minor) = (f64::from(self.major_radius), f64::from(self.minor_radius));
+        let four_r2 = 4.0 * major * major;
+        let f = ox * dx + oy * dy + oz * dz;
+        let k = ox * ox + oy * oy + oz * oz + major * major - minor * minor;
+        polynomial::solve_quartic(
+            1.0,
+            4.0 * f,
+            4.0 * f * f + 2.0 * k - four_r2 * (dx * dx + dz * dz),
+            4.0 * f * k - 2.0 * four_r2 * (ox * dx + oz * dz),
+            k * k - four_r2 * (ox * ox + oz * oz),
+        )
+        .into_iter()
+        .map(|s| ((s + shift) / length) as f32)
+        .collect()
+    }
+
+    fn hit_record(&self, r: &Ray, t: f32) -> HitRecord {
+        let p = r.point_at_parameter(t);
+        let local = p - self.center;
+        // Out from the middle of the tube
+        let around = Vector3::new(local.x(), 0.0, local.z()).get_unit_vector();
+        let normal = (local - around * self.major_radius).get_unit_vector();
+        // Around the axis, then around the tube from its outside
+        let phi = local.z().atan2(local.x());
+        let ring = (local.x() * local.x() + local.z() * local.z()).sqrt() - self.major_radius;
+        let theta = local.y().atan2(ring);
+        HitRecord {
+            t,
+            p,
+            normal,
+            u: 0.5 + phi / (2.0 * f32::consts::PI),
+            v: 0.5 + theta / (2.0 * f32::consts::PI),
+            material: self.material.clone(),
+            object_id: 0,
+        }
+    }
+}
+
+impl Hitable for Torus {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        if !self.bounding_box()?.hit(r, t_min, t_max) {
+            return None;
+        }
+        self.roots(r)
+            .into_iter()
+            .find(|&t| t > t_min && t < t_max)
+            .map(|t| self.hit_record(r, t))
+    }
+
+    fn all_hits(&self, r: &Ray) -> Option<Vec<HitRecord>> {
+        Some(
+            self.roots(r)
+                .into_iter()
+                .map(|t| self.hit_record(r, t))
+                .collect(),
+        )
+    }
+
+    fn is_solid(&self) -> bool {
+        true
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.major_radius > 0.0 && self.minor_radius > 0.0 {
+            Ok(())
+        } else {
+            Err(format!(
+                "Torus radii {} and {} aren't positive",
+                self.major_radius, self.minor_radius
+            ))
+        }
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        let outer = self.major_radius + self.minor_radius;
+        let half_size = Vector3::new(outer, self.minor_radius, outer);
+        Some(AABB::new(self.center - half_size, self.center + half_size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hits_the_ring() {
+        let material = Material::Lambertian {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        };
+        let torus = Torus::new(Vector3::new(0.0, 1.0, 0.0), 2.0, 0.5, material);
+
+        // Across the middle, through both sides of the tube and the hole
+        let r = Ray::new(Vector3::new(-10.0, 1.0, 0.0), Vector3::new(2.0, 0.0, 0.0));
+        let hits = torus.all_hits(&r).unwrap();
+        let xs: Vec<f32> = hits.iter().map(|hr| hr.p.x()).collect();
+        let expected = [-2.5, -1.5, 1.5, 2.5];
+        assert_eq!(xs.len(), 4);
+        assert!(xs
+            .iter()
+            .zip(&expected)
+            .all(|(a, b)| (a - b).abs() < 0.0001));
+        for (i, hr) in hits.iter().enumerate() {
+            // Alternately going in and out
+            assert_eq!(hr.normal.dot(&r.direction()) < 0.0, i % 2 == 0);
+            assert!((hr.normal.length() - 1.0).abs() < 0.0001);
+        }
+        let hr = torus.hit(&r, 0.001, f32::MAX).unwrap();
+        assert!((hr.t - 3.75).abs() < 0.0001);
+
+        // Down through the top of the tube, from far away
+        let r = Ray::new(Vector3::new(0.0, 1000.0, 2.0), Vector3::new(0.0, -1.0, 0.0));
+        let hr = torus.hit(&r, 0.001, f32::MAX).unwrap();
+        assert!((hr.p.y() - 1.5).abs() < 0.001);
+        assert!(hr.normal.y() > 0.999);
+        // Down the hole
+        let r = Ray::new(Vector3::new(0.0, 10.0, 0.0), Vector3::new(0.0, -1.0, 0.0));
+        assert!(torus.hit(&r, 0.001, f32::MAX).is_none());
+    }
+}
@@ -4,6 +4,7 @@ use crate::hitable::Hitable;
 use crate::material::Material;
 use crate::ray::Ray;
 use crate::vec3::Vector3;
+use std::collections::HashMap;
 use std::f32;
 use std::fmt;
 
@@ -13,6 +14,8 @@ pub struct Triangle {
     v2: Vector3,
     v3: Vector3,
     material: Material,
+    // Texture coordinates at each vertex
+    uvs: [(f32, f32); 3],
     // There are two-normals, we only store one
 }
 
@@ -24,13 +27,95 @@ impl Triangle {
             v2,
             v3,
             material,
+            uvs: [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)],
         }
     }
+
+    pub fn with_uvs(mut self, uvs: [(f32, f32); 3]) -> Triangle {
+        self.uvs = uvs;
+        self
+    }
+
+    pub fn with_material(mut self, material: Material) -> Triangle {
+        self.material = material;
+        self
+    }
+
+    // Counter-clockwise vertices face this way
+    pub fn normal(&self) -> Vector3 {
+        (self.v2 - self.v1)
+            .cross(&(self.v3 - self.v1))
+            .get_unit_vector()
+    }
+
+    pub fn area(&self) -> f32 {
+        0.5 * (self.v2 - self.v1).cross(&(self.v3 - self.v1)).length()
+    }
+
+    // Texture coordinates at barycentric coordinates (b1, b2), the weights
+    // of v2 and v3
+    fn uv(&self, b1: f32, b2: f32) -> (f32, f32) {
+        let b0 = 1.0 - b1 - b2;
+        (
+            b0 * self.uvs[0].0 + b1 * self.uvs[1].0 + b2 * self.uvs[2].0,
+            b0 * self.uvs[0].1 + b1 * self.uvs[1].1 + b2 * self.uvs[2].1,
+        )
+    }
+
+    // A point spread uniformly over the triangle for u1, u2 uniform in
+    // [0, 1), with its texture coordinates
+    pub fn sample(&self, u1: f32, u2: f32) -> (Vector3, (f32, f32)) {
+        let su = u1.sqrt();
+        let (b1, b2) = (su * (1.0 - u2), su * u2);
+        let p = (1.0 - b1 - b2) * self.v1 + b1 * self.v2 + b2 * self.v3;
+        (p, self.uv(b1, b2))
+    }
 }
 
-impl Hitable for Triangle {
+// Two triangles spanning corner + s * edge_u + t * edge_v for s, t in
+// [0, 1], with matching texture coordinates. Facing edge_u x edge_v.
+pub fn quad(
+    corner: Vector3,
+    edge_u: Vector3,
+    edge_v: Vector3,
+    material: Material,
+) -> Vec<Triangle> {
+    let (a, b, c, d) = (
+        corner,
+        corner + edge_u,
+        corner + edge_u + edge_v,
+        corner + edge_v,
+    );
+    vec![
+        Triangle::new(a, b, c, material.clone()).with_uvs([(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)]),
+        Triangle::new(a, c, d, material).with_uvs([(0.0, 0.0), (1.0, 1.0), (0.0, 1.0)]),
+    ]
+}
+
+// Whether triangles, wound the same way, close off an inside: every edge
+// is shared by exactly two of them, running opposite ways. Corners must
+// match exactly, as in meshes with shared vertices.
+pub fn is_closed(triangles: &[[Vector3; 3]]) -> bool {
+    // Adding zero makes -0 and 0 the same
+    let key = |v: &Vector3| [0, 1, 2].map(|axis| (v.component(axis) + 0.0).to_bits());
+    let mut edges = HashMap::new();
+    for corners in triangles {
+        for i in 0..3 {
+            let edge = (key(&corners[i]), key(&corners[(i + 1) % 3]));
+            *edges.entry(edge).or_insert(0) += 1;
+        }
+    }
+    !edges.is_empty()
+        && edges
+            .iter()
+            .all(|(&(a, b), &count)| count == 1 && edges.get(&(b, a)) == Some(&1))
+}
+
+impl Triangle {
+    // Where the line of r crosses the triangle, at any t, and the
+    // barycentric coordinates there
     // http://fileadmin.cs.lth.se/cs/Personal/Tomas_Akenine-Moller/code/raytri_tam.pdf
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn crossing(&self, r: &Ray) -> Option<(f32, f32, f32)> {
         // TODO This are recalculated everytime, could probably store them with the triangle
         // Edges
         let edge1 = self.v2 - self.v1;
@@ -56,7 +141,7 @@ impl Hitable for Triangle {
                 return None;
             }
         } else {
-            if u >= 0.0 || u <= det {
+            if u > 0.0 || u < det {
                 return None;
             }
         }
@@ -68,7 +153,7 @@ impl Hitable for Triangle {
                 return None;
             }
         } else {
-            if v >= 0.0 || u + v <= det {
+            if v > 0.0 || u + v < det {
                 return None;
             }
         }
@@ -78,32 +163,62 @@ impl Hitable for Triangle {
         let mut t = edge2.dot(&qvec);
         t *= inv_det;
 
+        Some((t, u * inv_det, v * inv_det))
+    }
+
+    fn hit_record(&self, r: &Ray, (t, b1, b2): (f32, f32, f32)) -> HitRecord {
         // Normal
         // TODO Could also calculate this once and store with the triangle
-        let n = edge1.cross(&edge2).get_unit_vector();
+        let n = self.normal();
 
         let p = r.point_at_parameter(t);
 
+        let (tex_u, tex_v) = self.uv(b1, b2);
+        HitRecord {
+            t,
+            p,
+            normal: n,
+            u: tex_u,
+            v: tex_v,
+            material: self.material.clone(),
+            object_id: 0,
+        }
+    }
+}
+
+impl Hitable for Triangle {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let crossing = self.crossing(r)?;
+        let t = crossing.0;
         if t > 0.00001 && t < t_max && t > t_min {
-            return Some(HitRecord {
-                t,
-                p,
-                normal: n,
-                material: self.material.clone(),
-            });
+            Some(self.hit_record(r, crossing))
         } else {
-            return None;
+            None
         }
     }
 
+    // Closed meshes are solids, with their triangles facing out
+    fn all_hits(&self, r: &Ray) -> Option<Vec<HitRecord>> {
+        Some(
+            self.crossing(r)
+                .map(|crossing| self.hit_record(r, crossing))
+                .into_iter()
+                .collect(),
+        )
+    }
+
+    fn triangles(&self) -> Option<Vec<[Vector3; 3]>> {
+        Some(vec![[self.v1, self.v2, self.v3]])
+    }
+
     fn bounding_box(&self) -> Option<AABB> {
         let min_x = self.v1.x().min(self.v2.x()).min(self.v3.x());
         let min_y = self.v1.y().min(self.v2.y()).min(self.v3.y());
         let min_z = self.v1.z().min(self.v2.z()).min(self.v3.z());
 
-        let max_x = self.v1.x().min(self.v2.x()).min(self.v3.x());
-        let max_y = self.v1.y().min(self.v2.y()).min(self.v3.y());
-        let max_z = self.v1.z().min(self.v2.z()).min(self.v3.z());
+        let max_x = self.v1.x().max(self.v2.x()).max(self.v3.x());
+        let max_y = self.v1.y().max(self.v2.y()).max(self.v3.y());
+        let max_z = self.v1.z().max(self.v2.z()).max(self.v3.z());
 
         Some(AABB::new(
             Vector3::new(min_x, min_y, min_z),
@@ -224,14 +339,62 @@ mod tests {
             },
         );
 
-        match t.hit(&r, 0.0, f32::MAX) {
-            Some(hr) => panic!(
+        if let Some(hr) = t.hit(&r, 0.0, f32::MAX) {
+            panic!(
                 "Triangle {} should not be hit by ray {} . Intersection point found at {}.",
                 t,
                 r,
                 r.point_at_parameter(hr.t)
-            ),
-            None => (),
+            );
+        }
+    }
+
+    #[test]
+    fn bounding_box_contains_the_vertices() {
+        let (v1, v2, v3) = (
+            Vector3::new(2.0, -1.0, 0.5),
+            Vector3::new(-3.0, 4.0, 1.0),
+            Vector3::new(0.0, 1.0, -2.0),
+        );
+        let t = Triangle::new(
+            v1,
+            v2,
+            v3,
+            Material::Lambertian {
+                albedo: Vector3::new(0.0, 0.0, 0.0),
+            },
+        );
+        let bbox = t.bounding_box().unwrap();
+        for v in [v1, v2, v3] {
+            for axis in 0..3 {
+                assert!(bbox.min().component(axis) <= v.component(axis));
+                assert!(bbox.max().component(axis) >= v.component(axis));
+            }
+        }
+        assert!(bbox.min() == Vector3::new(-3.0, -1.0, -2.0));
+        assert!(bbox.max() == Vector3::new(2.0, 4.0, 1.0));
+    }
+
+    // Rays exactly along an edge hit whichever way the triangle faces them,
+    // so none slip between the triangles of a closed mesh
+    #[test]
+    fn edges_hit_from_both_sides() {
+        let t = Triangle::new(
+            Vector3::new(0.0, 0.0, 2.0),
+            Vector3::new(2.0, 0.0, 2.0),
+            Vector3::new(0.0, 2.0, 2.0),
+            Material::Lambertian {
+                albedo: Vector3::new(0.0, 0.0, 0.0),
+            },
+        );
+        // Through the middle of the first edge, and through a corner
+        for p in [Vector3::new(1.0, 0.0, 2.0), Vector3::new(0.0, 0.0, 2.0)] {
+            for dz in [-1.0, 1.0] {
+                let direction = Vector3::new(0.0, 0.0, dz);
+                let r = Ray::new(p - direction * 2.0, direction);
+                let hr = t.hit(&r, 0.0, f32::MAX);
+                assert!(hr.is_some_and(|hr| (hr.t - 2.0).abs() < 0.000001));
+            }
         }
     }
 }
@@ -27,6 +27,16 @@ impl Vector3 {
     pub fn z(&self) -> f32 {
         self.z
     }
+    // Component by axis index, 0 to 2
+    pub fn component(&self, axis: usize) -> f32 {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            2 => self.z,
+            _ => panic!("Bad axis index {}", axis),
+        }
+    }
+
     pub fn r(&self) -> f32 {
         self.x
     }
@@ -67,6 +77,18 @@ impl Vector3 {
         }
     }
 
+    // Two unit vectors completing an orthonormal basis with this unit vector
+    pub fn orthonormal_basis(&self) -> (Vector3, Vector3) {
+        let a = if self.x.abs() > 0.9 {
+            Vector3::new(0.0, 1.0, 0.0)
+        } else {
+            Vector3::new(1.0, 0.0, 0.0)
+        };
+        let v = self.cross(&a).get_unit_vector();
+        let u = v.cross(self);
+        (u, v)
+    }
+
     pub fn get_unit_vector(&self) -> Vector3 {
         let len = self.length();
         if len > 0.0 {
@@ -0,0 +1,309 @@
+use crate::perlin::Perlin;
+use crate::vec3::Vector3;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+// A dense grid of voxels with a density channel and an optional emission
+// channel. Values are stored x fastest, then y, then z.
+//
+// On disk, a grid is a single text header line
+//     dense <nx> <ny> <nz> <channels>
+// followed by nx * ny * nz * channels little-endian f32 values, with the
+// channels of each voxel next to each other. Channel 0 is density, channel
+// 1, if present, is emission.
+pub struct VoxelGrid {
+    nx: usize,
+    ny: usize,
+    nz: usize,
+    density: Vec<f32>,
+    emission: Vec<f32>,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl VoxelGrid {
+    pub fn new(
+        nx: usize,
+        ny: usize,
+        nz: usize,
+        density: Vec<f32>,
+        emission: Vec<f32>,
+    ) -> VoxelGrid {
+        assert_eq!(density.len(), nx * ny * nz);
+        assert!(emission.is_empty() || emission.len() == density.len());
+        VoxelGrid {
+            nx,
+            ny,
+            nz,
+            density,
+            emission,
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<VoxelGrid> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+
+        let fields: Vec<&str> = header.split_whitespace().collect();
+        if fields.len() != 5 || fields[0] != "dense" {
+            return Err(invalid_data(format!(
+                "Bad voxel grid header: {}",
+                header.trim()
+            )));
+        }
+        let mut dims = [0usize; 4];
+        for (dim, field) in dims.iter_mut().zip(fields[1..].iter()) {
+            *dim = field
+                .parse()
+                .map_err(|_| invalid_data(format!("Bad voxel grid header: {}", header.trim())))?;
+        }
+        let [nx, ny, nz, channels] = dims;
+        if nx == 0 || ny == 0 || nz == 0 {
+            return Err(invalid_data(format!(
+                "Voxel grid {}x{}x{} is empty",
+                nx, ny, nz
+            )));
+        }
+        if !(1..=2).contains(&channels) {
+            return Err(invalid_data(format!(
+                "Unsupported channel count {}",
+                channels
+            )));
+        }
+
+        let too_big = || invalid_data(format!("Voxel grid {}x{}x{} is too big", nx, ny, nz));
+        let count = nx
+            .checked_mul(ny)
+            .and_then(|n| n.checked_mul(nz))
+            .ok_or_else(too_big)?;
+        let size = count.checked_mul(channels * 4).ok_or_else(too_big)?;
+        let mut bytes = vec![0u8; size];
+        reader.read_exact(&mut bytes)?;
+
+        let mut density = Vec::with_capacity(count);
+        let mut emission = Vec::new();
+        for voxel in bytes.chunks(channels * 4) {
+            let mut values = voxel
+                .chunks(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+            density.push(values.next().unwrap());
+            if let Some(e) = values.next() {
+                emission.push(e);
+            }
+        }
+
+        Ok(VoxelGrid::new(nx, ny, nz, density, emission))
+    }
+
+    // A billowy cloud filling most of the grid
+    pub fn procedural_cloud(resolution: usize) -> VoxelGrid {
+        let noise = Perlin::new();
+        let density = |q: Vector3| {
+            let shape = 1.0 - Vector3::new(q.x(), 1.6 * q.y(), q.z()).length();
+            let detail = noise.turb(&(q * 3.0), 5);
+            ((shape + 0.5 * detail - 0.1) * 3.0).clamp(0.0, 1.0)
+        };
+        VoxelGrid::from_fn(resolution, &density, None)
+    }
+
+    // A fireball, hotter and denser towards its bottom and centre
+    pub fn procedural_fire(resolution: usize) -> VoxelGrid {
+        let noise = Perlin::new();
+        let shape = |q: &Vector3| {
+            1.0 - Vector3::new(q.x() * (1.5 + q.y()), 0.8 * q.y(), q.z() * (1.5 + q.y())).length()
+        };
+        let density = |q: Vector3| (shape(&q) + 0.5 * noise.turb(&(q * 4.0), 4) - 0.2).max(0.0);
+        let emission = |q: Vector3| {
+            let heat = shape(&q) + 0.4 * noise.turb(&(q * 5.0 + Vector3::new(7.0, 0.0, 0.0)), 4);
+            (heat * (1.0 - q.y()) - 0.1).max(0.0)
+        };
+        VoxelGrid::from_fn(resolution, &density, Some(&emission))
+    }
+
+    // Fills a cubic grid by evaluating functions of the voxel position in
+    // [-1, 1]^3
+    fn from_fn(
+        resolution: usize,
+        density: &dyn Fn(Vector3) -> f32,
+        emission: Option<&dyn Fn(Vector3) -> f32>,
+    ) -> VoxelGrid {
+        let mut densities = Vec::with_capacity(resolution * resolution * resolution);
+        let mut emissions = Vec::new();
+        for z in 0..resolution {
+            for y in 0..resolution {
+                for x in 0..resolution {
+                    let q = Vector3::new(x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5)
+                        * (2.0 / resolution as f32)
+                        - Vector3::new(1.0, 1.0, 1.0);
+                    densities.push(density(q));
+                    if let Some(emission) = emission {
+                        emissions.push(emission(q));
+                    }
+                }
+            }
+        }
+        VoxelGrid::new(resolution, resolution, resolution, densities, emissions)
+    }
+
+    pub fn has_emission(&self) -> bool {
+        !self.emission.is_empty()
+    }
+
+    fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        x + self.nx * (y + self.ny * z)
+    }
+
+    // Trilinear interpolation of a channel at a point in [0, 1]^3 of the
+    // grid, with voxel centres at (i + 0.5) / n
+    fn lookup(&self, channel: &[f32], q: &Vector3) -> f32 {
+        let gx = (q.x() * self.nx as f32 - 0.5)
+            .max(0.0)
+            .min((self.nx - 1) as f32);
+        let gy = (q.y() * self.ny as f32 - 0.5)
+            .max(0.0)
+            .min((self.ny - 1) as f32);
+        let gz = (q.z() * self.nz as f32 - 0.5)
+            .max(0.0)
+            .min((self.nz - 1) as f32);
+        let (x0, y0, z0) = (gx as usize, gy as usize, gz as usize);
+        let x1 = (x0 + 1).min(self.nx - 1);
+        let y1 = (y0 + 1).min(self.ny - 1);
+        let z1 = (z0 + 1).min(self.nz - 1);
+        let (fx, fy, fz) = (gx - x0 as f32, gy - y0 as f32, gz - z0 as f32);
+
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let c = |x, y, z| channel[self.index(x, y, z)];
+        lerp(
+            lerp(
+                lerp(c(x0, y0, z0), c(x1, y0, z0), fx),
+                lerp(c(x0, y1, z0), c(x1, y1, z0), fx),
+                fy,
+            ),
+            lerp(
+                lerp(c(x0, y0, z1), c(x1, y0, z1), fx),
+                lerp(c(x0, y1, z1), c(x1, y1, z1), fx),
+                fy,
+            ),
+            fz,
+        )
+    }
+
+    pub fn density(&self, q: &Vector3) -> f32 {
+        self.lookup(&self.density, q)
+    }
+
+    pub fn emission(&self, q: &Vector3) -> f32 {
+        if self.has_emission() {
+            self.lookup(&self.emission, q)
+        } else {
+            0.0
+        }
+    }
+}
+
+// Coarse grid of upper bounds on the density, so delta tracking can take
+// long steps through thin regions of the volume
+pub struct MajorantGrid {
+    pub resolution: usize,
+    majorants: Vec<f32>,
+}
+
+impl MajorantGrid {
+    pub fn new(grid: &VoxelGrid, resolution: usize) -> MajorantGrid {
+        // Voxels whose interpolation footprint overlaps a majorant cell
+        let voxel_range = |cell: usize, n: usize| {
+            let lo = (cell * n) as f32 / resolution as f32 - 0.5;
+            let hi = ((cell + 1) * n) as f32 / resolution as f32 - 0.5;
+            let first = lo.floor().max(0.0) as usize;
+            let last = (hi.ceil().max(0.0) as usize).min(n - 1);
+            first..=last
+        };
+
+        let mut majorants = Vec::with_capacity(resolution * resolution * resolution);
+        for cz in 0..resolution {
+            for cy in 0..resolution {
+                for cx in 0..resolution {
+                    let mut max_density: f32 = 0.0;
+                    for z in voxel_range(cz, grid.nz) {
+                        for y in voxel_range(cy, grid.ny) {
+                            for x in voxel_range(cx, grid.nx) {
+                                max_density = max_density.max(grid.density[grid.index(x, y, z)]);
+                            }
+                        }
+                    }
+                    majorants.push(max_density);
+                }
+            }
+        }
+        MajorantGrid {
+            resolution,
+            majorants,
+        }
+    }
+
+    pub fn majorant(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.majorants[x + self.resolution * (y + self.resolution * z)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn load_dense_file() {
+        let path = std::env::temp_dir().join("raytracer_voxel_test.grid");
+        {
+            let mut file = File::create(&path).unwrap();
+            writeln!(file, "dense 2 1 1 2").unwrap();
+            for value in [1.0f32, 0.5, 3.0, 0.0].iter() {
+                file.write_all(&value.to_le_bytes()).unwrap();
+            }
+        }
+
+        let grid = VoxelGrid::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(grid.has_emission());
+        let centre = Vector3::new(0.5, 0.5, 0.5);
+        assert!((grid.density(&centre) - 2.0).abs() < 0.0001);
+        assert!((grid.emission(&centre) - 0.25).abs() < 0.0001);
+        // Clamped to the first voxel's value at the edge
+        assert!((grid.density(&Vector3::new(0.0, 0.5, 0.5)) - 1.0).abs() < 0.0001);
+    }
+
+    #[test]
+    fn majorants_bound_density() {
+        let grid = VoxelGrid::procedural_cloud(16);
+        let majorants = MajorantGrid::new(&grid, 4);
+        for i in 0..1000 {
+            let q = Vector3::new(
+                (i % 10) as f32 / 10.0 + 0.05,
+                ((i / 10) % 10) as f32 / 10.0 + 0.05,
+                (i / 100) as f32 / 10.0 + 0.05,
+            );
+            let cell = |v: f32| ((v * 4.0) as usize).min(3);
+            assert!(grid.density(&q) <= majorants.majorant(cell(q.x()), cell(q.y()), cell(q.z())));
+        }
+    }
+
+    #[test]
+    fn bad_header() {
+        let path = std::env::temp_dir().join("raytracer_voxel_bad.grid");
+        // Too many voxels, and too many bytes for them
+        let voxels = format!("dense {} {} 2 1\n", usize::MAX / 2, usize::MAX / 2);
+        let bytes = format!("dense {} 1 1 2\n", usize::MAX / 4);
+        for header in ["sparse 1 1 1 1\n", "dense 4 0 4 1\n", &voxels, &bytes] {
+            std::fs::write(&path, header).unwrap();
+            let result = VoxelGrid::load(&path);
+            assert!(result.is_err(), "{}", header);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}